@@ -0,0 +1,144 @@
+// Дебютная книга в формате polyglot (.bin).
+
+mod keys;
+
+use crate::chess::{Move, MoveList, Piece, Position, Square, XorshiftRng};
+use std::path::Path;
+
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Clone, Copy)]
+struct BookEntry {
+    key: u64,
+    mv: u16,
+    weight: u16,
+}
+
+pub struct Book {
+    entries: Vec<BookEntry>,
+    rng: XorshiftRng,
+}
+
+impl Book {
+    pub fn load(path: &Path) -> Result<Book, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        if bytes.len() % ENTRY_SIZE != 0 {
+            return Err(format!("bad book file size {}", bytes.len()));
+        }
+        // записи хранятся big-endian и отсортированы по ключу
+        let entries = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|x| BookEntry {
+                key: u64::from_be_bytes(x[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes([x[8], x[9]]),
+                weight: u16::from_be_bytes([x[10], x[11]]),
+            })
+            .collect();
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        return Ok(Book {
+            entries: entries,
+            rng: XorshiftRng::with_seed(seed),
+        });
+    }
+
+    pub fn size(&self) -> usize {
+        return self.entries.len();
+    }
+
+    // Случайный ход с вероятностью, пропорциональной весу.
+    pub fn probe(&mut self, pos: &Position) -> Option<Move> {
+        let key = keys::polyglot_key(pos);
+        let start = self.entries.partition_point(|x| x.key < key);
+        let candidates: Vec<_> = self.entries[start..]
+            .iter()
+            .take_while(|x| x.key == key)
+            .filter_map(|x| Some((to_move(pos, x.mv)?, x.weight as u64)))
+            .collect();
+        let total: u64 = candidates.iter().map(|&(_, weight)| weight).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut r = self.rng.next() % total;
+        for (mv, weight) in candidates {
+            if r < weight {
+                return Some(mv);
+            }
+            r -= weight;
+        }
+        return None;
+    }
+}
+
+// Ход polyglot: to (биты 0-5), from (6-11), превращение (12-14).
+// Рокировка записана как взятие королем своей ладьи, так же как в Move.
+fn to_move(pos: &Position, mv: u16) -> Option<Move> {
+    let to = Square::make((mv & 7) as usize, (mv >> 3 & 7) as usize);
+    let from = Square::make((mv >> 6 & 7) as usize, (mv >> 9 & 7) as usize);
+    let promotion = match mv >> 12 & 7 {
+        0 => Piece::NONE,
+        1 => Piece::KNIGHT,
+        2 => Piece::BISHOP,
+        3 => Piece::ROOK,
+        4 => Piece::QUEEN,
+        _ => return None,
+    };
+
+    let mut ml = MoveList::new();
+    ml.gen_legal_moves(pos);
+    return ml.moves[..ml.size]
+        .iter()
+        .map(|x| x.mv)
+        .find(|x| x.from() == from && x.to() == to && x.promotion() == promotion);
+}
+
+#[test]
+fn test_book_probe() {
+    unsafe { crate::chess::init() };
+    let castling_fen = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1";
+    let start = Position::from_fen(Position::INITIAL_POSITION_FEN).unwrap();
+    let castling = Position::from_fen(castling_fen).unwrap();
+
+    let encode = |from: &str, to: &str| -> u16 {
+        let from = Square::parse(from).unwrap().index() as u16;
+        let to = Square::parse(to).unwrap().index() as u16;
+        return from << 6 | to;
+    };
+    let mut entries = vec![
+        (keys::polyglot_key(&start), encode("e2", "e4"), 3),
+        (keys::polyglot_key(&start), encode("d2", "d4"), 1),
+        (keys::polyglot_key(&start), encode("b1", "c3"), 0),
+        (keys::polyglot_key(&castling), encode("e1", "h1"), 1),
+    ];
+    entries.sort_by_key(|x| x.0);
+    let mut bytes = Vec::new();
+    for (key, mv, weight) in entries {
+        bytes.extend_from_slice(&key.to_be_bytes());
+        bytes.extend_from_slice(&mv.to_be_bytes());
+        bytes.extend_from_slice(&(weight as u16).to_be_bytes());
+        bytes.extend_from_slice(&0_u32.to_be_bytes());
+    }
+    let path = std::env::temp_dir().join(format!("counter_book_{}.bin", std::process::id()));
+    std::fs::write(&path, bytes).unwrap();
+    let mut book = Book::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(book.size(), 4);
+
+    let mut counts = [0; 2];
+    for _ in 0..400 {
+        let lan = format!("{:?}", book.probe(&start).unwrap());
+        match lan.as_str() {
+            "e2e4" => counts[0] += 1,
+            "d2d4" => counts[1] += 1,
+            _ => panic!("unexpected book move {lan}"),
+        }
+    }
+    assert!(counts[0] > counts[1] && counts[1] > 0);
+
+    let mv = book.probe(&castling).unwrap();
+    assert_eq!(format!("{:?}", mv), "e1g1");
+
+    let empty = Position::from_fen("8/8/8/8/8/8/8/K6k w - - 0 1").unwrap();
+    assert!(book.probe(&empty).is_none());
+}
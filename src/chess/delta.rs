@@ -0,0 +1,41 @@
+use super::{Piece, Side, Square};
+
+// Не больше 3 изменений: взятие с превращением (снять взятую фигуру и пешку,
+// поставить новую) или рокировка (король и ладья)
+pub const MAX_DELTAS: usize = 3;
+
+// Изменение расстановки фигур при ходе
+#[derive(Clone, Copy, PartialEq)]
+pub enum PieceDelta {
+    Add(Side, Piece, Square),
+    Remove(Side, Piece, Square),
+    Move(Side, Piece, Square, Square),
+}
+
+#[derive(Clone)]
+pub struct PieceDeltas {
+    pub deltas: [PieceDelta; MAX_DELTAS],
+    pub size: usize,
+}
+
+impl PieceDeltas {
+    pub fn new() -> PieceDeltas {
+        return PieceDeltas {
+            deltas: [PieceDelta::Add(Side::WHITE, Piece::NONE, Square::A1); MAX_DELTAS],
+            size: 0,
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.size = 0;
+    }
+
+    pub fn push(&mut self, delta: PieceDelta) {
+        self.deltas[self.size] = delta;
+        self.size += 1;
+    }
+
+    pub fn as_slice(&self) -> &[PieceDelta] {
+        return &self.deltas[..self.size];
+    }
+}
@@ -1,4 +1,5 @@
 pub mod bitboard;
+mod delta;
 mod movegen;
 mod moves;
 mod piece;
@@ -6,10 +7,12 @@ mod position;
 mod rand;
 mod square;
 
+pub use delta::{PieceDelta, PieceDeltas};
 pub use movegen::MoveList;
 pub use moves::Move;
 pub use piece::{Piece, Side};
 pub use position::Position;
+pub use rand::XorshiftRng;
 pub use square::Square;
 
 pub unsafe fn init() {
@@ -10,14 +10,6 @@ const RANK3_MASK: u64 = bitboard::RANKMASK[Square::RANK_3 as usize];
 const RANK6_MASK: u64 = bitboard::RANKMASK[Square::RANK_6 as usize];
 const RANK7_MASK: u64 = bitboard::RANKMASK[Square::RANK_7 as usize];
 
-const F1G1_MASK: u64 = Square::F1.to_bitboard() | Square::G1.to_bitboard();
-const B1D1_MASK: u64 =
-    Square::B1.to_bitboard() | Square::C1.to_bitboard() | Square::D1.to_bitboard();
-
-const F8G8_MASK: u64 = Square::F8.to_bitboard() | Square::G8.to_bitboard();
-const B8D8_MASK: u64 =
-    Square::B8.to_bitboard() | Square::C8.to_bitboard() | Square::D8.to_bitboard();
-
 #[derive(Copy, Clone)]
 pub struct OrderedMove {
     pub mv: Move,
@@ -103,31 +95,14 @@ impl MoveList {
         self.gen_king_moves(pos, !own_pieces);
 
         if !pos.is_check() {
-            if pos.side_to_move == Side::WHITE {
-                if (pos.castling_rights & position::CR_WHITE_KING_SIDE) != 0
-                    && (all_pieces & F1G1_MASK) == 0
-                    && pos.attackers_by_side(Side::BLACK, Square::F1) == 0
-                {
-                    self.add(Move::make(Square::E1, Square::G1, Piece::KING, Piece::NONE));
-                }
-                if (pos.castling_rights & position::CR_WHITE_QUEEN_SIDE) != 0
-                    && (all_pieces & B1D1_MASK) == 0
-                    && pos.attackers_by_side(Side::BLACK, Square::D1) == 0
-                {
-                    self.add(Move::make(Square::E1, Square::C1, Piece::KING, Piece::NONE));
-                }
+            let rights = if pos.side_to_move == Side::WHITE {
+                [position::CR_WHITE_KING_SIDE, position::CR_WHITE_QUEEN_SIDE]
             } else {
-                if (pos.castling_rights & position::CR_BLACK_KING_SIDE) != 0
-                    && (all_pieces & F8G8_MASK) == 0
-                    && pos.attackers_by_side(Side::WHITE, Square::F8) == 0
-                {
-                    self.add(Move::make(Square::E8, Square::G8, Piece::KING, Piece::NONE));
-                }
-                if (pos.castling_rights & position::CR_BLACK_QUEEN_SIDE) != 0
-                    && (all_pieces & B8D8_MASK) == 0
-                    && pos.attackers_by_side(Side::WHITE, Square::D8) == 0
-                {
-                    self.add(Move::make(Square::E8, Square::C8, Piece::KING, Piece::NONE));
+                [position::CR_BLACK_KING_SIDE, position::CR_BLACK_QUEEN_SIDE]
+            };
+            for right in rights {
+                if (pos.castling_rights & right) != 0 {
+                    self.gen_castling(pos, right);
                 }
             }
         }
@@ -185,6 +160,25 @@ impl MoveList {
         self.gen_king_moves(pos, opp_pieces);
     }
 
+    // Ходы, которых нет в gen_captures: тихие ходы, рокировки и слабые
+    // превращения, в том числе со взятием
+    pub fn gen_quiets(&mut self, pos: &Position) {
+        self.gen_moves(pos);
+        let mut size = 0;
+        for i in 0..self.size {
+            let item = self.moves[i];
+            let promotion = item.mv.promotion();
+            if promotion == Piece::QUEEN
+                || (promotion == Piece::NONE && item.mv.captured_piece() != Piece::NONE)
+            {
+                continue;
+            }
+            self.moves[size] = item;
+            size += 1;
+        }
+        self.size = size;
+    }
+
     pub fn gen_legal_moves(&mut self, pos: &Position) {
         self.gen_moves(pos);
         let mut child: Position = unsafe { std::mem::zeroed() };
@@ -260,6 +254,30 @@ impl MoveList {
         }
     }
 
+    // Король и ладья встают на g1/f1 или c1/d1 (Chess960): все поля между
+    // начальными и конечными должны быть пусты, поля пути короля - не биты.
+    // Конечное поле короля после хода проверяет make_move.
+    fn gen_castling(&mut self, pos: &Position, right: u8) {
+        let king = pos.king_sq(pos.side_to_move);
+        let rook = pos.castling_rook(right);
+        let mv = Move::make_castling(king, rook);
+        let (king_to, rook_to) = mv.castling_squares();
+
+        let occupied = pos.all_pieces() ^ king.to_bitboard() ^ rook.to_bitboard();
+        if (occupied & (span(king, king_to) | span(rook, rook_to))) != 0 {
+            return;
+        }
+        let mut path = span(king, king_to) ^ king.to_bitboard();
+        while path != 0 {
+            let sq = bitboard::first_one(path);
+            if pos.attackers_by_side(pos.side_to_move.opp(), sq) != 0 {
+                return;
+            }
+            path &= path - 1;
+        }
+        self.add(mv);
+    }
+
     fn gen_king_moves(&mut self, pos: &Position, target: u64) {
         let from = pos.king_sq(pos.side_to_move);
         let mut to_bb = bitboard::king_attacks(from) & target;
@@ -305,3 +323,100 @@ impl MoveList {
         }
     }
 }
+
+impl Position {
+    // Ход мог быть сгенерирован gen_moves в этой позиции. Шах своему королю
+    // не проверяется, это делает make_move. Нужно, чтобы пробовать ход из
+    // хеш-таблицы и killer-ходы до генерации.
+    pub fn is_pseudo_legal(&self, mv: Move) -> bool {
+        if mv == Move::NONE || mv.is_null() {
+            return false;
+        }
+        let side = self.side_to_move;
+        let own_pieces = self.colours(side);
+        let from = mv.from();
+        let to = mv.to();
+        let piece = mv.moving_piece();
+        if own_pieces & from.to_bitboard() == 0 || self.piece_on_square(from) != piece {
+            return false;
+        }
+
+        if mv.is_castling() {
+            if piece != Piece::KING || mv.promotion() != Piece::NONE || self.is_check() {
+                return false;
+            }
+            let rights = if side == Side::WHITE {
+                [position::CR_WHITE_KING_SIDE, position::CR_WHITE_QUEEN_SIDE]
+            } else {
+                [position::CR_BLACK_KING_SIDE, position::CR_BLACK_QUEEN_SIDE]
+            };
+            for right in rights {
+                if (self.castling_rights & right) != 0 && self.castling_rook(right) == to {
+                    let mut ml = MoveList::new();
+                    ml.gen_castling(self, right);
+                    return ml.size == 1 && ml.moves[0].mv == mv;
+                }
+            }
+            return false;
+        }
+
+        let last_rank = to.rank() == Square::RANK_8 || to.rank() == Square::RANK_1;
+        let promotion = mv.promotion();
+        if piece == Piece::PAWN && last_rank {
+            if !matches!(
+                promotion,
+                Piece::KNIGHT | Piece::BISHOP | Piece::ROOK | Piece::QUEEN
+            ) {
+                return false;
+            }
+        } else if promotion != Piece::NONE {
+            return false;
+        }
+
+        let target = to.to_bitboard();
+        if piece == Piece::PAWN && self.ep_square == Some(to) {
+            return mv.captured_piece() == Piece::PAWN
+                && (bitboard::pawn_attacks(side, from) & target) != 0;
+        }
+        if own_pieces & target != 0 || self.piece_on_square(to) != mv.captured_piece() {
+            return false;
+        }
+
+        let all_pieces = self.all_pieces();
+        return match piece {
+            Piece::PAWN => {
+                if mv.captured_piece() != Piece::NONE {
+                    return (bitboard::pawn_attacks(side, from) & target) != 0;
+                }
+                let step = from.forward(side);
+                if to == step {
+                    return true;
+                }
+                let start_rank = if side == Side::WHITE {
+                    Square::RANK_2
+                } else {
+                    Square::RANK_7
+                };
+                from.rank() == start_rank
+                    && self.piece_on_square(step) == Piece::NONE
+                    && to == step.forward(side)
+            }
+            Piece::KNIGHT => (bitboard::knight_attacks(from) & target) != 0,
+            Piece::BISHOP => (bitboard::bishop_attacks(from, all_pieces) & target) != 0,
+            Piece::ROOK => (bitboard::rook_attacks(from, all_pieces) & target) != 0,
+            Piece::QUEEN => (bitboard::queen_attacks(from, all_pieces) & target) != 0,
+            Piece::KING => (bitboard::king_attacks(from) & target) != 0,
+            _ => false,
+        };
+    }
+}
+
+// Поля горизонтали от a до b включительно
+fn span(a: Square, b: Square) -> u64 {
+    let (lo, hi) = if a.index() <= b.index() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    return (hi.to_bitboard() - lo.to_bitboard()) | hi.to_bitboard();
+}
@@ -5,8 +5,14 @@ use std::fmt::Write;
 #[derive(Copy, Clone, Default, PartialEq)]
 pub struct Move(u32);
 
+// Рокировка хранится как взятие королем своей ладьи. Обычный формат ({:?})
+// печатает поле назначения короля, альтернативный ({:#?}) - нотацию Chess960.
 impl fmt::Debug for Move {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_castling() && !f.alternate() {
+            write!(f, "{:?}{:?}", self.from(), self.castling_squares().0)?;
+            return Ok(());
+        }
         write!(f, "{:?}{:?}", self.from(), self.to())?;
         if let Some(promotion) = promotion_char(self.promotion()) {
             write!(f, "{}", promotion)?;
@@ -25,6 +31,8 @@ fn promotion_char(piece: Piece) -> Option<char> {
     }
 }
 
+const CASTLING_FLAG: u32 = 1 << 21;
+
 impl Move {
     pub const NONE: Move = Move(0);
     pub const NULL: Move = Move(1);
@@ -42,6 +50,18 @@ impl Move {
         )
     }
 
+    pub fn make_castling(king_from: Square, rook_from: Square) -> Move {
+        return Move(Move::make(king_from, rook_from, Piece::KING, Piece::NONE).0 ^ CASTLING_FLAG);
+    }
+
+    pub fn from_raw(value: u32) -> Move {
+        return Move(value);
+    }
+
+    pub fn raw(self) -> u32 {
+        return self.0;
+    }
+
     pub fn with_promotion(self, promotion: Piece) -> Move {
         return Move(self.0 ^ ((promotion as u32) << 18));
     }
@@ -69,11 +89,34 @@ impl Move {
         unsafe { std::mem::transmute(value as u8) }
     }
 
+    pub fn is_castling(self) -> bool {
+        return self.0 & CASTLING_FLAG != 0;
+    }
+
+    // Поля назначения короля и ладьи при рокировке
+    pub fn castling_squares(self) -> (Square, Square) {
+        let rank = self.from().rank() as usize;
+        if self.to().file() > self.from().file() {
+            return (
+                Square::make(Square::FILE_G, rank),
+                Square::make(Square::FILE_F, rank),
+            );
+        }
+        return (
+            Square::make(Square::FILE_C, rank),
+            Square::make(Square::FILE_D, rank),
+        );
+    }
+
     pub fn parse_lan(pos: &Position, s: &str) -> Option<Move> {
         let mut ml = MoveList::new();
         ml.gen_moves(pos);
         for m in &ml.moves[..ml.size] {
-            let lan = format!("{:?}", m.mv);
+            let lan = if pos.chess960 {
+                format!("{:#?}", m.mv)
+            } else {
+                format!("{:?}", m.mv)
+            };
             if lan.eq_ignore_ascii_case(s) {
                 //if lan == s {
                 return Some(m.mv);
@@ -98,18 +141,12 @@ impl Move {
 
 #[allow(unused_must_use, unused_variables)]
 fn move_to_san(pos: &Position, ml: &MoveList, mv: Move) -> String {
-    if mv.moving_piece() == Piece::KING {
+    if mv.is_castling() {
         //SAN kingside castling is indicated by the sequence O-O; queenside castling is indicated by the sequence O-O-O (note that these are capital Os, not zeroes, contrary to the FIDE standard for notation).
-        if mv.from() == Square::E1 && mv.to() == Square::G1
-            || mv.from() == Square::E8 && mv.to() == Square::G8
-        {
+        if mv.to().file() > mv.from().file() {
             return String::from("O-O");
         }
-        if mv.from() == Square::E1 && mv.to() == Square::C1
-            || mv.from() == Square::E8 && mv.to() == Square::C8
-        {
-            return String::from("O-O-O");
-        }
+        return String::from("O-O-O");
     }
 
     let mut ambiguity = false;
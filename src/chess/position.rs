@@ -1,5 +1,5 @@
 use super::rand::XorshiftRng;
-use super::{Move, Piece, Side, Square, bitboard};
+use super::{Move, Piece, PieceDelta, PieceDeltas, Side, Square, bitboard};
 
 pub const CR_WHITE_KING_SIDE: u8 = 1;
 pub const CR_WHITE_QUEEN_SIDE: u8 = 2;
@@ -27,8 +27,12 @@ pub struct Position {
     pub castling_rights: u8,
     pub rule50: isize,
     pub ep_square: Option<Square>,
+    pub chess960: bool,
+    castling_rooks: [Square; 4], // по номеру бита права на рокировку
     piece_key: u64,
     pub key: u64,
+    // ключ только пешечной структуры
+    pub pawn_key: u64,
 }
 
 impl Position {
@@ -109,10 +113,14 @@ impl Position {
         return Piece::NONE;
     }
 
+    // Начальное поле ладьи для права на рокировку (CR_*)
+    pub fn castling_rook(&self, right: u8) -> Square {
+        return self.castling_rooks[right.trailing_zeros() as usize];
+    }
+
     fn new(
         pieces: &[PieceInfo],
         side_to_move: Side,
-        castle_rights: u8,
         ep_square: Option<Square>,
         fifty: isize,
     ) -> Option<Position> {
@@ -129,8 +137,11 @@ impl Position {
             side_to_move: side_to_move,
             piece_key: 0,
             key: 0,
-            castling_rights: castle_rights,
+            pawn_key: 0,
+            castling_rights: 0,
+            castling_rooks: [Square::A1; 4],
             ep_square: ep_square,
+            chess960: false,
             rule50: fifty,
         };
         for piece_info in pieces.iter() {
@@ -140,10 +151,55 @@ impl Position {
         if !pos.is_legal() {
             return None;
         }
-        pos.update_key();
         return Some(pos);
     }
 
+    // KQkq (X-FEN: самая крайняя ладья) или вертикали ладей (Shredder-FEN)
+    fn set_castling_rights(&mut self, s: &str) {
+        for ch in s.chars() {
+            let side = if ch.is_ascii_uppercase() {
+                Side::WHITE
+            } else {
+                Side::BLACK
+            };
+            let rank = if side == Side::WHITE {
+                Square::RANK_1
+            } else {
+                Square::RANK_8
+            };
+            let king = self.king_sq(side);
+            if king.rank() != rank {
+                continue;
+            }
+            let rooks = self.rooks & self.colours(side) & bitboard::RANKMASK[rank as usize];
+            let king_side = rooks & !(king.to_bitboard() - 1);
+            let queen_side = rooks & (king.to_bitboard() - 1);
+            let rook = match ch.to_ascii_lowercase() {
+                'k' if king_side != 0 => Square(63 - king_side.leading_zeros() as u8),
+                'q' if queen_side != 0 => bitboard::first_one(queen_side),
+                'a'..='h' => {
+                    let file = ch.to_ascii_lowercase() as usize - 'a' as usize;
+                    let sq = Square::make(file, rank as usize);
+                    if rooks & sq.to_bitboard() == 0 {
+                        continue;
+                    }
+                    sq
+                }
+                _ => continue,
+            };
+            let mut right = if rook.file() > king.file() {
+                CR_WHITE_KING_SIDE
+            } else {
+                CR_WHITE_QUEEN_SIDE
+            };
+            if side == Side::BLACK {
+                right <<= 2;
+            }
+            self.castling_rights |= right;
+            self.castling_rooks[right.trailing_zeros() as usize] = rook;
+        }
+    }
+
     pub fn from_fen(fen: &str) -> Option<Position> {
         let tokens: Vec<_> = fen.split(' ').collect();
         if tokens.len() <= 3 {
@@ -180,21 +236,6 @@ impl Position {
             Side::BLACK
         };
 
-        let s_castle_rights = tokens[2];
-        let mut cr: u8 = 0;
-        if s_castle_rights.contains("K") {
-            cr |= CR_WHITE_KING_SIDE;
-        }
-        if s_castle_rights.contains("Q") {
-            cr |= CR_WHITE_QUEEN_SIDE;
-        }
-        if s_castle_rights.contains("k") {
-            cr |= CR_BLACK_KING_SIDE;
-        }
-        if s_castle_rights.contains("q") {
-            cr |= CR_BLACK_QUEEN_SIDE;
-        }
-
         let ep_square = Square::parse(tokens[3]);
 
         let mut rule50: isize = 0;
@@ -202,10 +243,122 @@ impl Position {
             rule50 = tokens[4].parse::<isize>().unwrap_or(0);
         }
 
-        return Position::new(&pieces, side_to_move, cr, ep_square, rule50);
+        let mut pos = Position::new(&pieces, side_to_move, ep_square, rule50)?;
+        pos.set_castling_rights(tokens[2]);
+        pos.update_key();
+        return Some(pos);
+    }
+
+    // Рокировка записывается как KQkq, если ладья крайняя (X-FEN), иначе вертикалью
+    // ладьи (Shredder-FEN). Номер хода не хранится и всегда равен 1.
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.side_piece_on_square(Square::make(file, rank)) {
+                    Some((side, piece)) => {
+                        if empty != 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let ch = piece_to_char(piece);
+                        fen.push(if side == Side::WHITE {
+                            ch.to_ascii_uppercase()
+                        } else {
+                            ch
+                        });
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty != 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank != 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.side_to_move == Side::WHITE {
+            " w "
+        } else {
+            " b "
+        });
+
+        let mut castling = String::new();
+        for right in [
+            CR_WHITE_KING_SIDE,
+            CR_WHITE_QUEEN_SIDE,
+            CR_BLACK_KING_SIDE,
+            CR_BLACK_QUEEN_SIDE,
+        ] {
+            if self.castling_rights & right == 0 {
+                continue;
+            }
+            let side = if right <= CR_WHITE_QUEEN_SIDE {
+                Side::WHITE
+            } else {
+                Side::BLACK
+            };
+            let rook = self.castling_rook(right);
+            let king = self.king_sq(side).to_bitboard();
+            let rooks = self.rooks & self.colours(side) & bitboard::RANKMASK[rook.rank() as usize];
+            let king_side = right == CR_WHITE_KING_SIDE || right == CR_BLACK_KING_SIDE;
+            let outermost = if king_side {
+                Square(63 - (rooks & !(king - 1)).leading_zeros() as u8)
+            } else {
+                bitboard::first_one(rooks & (king - 1))
+            };
+            let ch = if outermost != rook {
+                (b'a' + rook.file()) as char
+            } else if king_side {
+                'k'
+            } else {
+                'q'
+            };
+            castling.push(if side == Side::WHITE {
+                ch.to_ascii_uppercase()
+            } else {
+                ch
+            });
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+
+        match self.ep_square {
+            Some(sq) => fen.push_str(&format!(" {:?}", sq)),
+            None => fen.push_str(" -"),
+        }
+        fen.push_str(&format!(" {} 1", self.rule50));
+        return fen;
     }
 
     pub fn make_move(&self, m: Move, result: &mut Position) -> bool {
+        return self.do_move(m, result, None);
+    }
+
+    // То же, что make_move, но дополнительно записывает в deltas изменения
+    // расстановки фигур для инкрементального обновления оценки
+    pub fn make_move_with_deltas(
+        &self,
+        m: Move,
+        result: &mut Position,
+        deltas: &mut PieceDeltas,
+    ) -> bool {
+        deltas.clear();
+        return self.do_move(m, result, Some(deltas));
+    }
+
+    #[inline(always)]
+    fn do_move(
+        &self,
+        m: Move,
+        result: &mut Position,
+        mut deltas: Option<&mut PieceDeltas>,
+    ) -> bool {
         let from = m.from();
         let to = m.to();
         let moving_piece = m.moving_piece();
@@ -221,10 +374,15 @@ impl Position {
         result.black = self.black;
         result.side_to_move = self.side_to_move.opp();
         result.piece_key = self.piece_key;
+        result.pawn_key = self.pawn_key;
         result.ep_square = None;
+        result.chess960 = self.chess960;
+        result.castling_rooks = self.castling_rooks;
 
-        result.castling_rights =
-            self.castling_rights & CASTLE_MASK[from.index()] & CASTLE_MASK[to.index()];
+        result.castling_rights = self.castling_rights;
+        if self.castling_rights != 0 {
+            result.castling_rights &= self.castle_mask(from) & self.castle_mask(to);
+        }
 
         if moving_piece == Piece::PAWN || captured_piece != Piece::NONE {
             result.rule50 = 0;
@@ -233,22 +391,58 @@ impl Position {
         }
 
         if captured_piece != Piece::NONE {
-            if captured_piece == Piece::PAWN && Some(to) == self.ep_square {
-                result.xor_piece(
-                    result.side_to_move,
-                    Piece::PAWN,
-                    to.with_step(if self.side_to_move == Side::WHITE {
-                        -8
-                    } else {
-                        8
-                    }),
-                );
+            let cap_sq = if captured_piece == Piece::PAWN && Some(to) == self.ep_square {
+                to.with_step(if self.side_to_move == Side::WHITE {
+                    -8
+                } else {
+                    8
+                })
             } else {
-                result.xor_piece(result.side_to_move, captured_piece, to);
+                to
+            };
+            result.xor_piece(result.side_to_move, captured_piece, cap_sq);
+            if let Some(deltas) = deltas.as_deref_mut() {
+                deltas.push(PieceDelta::Remove(
+                    result.side_to_move,
+                    captured_piece,
+                    cap_sq,
+                ));
             }
         }
 
-        result.move_piece(self.side_to_move, moving_piece, from, to);
+        if m.is_castling() {
+            // поля короля и ладьи могут совпадать, поэтому снимаем обе фигуры
+            let (king_to, rook_to) = m.castling_squares();
+            result.xor_piece(self.side_to_move, Piece::KING, from);
+            result.xor_piece(self.side_to_move, Piece::ROOK, to);
+            result.xor_piece(self.side_to_move, Piece::KING, king_to);
+            result.xor_piece(self.side_to_move, Piece::ROOK, rook_to);
+            if let Some(deltas) = deltas.as_deref_mut() {
+                deltas.push(PieceDelta::Move(
+                    self.side_to_move,
+                    Piece::KING,
+                    from,
+                    king_to,
+                ));
+                deltas.push(PieceDelta::Move(
+                    self.side_to_move,
+                    Piece::ROOK,
+                    to,
+                    rook_to,
+                ));
+            }
+        } else {
+            result.move_piece(self.side_to_move, moving_piece, from, to);
+            if let Some(deltas) = deltas {
+                // превращение записываем как снятие пешки и новую фигуру
+                if m.promotion() != Piece::NONE {
+                    deltas.push(PieceDelta::Remove(self.side_to_move, Piece::PAWN, from));
+                    deltas.push(PieceDelta::Add(self.side_to_move, m.promotion(), to));
+                } else {
+                    deltas.push(PieceDelta::Move(self.side_to_move, moving_piece, from, to));
+                }
+            }
+        }
 
         if moving_piece == Piece::PAWN {
             if self.side_to_move == Side::WHITE {
@@ -269,22 +463,6 @@ impl Position {
                     result.xor_piece(Side::BLACK, m.promotion(), to);
                 }
             }
-        } else if moving_piece == Piece::KING {
-            if self.side_to_move == Side::WHITE {
-                if from == Square::E1 && to == Square::G1 {
-                    result.move_piece(Side::WHITE, Piece::ROOK, Square::H1, Square::F1);
-                }
-                if from == Square::E1 && to == Square::C1 {
-                    result.move_piece(Side::WHITE, Piece::ROOK, Square::A1, Square::D1);
-                }
-            } else {
-                if from == Square::E8 && to == Square::G8 {
-                    result.move_piece(Side::BLACK, Piece::ROOK, Square::H8, Square::F8);
-                }
-                if from == Square::E8 && to == Square::C8 {
-                    result.move_piece(Side::BLACK, Piece::ROOK, Square::A8, Square::D8);
-                }
-            }
         }
 
         if !result.is_legal() {
@@ -293,6 +471,7 @@ impl Position {
         result.checkers = result.compute_checkers();
         result.update_key();
         debug_assert!(result.key == result.compute_key_slow());
+        debug_assert!(result.pawn_key == result.compute_pawn_key_slow());
         return true;
     }
 
@@ -311,14 +490,30 @@ impl Position {
         result.checkers = 0;
         result.side_to_move = self.side_to_move.opp();
         result.castling_rights = self.castling_rights;
+        result.castling_rooks = self.castling_rooks;
+        result.chess960 = self.chess960;
         result.rule50 = self.rule50 + 1;
         result.ep_square = None;
         result.piece_key = self.piece_key;
+        result.pawn_key = self.pawn_key;
         result.update_key();
 
         debug_assert!(result.key == result.compute_key_slow());
     }
 
+    // Права на рокировку, которые остаются после хода с поля или на поле sq
+    fn castle_mask(&self, sq: Square) -> u8 {
+        let mut mask =
+            CR_WHITE_KING_SIDE | CR_WHITE_QUEEN_SIDE | CR_BLACK_KING_SIDE | CR_BLACK_QUEEN_SIDE;
+        for (i, &rook) in self.castling_rooks.iter().enumerate() {
+            let side = if i < 2 { Side::WHITE } else { Side::BLACK };
+            if rook == sq || self.king_sq(side) == sq {
+                mask &= !(1 << i);
+            }
+        }
+        return mask;
+    }
+
     fn update_key(&mut self) {
         let mut key = self.piece_key;
         if self.side_to_move == Side::WHITE {
@@ -354,6 +549,18 @@ impl Position {
         return res;
     }
 
+    fn compute_pawn_key_slow(&self) -> u64 {
+        let mut res = 0_u64;
+        let mut bb = self.pawns;
+        while bb != 0 {
+            let sq = bitboard::first_one(bb);
+            bb &= bb - 1;
+            let (side, _) = self.side_piece_on_square(sq).unwrap();
+            res ^= HASH_KEYS.psq_key(side, Piece::PAWN, sq);
+        }
+        return res;
+    }
+
     fn xor_piece(&mut self, side: Side, piece: Piece, sq: Square) {
         let b = sq.to_bitboard();
         match side {
@@ -370,6 +577,9 @@ impl Position {
             Piece::KING => self.kings ^= b,
         }
         self.piece_key ^= HASH_KEYS.psq_key(side, piece, sq);
+        if piece == Piece::PAWN {
+            self.pawn_key ^= HASH_KEYS.psq_key(side, piece, sq);
+        }
     }
 
     fn move_piece(&mut self, side: Side, piece: Piece, from: Square, to: Square) {
@@ -387,7 +597,11 @@ impl Position {
             Piece::QUEEN => self.queens ^= b,
             Piece::KING => self.kings ^= b,
         }
-        self.piece_key ^= HASH_KEYS.psq_key(side, piece, from) ^ HASH_KEYS.psq_key(side, piece, to);
+        let key = HASH_KEYS.psq_key(side, piece, from) ^ HASH_KEYS.psq_key(side, piece, to);
+        self.piece_key ^= key;
+        if piece == Piece::PAWN {
+            self.pawn_key ^= key;
+        }
     }
 }
 
@@ -403,22 +617,16 @@ fn char_to_piece(ch: char) -> Option<Piece> {
     }
 }
 
-static CASTLE_MASK: [u8; Square::SQUARE_NB] = init_castle_mask();
-
-const fn init_castle_mask() -> [u8; 64] {
-    const CASTLEMASK_ALL: u8 =
-        CR_WHITE_KING_SIDE | CR_WHITE_QUEEN_SIDE | CR_BLACK_KING_SIDE | CR_BLACK_QUEEN_SIDE;
-    let mut res = [CASTLEMASK_ALL; 64];
-
-    res[Square::A1.index()] &= !CR_WHITE_QUEEN_SIDE;
-    res[Square::E1.index()] &= !(CR_WHITE_KING_SIDE | CR_WHITE_QUEEN_SIDE);
-    res[Square::H1.index()] &= !CR_WHITE_KING_SIDE;
-
-    res[Square::A8.index()] &= !CR_BLACK_QUEEN_SIDE;
-    res[Square::E8.index()] &= !(CR_BLACK_KING_SIDE | CR_BLACK_QUEEN_SIDE);
-    res[Square::H8.index()] &= !CR_BLACK_KING_SIDE;
-
-    return res;
+fn piece_to_char(piece: Piece) -> char {
+    match piece {
+        Piece::PAWN => 'p',
+        Piece::KNIGHT => 'n',
+        Piece::BISHOP => 'b',
+        Piece::ROOK => 'r',
+        Piece::QUEEN => 'q',
+        Piece::KING => 'k',
+        Piece::NONE => '?',
+    }
 }
 
 struct HashKeys {
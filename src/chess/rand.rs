@@ -9,6 +9,11 @@ impl XorshiftRng {
         return XorshiftRng { seed: 1070372_u64 };
     }
 
+    // нулевое состояние xorshift не меняется
+    pub const fn with_seed(seed: u64) -> Self {
+        return XorshiftRng { seed: seed | 1 };
+    }
+
     pub const fn next(&mut self) -> u64 {
         self.seed ^= self.seed >> 12;
         self.seed ^= self.seed << 25;
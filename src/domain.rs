@@ -1,13 +1,20 @@
-use crate::chess::{Move, Position};
-use std::sync::Arc;
+use crate::chess::{Move, PieceDeltas, Position};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::time::Duration;
 
-pub trait IEvaluator {
+pub trait IEvaluator: Send {
+    fn box_clone(&self) -> Box<dyn IEvaluator>;
     fn init(&mut self, pos: &Position);
-    fn make_move(&mut self, pos: &Position, mv: Move);
+    // pos - позиция после хода, deltas - изменения фигур из Position::make_move_with_deltas
+    fn make_move(&mut self, pos: &Position, deltas: &PieceDeltas);
     fn unmake_move(&mut self);
     fn quik_evaluate(&mut self, pos: &Position) -> isize;
+    // Оценку приближает к нулю правило 50 ходов. Применяет поиск после кеша
+    // и хеш-таблицы, потому что их ключ rule50 не учитывает.
+    fn rule50_scaling(&self) -> bool {
+        return false;
+    }
 
     fn evaluate(&mut self, pos: &Position) -> isize {
         self.init(pos);
@@ -20,9 +27,12 @@ pub trait IEngine {
     fn set_option(&mut self, name: &str, value: &str);
     fn clear(&mut self);
     fn search(&mut self, search_params: SearchParams) -> SearchInfo;
+    // хеш-таблица в файле из опции HashFile
+    fn save_hash(&self) -> Result<(), String>;
+    fn load_hash(&mut self) -> Result<(), String>;
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct TournamentLimit {
     pub white_time: Option<u64>,
     pub black_time: Option<u64>,
@@ -34,6 +44,7 @@ pub struct TournamentLimit {
 #[derive(Debug, Default)]
 pub struct LimitsType {
     pub infinite: bool,
+    pub ponder: bool,
     pub fixed_nodes: Option<u64>,
     pub fixed_time: Option<Duration>,
     pub fixed_depth: Option<u32>,
@@ -54,6 +65,7 @@ pub struct SearchParams {
     pub repeats: Vec<u64>,
     pub limits: LimitsType,
     pub cancel: CancelToken,
+    pub ponderhit: PonderHitToken,
     pub progress: Box<dyn FnMut(&SearchInfo)>,
 }
 
@@ -69,6 +81,13 @@ impl Default for UciScore {
     }
 }
 
+#[derive(Debug, Default, Clone)]
+pub struct PvLine {
+    pub score: UciScore,
+    pub main_line: Vec<Move>,
+}
+
+// score и main_line совпадают с первой линией в lines
 #[derive(Debug, Default)]
 pub struct SearchInfo {
     pub depth: usize,
@@ -76,6 +95,9 @@ pub struct SearchInfo {
     pub nodes: u64,
     pub duration: Duration,
     pub main_line: Vec<Move>,
+    pub lines: Vec<PvLine>,
+    // заполнение хеш-таблицы в промилле
+    pub hashfull: usize,
 }
 
 #[derive(Debug)]
@@ -93,6 +115,10 @@ pub enum OptionValue {
         value: isize,
     },
     String(String),
+    Combo {
+        value: String,
+        vars: Vec<&'static str>,
+    },
 }
 
 #[derive(Clone)]
@@ -111,3 +137,42 @@ impl CancelToken {
         self.0.store(true, Ordering::Release);
     }
 }
+
+// ponderhit передает контроль времени в уже идущий поиск
+#[derive(Clone)]
+pub struct PonderHitToken(Arc<(Mutex<PonderState>, Condvar)>);
+
+#[derive(Default)]
+struct PonderState {
+    limits: Option<TournamentLimit>,
+    stopped: bool,
+}
+
+impl PonderHitToken {
+    pub fn new() -> Self {
+        PonderHitToken(Arc::new((Mutex::default(), Condvar::new())))
+    }
+
+    pub fn ponderhit(&self, limits: TournamentLimit) {
+        self.0.0.lock().unwrap().limits = Some(limits);
+        self.0.1.notify_all();
+    }
+
+    // stop или quit: ждать ponderhit больше не нужно
+    pub fn stop(&self) {
+        self.0.0.lock().unwrap().stopped = true;
+        self.0.1.notify_all();
+    }
+
+    pub fn limits(&self) -> Option<TournamentLimit> {
+        return self.0.0.lock().unwrap().limits.clone();
+    }
+
+    // Ждет ponderhit или stop
+    pub fn wait(&self) {
+        let mut state = self.0.0.lock().unwrap();
+        while state.limits.is_none() && !state.stopped {
+            state = self.0.1.wait(state).unwrap();
+        }
+    }
+}
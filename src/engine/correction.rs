@@ -0,0 +1,45 @@
+// Поправка статической оценки по пешечной структуре, у каждого потока своя.
+// Запись - скользящее среднее разности результата поиска и статической оценки
+// в 1/GRAIN сантипешки.
+
+use crate::chess::{Position, Side};
+
+const SIZE: usize = 16_384;
+const GRAIN: isize = 256;
+const WEIGHT_SCALE: isize = 256;
+// поправка не больше 64 сантипешек
+const MAX_ENTRY: isize = 64 * GRAIN;
+
+pub struct CorrectionHistory {
+    entries: Vec<i16>,
+}
+
+impl CorrectionHistory {
+    pub fn new() -> Self {
+        return CorrectionHistory {
+            entries: vec![0; Side::SIDE_NB * SIZE],
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(0);
+    }
+
+    // Статическая оценка с поправкой, eval - за сторону, которая ходит
+    pub fn correct(&self, pos: &Position, eval: isize) -> isize {
+        return eval + self.entries[index(pos)] as isize / GRAIN;
+    }
+
+    // Чем глубже поиск, тем больше вес нового значения
+    pub fn update(&mut self, pos: &Position, depth: isize, diff: isize) {
+        let entry = &mut self.entries[index(pos)];
+        let weight = (depth + 1).min(16);
+        let value =
+            (*entry as isize * (WEIGHT_SCALE - weight) + diff * GRAIN * weight) / WEIGHT_SCALE;
+        *entry = value.clamp(-MAX_ENTRY, MAX_ENTRY) as i16;
+    }
+}
+
+fn index(pos: &Position) -> usize {
+    return pos.side_to_move.index() * SIZE + (pos.pawn_key % SIZE as u64) as usize;
+}
@@ -0,0 +1,56 @@
+// Кеш статической оценки, у каждого потока свой.
+// Запись: старшие 48 бит ключа и оценка в младших 16 битах.
+
+const EVAL_MASK: u64 = 0xFFFF;
+
+pub struct EvalCache {
+    megabytes: usize,
+    entries: Vec<u64>,
+}
+
+impl EvalCache {
+    pub fn new(megabytes: usize) -> Self {
+        let mut cache = EvalCache {
+            megabytes: 0,
+            entries: Vec::new(),
+        };
+        cache.resize(megabytes);
+        return cache;
+    }
+
+    pub fn size(&self) -> usize {
+        return self.megabytes;
+    }
+
+    // 0 мегабайт отключает кеш
+    pub fn resize(&mut self, megabytes: usize) {
+        let size = (1_usize << 20) * megabytes / std::mem::size_of::<u64>();
+        self.megabytes = megabytes;
+        if size != self.entries.len() {
+            self.entries = vec![0; size];
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(0);
+    }
+
+    pub fn read(&self, key: u64) -> Option<isize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let entry = self.entries[(key % self.entries.len() as u64) as usize];
+        if entry & !EVAL_MASK != key & !EVAL_MASK || entry == 0 {
+            return None;
+        }
+        return Some((entry & EVAL_MASK) as u16 as i16 as isize);
+    }
+
+    pub fn update(&mut self, key: u64, eval: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let index = (key % self.entries.len() as u64) as usize;
+        self.entries[index] = (key & !EVAL_MASK) | (eval as i16 as u16 as u64);
+    }
+}
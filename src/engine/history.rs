@@ -1,11 +1,17 @@
 use super::moveorder::MoveOrderContext;
+use super::utils;
 use crate::chess::{Move, Side, Square};
 
 const MAIN_HISTORY_SIZE: usize = Side::SIDE_NB * Square::SQUARE_NB * Square::SQUARE_NB;
+// сторона, фигура, поле назначения, взятая фигура
+const CAPTURE_HISTORY_SIZE: usize = 1_024 * 8;
 
 pub struct HistoryTable {
     main_history: [i16; MAIN_HISTORY_SIZE],
     continuation_history: Vec<[i16; 1_024]>,
+    capture_history: [i16; CAPTURE_HISTORY_SIZE],
+    // тихий ход, который последним улучшил alpha в ответ на ход соперника
+    counter_moves: [Move; 1_024],
 }
 
 impl HistoryTable {
@@ -13,6 +19,8 @@ impl HistoryTable {
         return HistoryTable {
             main_history: [0_i16; MAIN_HISTORY_SIZE],
             continuation_history: (0..1_024).map(|_| [0_i16; 1_024]).collect(),
+            capture_history: [0_i16; CAPTURE_HISTORY_SIZE],
+            counter_moves: [Move::NONE; 1_024],
         };
     }
 
@@ -23,6 +31,20 @@ impl HistoryTable {
                 *y = 0;
             }
         }
+        self.capture_history.fill(0);
+        self.counter_moves.fill(Move::NONE);
+    }
+
+    pub fn read_capture(&self, context: &MoveOrderContext, mv: Move) -> isize {
+        return self.capture_history[capture_index(context.side, mv)] as isize;
+    }
+
+    // Ответ на предыдущий ход соперника prev_move, side - сторона, которая ходит
+    pub fn read_counter_move(&self, side: Side, prev_move: Move) -> Move {
+        return match try_piece_square_index(side.opp(), prev_move) {
+            Some(counter_index) => self.counter_moves[counter_index],
+            None => Move::NONE,
+        };
     }
 
     pub fn read_total(&self, context: &MoveOrderContext, mv: Move) -> isize {
@@ -89,6 +111,34 @@ impl HistoryTable {
                 true,
             );
         }
+        if let Some(counter_index) = context.counter_index {
+            self.counter_moves[counter_index] = best_move;
+        }
+    }
+
+    // Взятия до лучшего хода получают штраф, лучший ход-взятие - бонус.
+    // Вызывается и когда лучший ход тихий: тогда все взятия были хуже.
+    pub fn update_captures(
+        &mut self,
+        context: &MoveOrderContext,
+        captures_searched: &[Move],
+        best_move: Move,
+        depth: isize,
+    ) {
+        let bonus = (depth * depth).min(400);
+
+        for &m in captures_searched {
+            if m == best_move {
+                break;
+            }
+            let index = capture_index(context.side, m);
+            update_history_entry(&mut self.capture_history[index], bonus, false);
+        }
+
+        if utils::is_capture_or_promotion(best_move) {
+            let index = capture_index(context.side, best_move);
+            update_history_entry(&mut self.capture_history[index], bonus, true);
+        }
     }
 }
 
@@ -100,6 +150,10 @@ fn piece_square_index(side: Side, mv: Move) -> usize {
     return (side.index() << 9) ^ ((mv.moving_piece() as usize) << 6) ^ mv.to().index();
 }
 
+fn capture_index(side: Side, mv: Move) -> usize {
+    return (piece_square_index(side, mv) << 3) ^ (mv.captured_piece() as usize);
+}
+
 pub fn try_piece_square_index(side: Side, mv: Move) -> Option<usize> {
     match mv {
         Move::NONE => None,
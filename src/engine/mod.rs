@@ -1,3 +1,5 @@
+mod correction;
+mod evalcache;
 mod history;
 mod moveorder;
 //mod search;
@@ -7,24 +9,63 @@ mod timemanager;
 mod transtable;
 mod utils;
 
+use crate::book::Book;
 use crate::chess::{Move, Position};
-use crate::domain::{EngineOption, IEngine, IEvaluator, OptionValue, SearchInfo, SearchParams};
+use crate::domain::{
+    CancelToken, EngineOption, IEngine, IEvaluator, OptionValue, SearchInfo, SearchParams,
+};
 use crate::eval;
+use crate::tablebase::Tablebases;
+use correction::CorrectionHistory;
+use evalcache::EvalCache;
 use history::HistoryTable;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use timemanager::TimeManager;
 use transtable::TransTable;
 
+const MAX_THREADS: usize = 256;
+const MAX_MULTI_PV: usize = 64;
+const THREAD_STACK_SIZE: usize = 16 << 20;
+const MAX_EVAL_HASH: usize = 1 << 10;
+
 pub struct Engine {
     experiment: bool,
+    ponder: bool,
+    multi_pv: usize,
+    trans_table: Arc<TransTable>,
+    hash_file: String,
+    total_nodes: Arc<AtomicU64>,
+    syzygy_path: String,
+    tablebases: Arc<Tablebases>,
+    own_book: bool,
+    book_file: String,
+    book: Option<Book>,
+    evaluator: String,
+    eval_file: String,
+    eval_hash: usize,
+    threads: Vec<Thread>,
+}
+
+// Состояние одного потока поиска (Lazy SMP).
+// Общие между потоками только хеш-таблица, эндшпильные таблицы и счётчик узлов.
+struct Thread {
     nodes: u64,
+    total_nodes: Arc<AtomicU64>,
     stack: Vec<SearchStack>,
     evaluator: Box<dyn IEvaluator>,
     time_manager: TimeManager,
     repeats: Vec<u64>,
-    trans_table: TransTable,
+    trans_table: Arc<TransTable>,
+    eval_cache: EvalCache,
     reductions: utils::Reductions,
     history: HistoryTable,
-    progress: Box<dyn FnMut(&SearchInfo)>,
+    correction_history: CorrectionHistory,
+    multi_pv: usize,
+    root_skip_moves: Vec<Move>,
+    tablebases: Arc<Tablebases>,
+    root_moves: Vec<Move>,
 }
 
 #[derive(Clone)]
@@ -40,29 +81,185 @@ struct SearchStack {
 
 impl Engine {
     pub fn new() -> Self {
+        let trans_table = Arc::new(TransTable::new(64));
+        let eval_hash = 4;
+        let total_nodes = Arc::new(AtomicU64::new(0));
+        let tablebases = Arc::new(Tablebases::new());
+        // без файла сети движок продолжает работать на материальной оценке
+        let evaluator =
+            eval::make_eval("nnue", eval::nnue::DEFAULT_EVAL_FILE).unwrap_or_else(|err| {
+                println!("info string {}", err);
+                eval::make_eval("material", "").unwrap()
+            });
+        let main_thread = Thread::new(
+            evaluator,
+            trans_table.clone(),
+            eval_hash,
+            total_nodes.clone(),
+            tablebases.clone(),
+        );
         return Engine {
             experiment: false,
+            ponder: false,
+            multi_pv: 1,
+            trans_table: trans_table,
+            hash_file: String::new(),
+            total_nodes: total_nodes,
+            syzygy_path: String::new(),
+            tablebases: tablebases,
+            own_book: false,
+            book_file: String::new(),
+            book: None,
+            evaluator: String::from("nnue"),
+            eval_file: String::from(eval::nnue::DEFAULT_EVAL_FILE),
+            eval_hash: eval_hash,
+            threads: vec![main_thread],
+        };
+    }
+
+    fn set_threads(&mut self, count: usize) {
+        let count = count.clamp(1, MAX_THREADS);
+        self.threads.truncate(count);
+        while self.threads.len() < count {
+            let evaluator = self.threads[0].evaluator.box_clone();
+            self.threads.push(Thread::new(
+                evaluator,
+                self.trans_table.clone(),
+                self.eval_hash,
+                self.total_nodes.clone(),
+                self.tablebases.clone(),
+            ));
+        }
+        eprintln!("init threads {count}");
+    }
+
+    fn resize_trans_table(&mut self, megabytes: usize) {
+        // потоки держат ссылки на таблицу, забираем их на время изменения размера
+        for t in self.threads.iter_mut() {
+            t.trans_table = Arc::new(TransTable::new(0));
+        }
+        Arc::get_mut(&mut self.trans_table)
+            .unwrap()
+            .resize(megabytes);
+        for t in self.threads.iter_mut() {
+            t.trans_table = self.trans_table.clone();
+        }
+    }
+
+    fn set_trans_table(&mut self, trans_table: TransTable) {
+        self.trans_table = Arc::new(trans_table);
+        for t in self.threads.iter_mut() {
+            t.trans_table = self.trans_table.clone();
+        }
+    }
+
+    fn resize_eval_cache(&mut self, megabytes: usize) {
+        self.eval_hash = megabytes.min(MAX_EVAL_HASH);
+        for t in self.threads.iter_mut() {
+            t.eval_cache.resize(self.eval_hash);
+        }
+    }
+
+    fn set_syzygy_path(&mut self, path: &str) {
+        self.syzygy_path = path.to_string();
+        self.tablebases = Arc::new(Tablebases::load(path));
+        for t in self.threads.iter_mut() {
+            t.tablebases = self.tablebases.clone();
+        }
+    }
+
+    fn set_book_file(&mut self, path: &str) {
+        self.book_file = path.to_string();
+        self.book = None;
+        if path.is_empty() || path == "<empty>" {
+            return;
+        }
+        match Book::load(std::path::Path::new(path)) {
+            Ok(book) => {
+                eprintln!("load book {} entries {}", path, book.size());
+                self.book = Some(book);
+            }
+            Err(err) => eprintln!("failed load book {}: {}", path, err),
+        }
+    }
+
+    // При ошибке загрузки остается прежняя оценка
+    fn set_evaluator(&mut self, name: &str, eval_file: &str) {
+        match eval::make_eval(name, eval_file) {
+            Ok(evaluator) => {
+                for t in self.threads.iter_mut().skip(1) {
+                    t.evaluator = evaluator.box_clone();
+                }
+                self.threads[0].evaluator = evaluator;
+                self.evaluator = String::from(name);
+                self.eval_file = String::from(eval_file);
+                // сохраненные оценки посчитаны прежней функцией
+                self.clear();
+            }
+            Err(err) => println!("info string {}", err),
+        }
+    }
+}
+
+impl Thread {
+    fn new(
+        evaluator: Box<dyn IEvaluator>,
+        trans_table: Arc<TransTable>,
+        eval_hash: usize,
+        total_nodes: Arc<AtomicU64>,
+        tablebases: Arc<Tablebases>,
+    ) -> Self {
+        return Thread {
             nodes: 0,
-            evaluator: eval::make_eval("").unwrap(),
+            total_nodes: total_nodes,
+            evaluator: evaluator,
             time_manager: TimeManager::default(),
             repeats: Vec::new(),
-            trans_table: TransTable::new(64),
+            trans_table: trans_table,
+            eval_cache: EvalCache::new(eval_hash),
             reductions: utils::Reductions::new(utils::lmr_main),
             history: HistoryTable::new(),
-            progress: Box::new(|_| {}),
+            correction_history: CorrectionHistory::new(),
+            multi_pv: 1,
+            root_skip_moves: Vec::new(),
+            tablebases: tablebases,
+            root_moves: Vec::new(),
             stack: vec![unsafe { std::mem::zeroed() }; utils::STACK_SIZE],
         };
     }
-}
 
-impl IEngine for Engine {
     fn clear(&mut self) {
-        self.trans_table.clear();
         self.history.clear();
+        self.correction_history.clear();
+        self.eval_cache.clear();
         for stack in &mut self.stack {
             stack.killer1 = Move::NONE;
             stack.killer2 = Move::NONE;
         }
+    }
+
+    fn prepare(
+        &mut self,
+        position: &Position,
+        repeats: &[u64],
+        multi_pv: usize,
+        time_manager: TimeManager,
+    ) {
+        self.time_manager = time_manager;
+        self.multi_pv = multi_pv;
+        self.repeats = repeats.to_vec();
+        self.stack[0].position = position.clone();
+        self.evaluator.init(&self.stack[0].position);
+        self.nodes = 0;
+    }
+}
+
+impl IEngine for Engine {
+    fn clear(&mut self) {
+        self.trans_table.clear();
+        for t in self.threads.iter_mut() {
+            t.clear();
+        }
         eprintln!("engine clear");
     }
 
@@ -76,6 +273,61 @@ impl IEngine for Engine {
                     value: self.trans_table.size() as isize,
                 },
             },
+            EngineOption {
+                name: "EvalHash",
+                value: OptionValue::Int {
+                    min: 0,
+                    max: MAX_EVAL_HASH as isize,
+                    value: self.eval_hash as isize,
+                },
+            },
+            EngineOption {
+                name: "HashFile",
+                value: OptionValue::String(self.hash_file.clone()),
+            },
+            EngineOption {
+                name: "Threads",
+                value: OptionValue::Int {
+                    min: 1,
+                    max: MAX_THREADS as isize,
+                    value: self.threads.len() as isize,
+                },
+            },
+            EngineOption {
+                name: "Ponder",
+                value: OptionValue::Bool(self.ponder),
+            },
+            EngineOption {
+                name: "MultiPV",
+                value: OptionValue::Int {
+                    min: 1,
+                    max: MAX_MULTI_PV as isize,
+                    value: self.multi_pv as isize,
+                },
+            },
+            EngineOption {
+                name: "OwnBook",
+                value: OptionValue::Bool(self.own_book),
+            },
+            EngineOption {
+                name: "BookFile",
+                value: OptionValue::String(self.book_file.clone()),
+            },
+            EngineOption {
+                name: "SyzygyPath",
+                value: OptionValue::String(self.syzygy_path.clone()),
+            },
+            EngineOption {
+                name: "Evaluator",
+                value: OptionValue::Combo {
+                    value: self.evaluator.clone(),
+                    vars: eval::EVALUATORS.to_vec(),
+                },
+            },
+            EngineOption {
+                name: "EvalFile",
+                value: OptionValue::String(self.eval_file.clone()),
+            },
             EngineOption {
                 name: "ExperimentSettings",
                 value: OptionValue::Bool(self.experiment),
@@ -87,9 +339,51 @@ impl IEngine for Engine {
         match name {
             "Hash" => {
                 if let Ok(size) = value.parse::<u32>() {
-                    self.trans_table.resize(size as usize);
+                    self.resize_trans_table(size as usize);
+                }
+            }
+            "EvalHash" => {
+                if let Ok(size) = value.parse::<u32>() {
+                    self.resize_eval_cache(size as usize);
                 }
             }
+            "HashFile" => {
+                self.hash_file = if value == "<empty>" {
+                    String::new()
+                } else {
+                    value.to_string()
+                };
+            }
+            "Threads" => {
+                if let Ok(count) = value.parse::<u32>() {
+                    self.set_threads(count as usize);
+                }
+            }
+            "Ponder" => {
+                self.ponder = value.eq_ignore_ascii_case("true");
+            }
+            "MultiPV" => {
+                if let Ok(multi_pv) = value.parse::<u32>() {
+                    self.multi_pv = (multi_pv as usize).clamp(1, MAX_MULTI_PV);
+                }
+            }
+            "OwnBook" => {
+                self.own_book = value.eq_ignore_ascii_case("true");
+            }
+            "BookFile" => {
+                self.set_book_file(value);
+            }
+            "SyzygyPath" => {
+                self.set_syzygy_path(value);
+            }
+            "Evaluator" => {
+                let eval_file = self.eval_file.clone();
+                self.set_evaluator(value, &eval_file);
+            }
+            "EvalFile" => {
+                let evaluator = self.evaluator.clone();
+                self.set_evaluator(&evaluator, value);
+            }
             "ExperimentSettings" => {
                 self.experiment = value.eq_ignore_ascii_case("true");
             }
@@ -98,18 +392,261 @@ impl IEngine for Engine {
     }
 
     fn search(&mut self, search_params: SearchParams) -> SearchInfo {
-        self.time_manager = TimeManager::new(
-            search_params.limits,
-            search_params.cancel,
-            search_params.position.side_to_move,
-        );
-        self.repeats = search_params.repeats;
-        self.progress = search_params.progress;
-        self.stack[0].position = search_params.position.clone();
-        self.evaluator.init(&self.stack[0].position);
+        let position = search_params.position;
+        let mut progress = search_params.progress;
+
+        // ход из книги без поиска; при анализе книга не используется
+        if self.own_book && !search_params.limits.infinite {
+            if let Some(mv) = self.book.as_mut().and_then(|book| book.probe(&position)) {
+                return SearchInfo {
+                    main_line: vec![mv],
+                    ..Default::default()
+                };
+            }
+        }
 
         self.trans_table.inc_date();
-        self.nodes = 0;
-        return search_counter55::iterative_deepening(self);
+        self.total_nodes.store(0, Ordering::Relaxed);
+
+        let (main_thread, helpers) = self.threads.split_first_mut().unwrap();
+        main_thread.prepare(
+            &position,
+            &search_params.repeats,
+            self.multi_pv,
+            TimeManager::new(
+                search_params.limits,
+                search_params.cancel,
+                position.side_to_move,
+                self.ponder,
+                search_params.ponderhit,
+            ),
+        );
+        // вспомогательные потоки останавливаются, когда закончил главный
+        let helpers_cancel = CancelToken::new();
+        for t in helpers.iter_mut() {
+            t.prepare(
+                &position,
+                &search_params.repeats,
+                self.multi_pv,
+                TimeManager::helper(helpers_cancel.clone()),
+            );
+        }
+
+        let results = std::thread::scope(|scope| {
+            let handles: Vec<_> = helpers
+                .iter_mut()
+                .map(|t| {
+                    std::thread::Builder::new()
+                        .stack_size(THREAD_STACK_SIZE)
+                        .spawn_scoped(scope, move || {
+                            search_counter55::iterative_deepening(t, &mut |_| {})
+                        })
+                        .unwrap()
+                })
+                .collect();
+            let main_result = search_counter55::iterative_deepening(main_thread, progress.as_mut());
+            helpers_cancel.cancel();
+            let mut results = vec![main_result];
+            for h in handles {
+                results.push(h.join().unwrap());
+            }
+            results
+        });
+
+        let nodes = self.threads.iter().map(|t| t.nodes).sum();
+        // при MultiPV голосование по первому ходу перепутает линии
+        let mut result = if self.multi_pv == 1 {
+            select_best_result(results)
+        } else {
+            results.into_iter().next().unwrap()
+        };
+        result.nodes = nodes;
+        return result;
+    }
+
+    fn save_hash(&self) -> Result<(), String> {
+        if self.hash_file.is_empty() {
+            return Err(String::from("HashFile is not set"));
+        }
+        self.trans_table
+            .save_file(Path::new(&self.hash_file))
+            .map_err(|e| format!("failed save hash {}: {}", self.hash_file, e))?;
+        eprintln!("save hash {}", self.hash_file);
+        return Ok(());
     }
+
+    // Таблица из файла заменяет текущую вместе с размером (опция Hash)
+    fn load_hash(&mut self) -> Result<(), String> {
+        if self.hash_file.is_empty() {
+            return Err(String::from("HashFile is not set"));
+        }
+        let trans_table = TransTable::load_file(Path::new(&self.hash_file))
+            .map_err(|e| format!("failed load hash {}: {}", self.hash_file, e))?;
+        eprintln!("load hash {}", self.hash_file);
+        self.set_trans_table(trans_table);
+        return Ok(());
+    }
+}
+
+// Голосование потоков: ход получает голоса пропорционально глубине и оценке.
+fn select_best_result(results: Vec<SearchInfo>) -> SearchInfo {
+    let completed: Vec<_> = results
+        .into_iter()
+        .filter(|x| !x.main_line.is_empty())
+        .collect();
+    if completed.len() <= 1 {
+        return completed.into_iter().next().unwrap_or_default();
+    }
+
+    let min_score = completed
+        .iter()
+        .map(|x| utils::uci_score_value(x.score))
+        .min()
+        .unwrap();
+    let vote = |mv: Move| -> isize {
+        completed
+            .iter()
+            .filter(|x| x.main_line[0] == mv)
+            .map(|x| (utils::uci_score_value(x.score) - min_score + 14) * x.depth as isize)
+            .sum()
+    };
+
+    let mut best_index = 0;
+    let mut best_vote = vote(completed[0].main_line[0]);
+    for (i, si) in completed.iter().enumerate().skip(1) {
+        let best = &completed[best_index];
+        let best_score = utils::uci_score_value(best.score);
+        let score = utils::uci_score_value(si.score);
+        if best_score >= utils::VALUE_WIN {
+            // найденный мат не отдаём
+            if score > best_score {
+                best_index = i;
+                best_vote = vote(si.main_line[0]);
+            }
+            continue;
+        }
+        let v = vote(si.main_line[0]);
+        if score >= utils::VALUE_WIN || v > best_vote {
+            best_index = i;
+            best_vote = v;
+        }
+    }
+    return completed.into_iter().nth(best_index).unwrap();
+}
+
+#[test]
+fn test_select_best_result() {
+    use crate::domain::UciScore;
+    unsafe { crate::chess::init() };
+    let pos = Position::from_fen(Position::INITIAL_POSITION_FEN).unwrap();
+    let mv = |lan: &str| Move::parse_lan(&pos, lan).unwrap();
+    let info = |depth: usize, score: UciScore, lan: &str| SearchInfo {
+        depth: depth,
+        score: score,
+        main_line: vec![mv(lan)],
+        ..Default::default()
+    };
+    let best = |results: Vec<SearchInfo>| select_best_result(results).main_line;
+
+    assert!(best(vec![]).is_empty());
+    // поток без завершенной итерации не голосует
+    assert_eq!(
+        best(vec![
+            SearchInfo::default(),
+            info(10, UciScore::Centipawns(20), "e2e4")
+        ]),
+        vec![mv("e2e4")]
+    );
+    // два потока за d2d4 перевешивают один с оценкой чуть лучше
+    assert_eq!(
+        best(vec![
+            info(10, UciScore::Centipawns(30), "e2e4"),
+            info(10, UciScore::Centipawns(20), "d2d4"),
+            info(10, UciScore::Centipawns(20), "d2d4"),
+        ]),
+        vec![mv("d2d4")]
+    );
+    // голос пропорционален глубине
+    assert_eq!(
+        best(vec![
+            info(20, UciScore::Centipawns(20), "e2e4"),
+            info(8, UciScore::Centipawns(20), "d2d4"),
+            info(8, UciScore::Centipawns(20), "d2d4"),
+        ]),
+        vec![mv("e2e4")]
+    );
+    // найденный мат не отдается большинству, более короткий мат лучше
+    assert_eq!(
+        best(vec![
+            info(5, UciScore::Mate(3), "e2e4"),
+            info(20, UciScore::Centipawns(50), "d2d4"),
+            info(20, UciScore::Centipawns(50), "d2d4"),
+        ]),
+        vec![mv("e2e4")]
+    );
+    assert_eq!(
+        best(vec![
+            info(10, UciScore::Centipawns(0), "e2e4"),
+            info(6, UciScore::Mate(5), "g1f3"),
+            info(7, UciScore::Mate(2), "d2d4"),
+        ]),
+        vec![mv("d2d4")]
+    );
+}
+
+#[test]
+fn test_multi_pv() {
+    use crate::domain::{LimitsType, PonderHitToken};
+    unsafe { crate::chess::init() };
+    // поиску нужен большой стек
+    std::thread::Builder::new()
+        .stack_size(THREAD_STACK_SIZE)
+        .spawn(|| {
+            let mut engine = Engine::new();
+            engine.set_option("Evaluator", "classical");
+            engine.set_option("MultiPV", "3");
+            let mut search = |fen: &str| {
+                engine.clear();
+                return engine.search(SearchParams {
+                    position: Position::from_fen(fen).unwrap(),
+                    repeats: Vec::new(),
+                    limits: LimitsType {
+                        fixed_depth: Some(6),
+                        ..Default::default()
+                    },
+                    cancel: CancelToken::new(),
+                    ponderhit: PonderHitToken::new(),
+                    progress: Box::new(|_| {}),
+                });
+            };
+
+            // лучший ход - взятие ферзя, остальные линии не лучше и с другими ходами
+            let pos = Position::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+            let result = search("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1");
+            assert_eq!(result.lines.len(), 3);
+            assert_eq!(result.main_line, result.lines[0].main_line);
+            assert_eq!(result.main_line[0], Move::parse_lan(&pos, "d2d5").unwrap());
+            let scores: Vec<isize> = result
+                .lines
+                .iter()
+                .map(|x| utils::uci_score_value(x.score))
+                .collect();
+            assert_eq!(scores[0], utils::uci_score_value(result.score));
+            assert!(scores.windows(2).all(|x| x[0] >= x[1]), "{:?}", scores);
+            let mut first_moves: Vec<u32> =
+                result.lines.iter().map(|x| x.main_line[0].raw()).collect();
+            first_moves.sort();
+            first_moves.dedup();
+            assert_eq!(first_moves.len(), 3);
+
+            // линий не больше, чем ходов
+            let result = search("7k/8/8/8/8/8/8/K7 w - - 0 1");
+            assert_eq!(result.lines.len(), 3);
+            let result = search("k7/8/8/8/8/8/1r6/K7 w - - 0 1");
+            assert_eq!(result.lines.len(), 1);
+            assert_eq!(result.main_line, result.lines[0].main_line);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
 }
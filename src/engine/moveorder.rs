@@ -2,11 +2,14 @@ use super::history::{self, HistoryTable};
 use super::{see, utils};
 use crate::chess::{Move, MoveList, Piece, Position, Side};
 
+#[derive(Clone, Copy)]
 pub struct MoveOrderContext {
     pub side: Side,
     pub trans_move: Move,
     pub killer1: Move,
     pub killer2: Move,
+    // ответ на предыдущий ход соперника из таблицы истории
+    pub counter_move: Move,
     pub counter_index: Option<usize>,
     pub follow_index: Option<usize>,
 }
@@ -17,57 +20,203 @@ impl MoveOrderContext {
         trans_move: Move,
         killer1: Move,
         killer2: Move,
-        counter_move: Move,
+        prev_move: Move,
         follow_move: Move,
+        counter_move: Move,
     ) -> Self {
         return MoveOrderContext {
             side,
             trans_move,
             killer1,
             killer2,
-            counter_index: history::try_piece_square_index(side.opp(), counter_move),
+            counter_move,
+            counter_index: history::try_piece_square_index(side.opp(), prev_move),
             follow_index: history::try_piece_square_index(side, follow_move),
         };
     }
+}
+
+#[derive(Clone, Copy)]
+enum Stage {
+    TransMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Killer1,
+    Killer2,
+    CounterMove,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    // только взятия, без деления по SEE
+    Captures,
+    Done,
+}
+
+// Ходы выдаются по одному, генерация и сортировка - только когда до них дошло:
+// ход из хеш-таблицы, выгодные взятия (SEE >= 0), killer-ходы, ответный ход,
+// тихие ходы по истории, невыгодные взятия.
+pub struct MovePicker {
+    context: MoveOrderContext,
+    stage: Stage,
+    captures_only: bool,
+    captures: MoveList,
+    quiets: MoveList,
+    index: usize,
+    // невыгодные взятия складываются в начало captures, на место выданных
+    bad_captures: usize,
+}
+
+impl MovePicker {
+    pub fn new(context: MoveOrderContext) -> Self {
+        return MovePicker {
+            context: context,
+            stage: Stage::TransMove,
+            captures_only: false,
+            captures: MoveList::new(),
+            quiets: MoveList::new(),
+            index: 0,
+            bad_captures: 0,
+        };
+    }
+
+    // Взятия и превращения в ферзя для форсированного поиска и probcut
+    pub fn new_captures(context: MoveOrderContext) -> Self {
+        return MovePicker {
+            stage: Stage::GenerateCaptures,
+            captures_only: true,
+            ..MovePicker::new(context)
+        };
+    }
 
-    pub fn evaluate_moves_counter55(
-        &self,
-        ml: &mut MoveList,
-        pos: &Position,
-        history: &HistoryTable,
-    ) {
-        for item in &mut ml.moves[..ml.size] {
-            let mv = item.mv;
-            let key = if mv == self.trans_move {
-                102_000
-            } else if utils::is_capture_or_promotion(mv) {
-                if see::see_ge(pos, mv, 0) {
-                    101_000 + mvvlva(mv)
-                } else {
-                    0 + mvvlva(mv)
+    pub fn next_move(&mut self, pos: &Position, history: &HistoryTable) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::TransMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    if pos.is_pseudo_legal(self.context.trans_move) {
+                        return Some(self.context.trans_move);
+                    }
                 }
-            } else if mv == self.killer1 {
-                100_001
-            } else if mv == self.killer2 {
-                100_000
-            } else {
-                history.read_total(self, mv)
-            };
-            item.key = key as i32;
+                Stage::GenerateCaptures => {
+                    self.captures.gen_captures(pos);
+                    // MVV/LVA, уточненный историей взятий
+                    for item in &mut self.captures.moves[..self.captures.size] {
+                        let mv = item.mv;
+                        item.key =
+                            (4_096 * mvvlva(mv) + history.read_capture(&self.context, mv)) as i32;
+                    }
+                    self.index = 0;
+                    self.stage = if self.captures_only {
+                        Stage::Captures
+                    } else {
+                        Stage::GoodCaptures
+                    };
+                }
+                Stage::GoodCaptures => {
+                    if self.index == self.captures.size {
+                        self.stage = Stage::Killer1;
+                        continue;
+                    }
+                    let mv = pick_best(&mut self.captures, self.index);
+                    self.index += 1;
+                    if mv == self.context.trans_move {
+                        continue;
+                    }
+                    if !see::see_ge(pos, mv, 0) {
+                        self.captures.moves[self.bad_captures].mv = mv;
+                        self.bad_captures += 1;
+                        continue;
+                    }
+                    return Some(mv);
+                }
+                Stage::Killer1 => {
+                    self.stage = Stage::Killer2;
+                    if self.is_refutation(pos, self.context.killer1) {
+                        return Some(self.context.killer1);
+                    }
+                }
+                Stage::Killer2 => {
+                    self.stage = Stage::CounterMove;
+                    if self.is_refutation(pos, self.context.killer2) {
+                        return Some(self.context.killer2);
+                    }
+                }
+                Stage::CounterMove => {
+                    self.stage = Stage::GenerateQuiets;
+                    let mv = self.context.counter_move;
+                    if mv != self.context.killer1
+                        && mv != self.context.killer2
+                        && self.is_refutation(pos, mv)
+                    {
+                        return Some(mv);
+                    }
+                }
+                Stage::GenerateQuiets => {
+                    self.quiets.gen_quiets(pos);
+                    for item in &mut self.quiets.moves[..self.quiets.size] {
+                        item.key = history.read_total(&self.context, item.mv) as i32;
+                    }
+                    self.index = 0;
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => {
+                    if self.index == self.quiets.size {
+                        self.index = 0;
+                        self.stage = Stage::BadCaptures;
+                        continue;
+                    }
+                    let mv = pick_best(&mut self.quiets, self.index);
+                    self.index += 1;
+                    if mv == self.context.trans_move
+                        || mv == self.context.killer1
+                        || mv == self.context.killer2
+                        || mv == self.context.counter_move
+                    {
+                        continue;
+                    }
+                    return Some(mv);
+                }
+                Stage::BadCaptures => {
+                    if self.index == self.bad_captures {
+                        self.stage = Stage::Done;
+                        continue;
+                    }
+                    let mv = self.captures.moves[self.index].mv;
+                    self.index += 1;
+                    return Some(mv);
+                }
+                Stage::Captures => {
+                    if self.index == self.captures.size {
+                        self.stage = Stage::Done;
+                        continue;
+                    }
+                    let mv = pick_best(&mut self.captures, self.index);
+                    self.index += 1;
+                    return Some(mv);
+                }
+                Stage::Done => return None,
+            }
         }
     }
+
+    // Тихий ход из отдельной стадии: killer или ответный ход
+    fn is_refutation(&self, pos: &Position, mv: Move) -> bool {
+        return mv != self.context.trans_move
+            && !utils::is_capture_or_promotion(mv)
+            && pos.is_pseudo_legal(mv);
+    }
 }
 
-pub fn evaluate_captures(ml: &mut MoveList) {
-    for item in &mut ml.moves[..ml.size] {
-        let mv = item.mv;
-        let key = if utils::is_capture_or_promotion(mv) {
-            mvvlva(mv)
-        } else {
-            -100_000
-        };
-        item.key = key as i32;
+// Ход с наибольшим ключом среди еще не выданных встает на место index
+fn pick_best(ml: &mut MoveList, index: usize) -> Move {
+    let mut best = index;
+    for i in index + 1..ml.size {
+        if ml.moves[i].key > ml.moves[best].key {
+            best = i;
+        }
     }
+    ml.moves.swap(index, best);
+    return ml.moves[index].mv;
 }
 
 fn piece_value(piece: Piece) -> isize {
@@ -1,64 +1,102 @@
-use super::{Engine, SearchStack, moveorder, see, transtable, utils};
-use crate::chess::{Move, MoveList, Piece, Position, Side};
-use crate::domain::{IEvaluator, SearchInfo};
+use super::evalcache::EvalCache;
+use super::moveorder::{self, MovePicker};
+use super::{SearchStack, Thread, see, transtable, utils};
+use crate::chess::{Move, MoveList, Piece, PieceDeltas, Position, Side};
+use crate::domain::{IEvaluator, PvLine, SearchInfo};
+use crate::tablebase;
+use std::sync::atomic::Ordering;
 
 // Клон поиска из Counter 5.5 (на golang)
 // https://github.com/ChizhovVadim/CounterGo/blob/a46cf2d76571b28ab093e626acead47815dd4c25/pkg/engine/search.go
-pub fn iterative_deepening(e: &mut Engine) -> SearchInfo {
-    e.reductions = utils::Reductions::new(lmr_counter55);
+pub fn iterative_deepening(t: &mut Thread, progress: &mut dyn FnMut(&SearchInfo)) -> SearchInfo {
+    t.reductions = utils::Reductions::new(lmr_counter55);
 
     let mut result = SearchInfo::default();
 
     let mut legal_moves = MoveList::new();
-    legal_moves.gen_legal_moves(&e.stack[0].position);
+    legal_moves.gen_legal_moves(&t.stack[0].position);
     if legal_moves.size == 0 {
         return result;
     }
-    let rnd_move = legal_moves.moves[0].mv;
+    t.root_moves = legal_moves.moves[..legal_moves.size]
+        .iter()
+        .map(|x| x.mv)
+        .collect();
+    // в эндшпиле оставляем только ходы, сохраняющие результат по таблицам
+    if let Some(moves) = t
+        .tablebases
+        .root_probe(&t.stack[0].position, &t.root_moves, &t.repeats)
+    {
+        if !moves.is_empty() {
+            t.root_moves = moves;
+        }
+    }
+    let rnd_move = t.root_moves[0];
     result.main_line = vec![rnd_move];
 
     for height in 0..=2 {
-        e.stack[height].killer1 = Move::NONE;
-        e.stack[height].killer2 = Move::NONE;
+        t.stack[height].killer1 = Move::NONE;
+        t.stack[height].killer2 = Move::NONE;
     }
 
-    let mut prev_score = 0;
-    for depth in 1..utils::MAX_HEIGHT {
-        if e.time_manager.check_timeout(e.nodes) {
+    let multi_pv = t.multi_pv.clamp(1, t.root_moves.len());
+    let mut prev_scores = vec![0; multi_pv];
+    'iterations: for depth in 1..utils::MAX_HEIGHT {
+        if t.time_manager.check_timeout(nodes_searched(t)) {
             break;
         }
         //self.root_depth = depth;
-        let score = aspiration_window(e, depth as isize, prev_score);
-        match score {
-            Some(score) => {
-                assert!(e.stack[0].pv_size > 0);
-                prev_score = score;
-                result = SearchInfo {
-                    depth: depth,
-                    score: utils::make_uci_score(score),
-                    nodes: e.nodes,
-                    duration: e.time_manager.elapsed(),
-                    main_line: e.stack[0].pv[..e.stack[0].pv_size].to_vec(),
-                };
-                (e.progress)(&result);
-                e.time_manager.iteration_complete(&result);
-            }
-            None => {
-                result.nodes = e.nodes;
-                result.duration = e.time_manager.elapsed();
-                break;
+        let mut lines: Vec<PvLine> = Vec::with_capacity(multi_pv);
+        let mut scores: Vec<isize> = Vec::with_capacity(multi_pv);
+        t.root_skip_moves.clear();
+        for &prev_score in prev_scores.iter() {
+            let score = aspiration_window(t, depth as isize, prev_score);
+            match score {
+                Some(score) => {
+                    assert!(t.stack[0].pv_size > 0);
+                    let main_line = t.stack[0].pv[..t.stack[0].pv_size].to_vec();
+                    t.root_skip_moves.push(main_line[0]);
+                    // линия с исключенными ходами может оказаться лучше предыдущих
+                    let index = scores.iter().take_while(|&&x| x >= score).count();
+                    scores.insert(index, score);
+                    lines.insert(
+                        index,
+                        PvLine {
+                            score: utils::make_uci_score(score),
+                            main_line: main_line,
+                        },
+                    );
+                }
+                None => {
+                    result.nodes = nodes_searched(t);
+                    result.duration = t.time_manager.elapsed();
+                    break 'iterations;
+                }
             }
         }
+        prev_scores = scores;
+        result = SearchInfo {
+            depth: depth,
+            score: lines[0].score,
+            nodes: nodes_searched(t),
+            duration: t.time_manager.elapsed(),
+            main_line: lines[0].main_line.clone(),
+            lines: lines,
+            hashfull: t.trans_table.hashfull(),
+        };
+        progress(&result);
+        t.time_manager.iteration_complete(&result);
     }
+    t.root_skip_moves.clear();
     return result;
 }
 
-fn aspiration_window(e: &mut Engine, depth: isize, prev_score: isize) -> Option<isize> {
+fn aspiration_window(t: &mut Thread, depth: isize, prev_score: isize) -> Option<isize> {
     if depth >= 5 && prev_score > utils::VALUE_LOSS && prev_score < utils::VALUE_WIN {
         const WINDOW: isize = 25;
         let mut alpha = (prev_score - WINDOW).max(-utils::VALUE_INFINITY);
         let mut beta = (prev_score + WINDOW).min(utils::VALUE_INFINITY);
-        let score = search(e, alpha, beta, depth, 0, Move::NONE)?;
+        let score = search(t, alpha, beta, depth, 0, Move::NONE)?;
         if score > alpha && score < beta {
             return Some(score);
         }
@@ -68,13 +106,13 @@ fn aspiration_window(e: &mut Engine, depth: isize, prev_score: isize) -> Option<
         if score <= alpha {
             alpha = -utils::VALUE_INFINITY;
         }
-        let score = search(e, alpha, beta, depth, 0, Move::NONE)?;
+        let score = search(t, alpha, beta, depth, 0, Move::NONE)?;
         if score > alpha && score < beta {
             return Some(score);
         }
     }
     return search(
-        e,
+        t,
         -utils::VALUE_INFINITY,
         utils::VALUE_INFINITY,
         depth,
@@ -85,7 +123,7 @@ fn aspiration_window(e: &mut Engine, depth: isize, prev_score: isize) -> Option<
 
 #[allow(invalid_value)]
 fn search(
-    e: &mut Engine,
+    t: &mut Thread,
     mut alpha: isize,
     beta: isize,
     depth: isize,
@@ -93,21 +131,22 @@ fn search(
     skip_move: Move,
 ) -> Option<isize> {
     if depth <= 0 {
-        return qs(e, alpha, beta, height);
+        return qs(t, alpha, beta, height);
     }
 
-    e.stack[height].pv_size = 0; // TODO clear_pv(e, height)
+    t.stack[height].pv_size = 0; // TODO clear_pv(t, height)
     let root_node = height == 0;
     let pv_node = beta != alpha + 1;
 
     if !root_node {
         if height >= utils::MAX_HEIGHT {
-            return Some(evaluate2(e, height));
+            let raw_eval = evaluate2(t, height);
+            return Some(corrected_eval(t, height, raw_eval));
         }
-        if utils::is_draw(&e.stack[height].position) {
+        if utils::is_draw(&t.stack[height].position) {
             return Some(utils::VALUE_DRAW);
         }
-        if is_repeat(e, height) {
+        if is_repeat(t, height) {
             return Some(utils::VALUE_DRAW);
         }
         let ralpha = alpha.max(utils::loss_in(height));
@@ -117,22 +156,22 @@ fn search(
         }
     }
 
-    let (tt_depth, mut tt_value, tt_bound, tt_move, tt_hit) = if skip_move == Move::NONE {
-        e.trans_table.read(e.stack[height].position.key)
+    let (tt_depth, mut tt_value, tt_bound, tt_move, tt_eval, tt_hit) = if skip_move == Move::NONE {
+        t.trans_table.read(t.stack[height].position.key)
     } else {
-        (0, 0, 0, Move::NONE, false)
+        (0, 0, 0, Move::NONE, transtable::EVAL_NONE, false)
     };
     if tt_hit {
         tt_value = utils::value_from_tt(tt_value, height);
         if tt_depth >= depth
             && !root_node
             && !pv_node
-            && !(e.stack[height - 1].current_mv.is_null())
+            && !(t.stack[height - 1].current_mv.is_null())
         {
             if tt_value >= beta && tt_bound & transtable::BOUND_LOWER != 0 {
                 if tt_move != Move::NONE && !utils::is_capture_or_promotion(tt_move) {
-                    update_killer(e, height, tt_move);
-                    //e.history.update(e.stack[height].position.side_to_move, &[], tt_move, depth);
+                    update_killer(t, height, tt_move);
+                    //t.history.update(t.stack[height].position.side_to_move, &[], tt_move, depth);
                 }
                 return Some(tt_value);
             }
@@ -142,15 +181,52 @@ fn search(
         }
     }
 
+    // эндшпильные таблицы: WDL точен только сразу после обнуления счетчика 50 ходов
+    if !root_node && skip_move == Move::NONE {
+        let pos = &t.stack[height].position;
+        if pos.rule50 == 0 && t.tablebases.can_probe(pos) {
+            if let Some(wdl) = t.tablebases.probe_wdl(pos) {
+                // выигрыш или проигрыш с учетом правила 50 ходов считаем ничьей
+                let (score, bound) = if wdl > tablebase::WDL_CURSED_WIN {
+                    (utils::tb_win_in(height), transtable::BOUND_LOWER)
+                } else if wdl < tablebase::WDL_BLESSED_LOSS {
+                    (utils::tb_loss_in(height), transtable::BOUND_UPPER)
+                } else {
+                    (utils::VALUE_DRAW, transtable::BOUND_EXACT)
+                };
+                if bound == transtable::BOUND_EXACT
+                    || bound == transtable::BOUND_LOWER && score >= beta
+                    || bound == transtable::BOUND_UPPER && score <= alpha
+                {
+                    t.trans_table.update_old_policy(
+                        pos.key,
+                        (depth + 6).min(utils::MAX_HEIGHT as isize),
+                        utils::value_to_tt(score, height),
+                        bound,
+                        Move::NONE,
+                        transtable::EVAL_NONE,
+                    );
+                    return Some(score);
+                }
+            }
+        }
+    }
+
     if height + 2 <= utils::MAX_HEIGHT {
-        e.stack[height + 2].killer1 = Move::NONE;
-        e.stack[height + 2].killer2 = Move::NONE;
+        t.stack[height + 2].killer1 = Move::NONE;
+        t.stack[height + 2].killer2 = Move::NONE;
     }
 
-    let in_check = e.stack[height].position.is_check();
-    let static_eval = evaluate2(e, height);
-    e.stack[height].static_eval = static_eval;
-    let improving = height < 2 || static_eval > e.stack[height - 2].static_eval;
+    let in_check = t.stack[height].position.is_check();
+    // в хеш-таблицу пишется оценка без поправки: поправка меняется со временем
+    let raw_eval = if tt_eval != transtable::EVAL_NONE {
+        tt_eval
+    } else {
+        evaluate2(t, height)
+    };
+    let static_eval = corrected_eval(t, height, raw_eval);
+    t.stack[height].static_eval = static_eval;
+    let improving = height < 2 || static_eval > t.stack[height - 2].static_eval;
 
     let mut tt_move_is_singular = false;
 
@@ -170,24 +246,24 @@ fn search(
             && beta < utils::VALUE_WIN
             && static_eval >= beta
             && !(tt_hit && tt_value < beta && (tt_bound & transtable::BOUND_UPPER) != 0)
-            && !(e.stack[height - 1].current_mv.is_null())
-            && !(height >= 2 && e.stack[height - 2].current_mv.is_null())
-            && utils::allow_nmp(&e.stack[height].position)
+            && !(t.stack[height - 1].current_mv.is_null())
+            && !(height >= 2 && t.stack[height - 2].current_mv.is_null())
+            && utils::allow_nmp(&t.stack[height].position)
         {
             let reduction = 4 + depth / 6 + ((static_eval - beta) / 200).min(2);
-            make_move(e, Move::NULL, height);
-            if is_timeout(e) {
+            make_move(t, Move::NULL, height);
+            if is_timeout(t) {
                 return None;
             }
             let score = -(search(
-                e,
+                t,
                 -beta,
                 1 - beta,
                 depth - reduction,
                 height + 1,
                 Move::NONE,
             )?);
-            unmake_move(e);
+            unmake_move(t);
             if score >= beta {
                 if score >= utils::VALUE_WIN {
                     return Some(beta);
@@ -207,26 +283,21 @@ fn search(
                 && tt_value < probcut_beta
                 && tt_bound & transtable::BOUND_UPPER != 0)
         {
-            let mut ml = MoveList::new();
-            ml.gen_captures(&e.stack[height].position);
-            moveorder::evaluate_captures(&mut ml);
-            ml.sort();
-
-            for item in &ml.moves[..ml.size] {
-                let mv = item.mv;
-                if !see::see_ge(&e.stack[height].position, mv, 0) {
+            let mut picker = MovePicker::new_captures(get_moveorder_context(t, height, Move::NONE));
+            while let Some(mv) = picker.next_move(&t.stack[height].position, &t.history) {
+                if !see::see_ge(&t.stack[height].position, mv, 0) {
                     continue;
                 }
-                if !make_move(e, mv, height) {
+                if !make_move(t, mv, height) {
                     continue;
                 }
-                if is_timeout(e) {
+                if is_timeout(t) {
                     return None;
                 }
-                let mut score = -(qs(e, -probcut_beta, -(probcut_beta - 1), height + 1)?);
+                let mut score = -(qs(t, -probcut_beta, -(probcut_beta - 1), height + 1)?);
                 if score >= probcut_beta {
                     score = -(search(
-                        e,
+                        t,
                         -probcut_beta,
                         1 - probcut_beta,
                         depth - 4,
@@ -234,7 +305,7 @@ fn search(
                         Move::NONE,
                     )?);
                 }
-                unmake_move(e);
+                unmake_move(t);
                 if score >= probcut_beta {
                     return Some(score);
                 }
@@ -252,7 +323,7 @@ fn search(
         {
             let singular_beta = (tt_value - 1 * depth).max(-utils::VALUE_INFINITY);
             let score = search(
-                e,
+                t,
                 singular_beta - 1,
                 singular_beta,
                 depth / 2,
@@ -265,18 +336,16 @@ fn search(
         }
     }
 
-    let moveorder_context = get_moveorder_context(e, height, tt_move);
-    let pos = &e.stack[height].position;
-    let mut ml = MoveList::new();
-    ml.gen_moves(pos);
-    moveorder_context.evaluate_moves_counter55(&mut ml, pos, &e.history);
-    ml.sort();
+    let moveorder_context = get_moveorder_context(t, height, tt_move);
+    let mut picker = MovePicker::new(moveorder_context);
 
     let mut has_legal_move = false;
     let mut best_move = Move::NONE;
     let mut moves_searched = 0;
     let mut quiets: [Move; 64] = unsafe { std::mem::MaybeUninit::uninit().assume_init() };
     let mut quiet_count = 0;
+    let mut captures = [Move::NONE; 32];
+    let mut capture_count = 0;
     let mut quiets_seen = 0;
     let old_alpha = alpha;
     let mut best = utils::loss_in(height);
@@ -288,9 +357,10 @@ fn search(
         res
     };
 
-    for item in &ml.moves[..ml.size] {
-        let mv = item.mv;
-        if mv == skip_move {
+    while let Some(mv) = picker.next_move(&t.stack[height].position, &t.history) {
+        if mv == skip_move
+            || root_node && (t.root_skip_moves.contains(&mv) || !t.root_moves.contains(&mv))
+        {
             continue;
         }
         let is_noisy = utils::is_capture_or_promotion(mv);
@@ -301,7 +371,7 @@ fn search(
         if depth <= 8 && best > utils::VALUE_LOSS && has_legal_move && !root_node && !in_check {
             if is_noisy {
                 let see_margin = depth.max((static_eval - alpha + 100) / 100);
-                if !see::see_ge(&e.stack[height].position, mv, -see_margin) {
+                if !see::see_ge(&t.stack[height].position, mv, -see_margin) {
                     continue;
                 }
             } else {
@@ -315,21 +385,21 @@ fn search(
                 {
                     continue;
                 }
-                if !see::see_ge(&e.stack[height].position, mv, -depth / 2) {
+                if !see::see_ge(&t.stack[height].position, mv, -depth / 2) {
                     continue;
                 }
             }
         }
 
-        if !make_move(e, mv, height) {
+        if !make_move(t, mv, height) {
             continue;
         }
-        if is_timeout(e) {
+        if is_timeout(t) {
             return None;
         }
         has_legal_move = true;
         moves_searched += 1;
-        let gives_check = e.stack[height + 1].position.is_check();
+        let gives_check = t.stack[height + 1].position.is_check();
 
         let mut extension = 0;
         if mv == tt_move && tt_move_is_singular {
@@ -341,8 +411,11 @@ fn search(
 
         let mut reduction = 0;
         if depth >= 3 && moves_searched > 1 && !is_noisy {
-            reduction = e.reductions.get(depth, moves_searched);
-            if mv == moveorder_context.killer1 || mv == moveorder_context.killer2 {
+            reduction = t.reductions.get(depth, moves_searched);
+            if mv == moveorder_context.killer1
+                || mv == moveorder_context.killer2
+                || mv == moveorder_context.counter_move
+            {
                 reduction -= 1;
             }
             if in_check || gives_check {
@@ -352,13 +425,18 @@ fn search(
                 reduction -= 2;
             }
             if !in_check {
-                reduction -= ((e.history.read_total(&moveorder_context, mv)) / 5_000).clamp(-2, 2);
+                reduction -= ((t.history.read_total(&moveorder_context, mv)) / 5_000).clamp(-2, 2);
                 if !improving {
                     reduction += 1;
                 }
             }
             reduction = reduction.max(0) + extension;
             reduction = reduction.min(depth - 2).max(0);
+        } else if depth >= 3 && moves_searched > 1 && !pv_node && !in_check && !gives_check {
+            // взятие, которое раньше часто оказывалось хуже других ходов
+            if t.history.read_capture(&moveorder_context, mv) < -8_192 {
+                reduction = 1;
+            }
         }
 
         let new_depth = depth - 1 + extension;
@@ -368,11 +446,15 @@ fn search(
             quiets[quiet_count] = mv;
             quiet_count += 1;
         }
+        if is_noisy && capture_count < captures.len() {
+            captures[capture_count] = mv;
+            capture_count += 1;
+        }
 
         let mut score = alpha + 1;
         if reduction > 0 {
             score = -(search(
-                e,
+                t,
                 -(alpha + 1),
                 -alpha,
                 new_depth - reduction,
@@ -381,20 +463,20 @@ fn search(
             )?);
         }
         if score > alpha && beta != alpha + 1 && moves_searched > 1 && new_depth > 0 {
-            score = -(search(e, -(alpha + 1), -alpha, new_depth, height + 1, Move::NONE)?);
+            score = -(search(t, -(alpha + 1), -alpha, new_depth, height + 1, Move::NONE)?);
         }
         if score > alpha {
-            score = -(search(e, -beta, -alpha, new_depth, height + 1, Move::NONE)?);
+            score = -(search(t, -beta, -alpha, new_depth, height + 1, Move::NONE)?);
         }
 
-        unmake_move(e);
+        unmake_move(t);
         if score > best {
             best = score;
             best_move = mv;
         }
         if score > alpha {
             alpha = score;
-            assign_pv(e, height, mv);
+            assign_pv(t, height, mv);
             if alpha >= beta {
                 break;
             }
@@ -408,11 +490,19 @@ fn search(
         return Some(utils::loss_in(height));
     }
 
-    if alpha > old_alpha && best_move != Move::NONE && !utils::is_capture_or_promotion(best_move) {
-        update_killer(e, height, best_move);
+    if alpha > old_alpha && best_move != Move::NONE {
+        if !utils::is_capture_or_promotion(best_move) {
+            update_killer(t, height, best_move);
 
-        e.history
-            .update(&moveorder_context, &quiets[..quiet_count], best_move, depth);
+            t.history
+                .update(&moveorder_context, &quiets[..quiet_count], best_move, depth);
+        }
+        t.history.update_captures(
+            &moveorder_context,
+            &captures[..capture_count],
+            best_move,
+            depth,
+        );
     }
 
     if skip_move == Move::NONE {
@@ -423,36 +513,52 @@ fn search(
         } else {
             transtable::BOUND_UPPER
         };
-        if !(root_node && bound == transtable::BOUND_UPPER) {
-            e.trans_table.update_old_policy(
-                e.stack[height].position.key,
+        // при MultiPV лучший ход корня исключен, такой результат в таблицу не пишем
+        if !(root_node && (bound == transtable::BOUND_UPPER || !t.root_skip_moves.is_empty())) {
+            t.trans_table.update_old_policy(
+                t.stack[height].position.key,
                 depth,
                 utils::value_to_tt(best, height),
                 bound,
                 best_move,
+                raw_eval,
             );
+
+            // учим поправку, только если оценка поиска точнее статической:
+            // не по взятиям и не по границе, которая статической оценке не противоречит
+            if !in_check
+                && !utils::is_capture_or_promotion(best_move)
+                && best > utils::VALUE_LOSS
+                && best < utils::VALUE_WIN
+                && !(bound == transtable::BOUND_LOWER && best <= static_eval)
+                && !(bound == transtable::BOUND_UPPER && best >= static_eval)
+            {
+                t.correction_history
+                    .update(&t.stack[height].position, depth, best - static_eval);
+            }
         }
     }
 
     return Some(best);
 }
 
-fn qs(e: &mut Engine, mut alpha: isize, beta: isize, height: usize) -> Option<isize> {
-    e.stack[height].pv_size = 0;
+fn qs(t: &mut Thread, mut alpha: isize, beta: isize, height: usize) -> Option<isize> {
+    t.stack[height].pv_size = 0;
 
-    if is_repeat(e, height) {
+    if is_repeat(t, height) {
         return Some(utils::VALUE_DRAW);
     }
-    let stack = &e.stack[height];
     if height >= utils::MAX_HEIGHT {
-        return Some(evaluate2(e, height));
+        let raw_eval = evaluate2(t, height);
+        return Some(corrected_eval(t, height, raw_eval));
     }
+    let stack = &t.stack[height];
     if utils::is_draw(&stack.position) {
         return Some(utils::VALUE_DRAW);
     }
 
-    let (_, mut tt_value, tt_bound, tt_move, tt_hit) =
-        e.trans_table.read(e.stack[height].position.key);
+    let (_, mut tt_value, tt_bound, tt_move, tt_eval, tt_hit) =
+        t.trans_table.read(t.stack[height].position.key);
     if tt_hit {
         tt_value = utils::value_from_tt(tt_value, height);
         if tt_bound == transtable::BOUND_EXACT
@@ -464,7 +570,7 @@ fn qs(e: &mut Engine, mut alpha: isize, beta: isize, height: usize) -> Option<is
     }
 
     let in_check = stack.position.is_check();
-    let mut ml = MoveList::new();
+    let moveorder_context = get_moveorder_context(t, height, tt_move);
     let mut best = utils::loss_in(height);
     if best > alpha {
         alpha = best;
@@ -472,13 +578,15 @@ fn qs(e: &mut Engine, mut alpha: isize, beta: isize, height: usize) -> Option<is
             return Some(alpha);
         }
     }
-    if in_check {
-        let moveorder_context = get_moveorder_context(e, height, tt_move);
-        let pos = &stack.position;
-        ml.gen_moves(pos);
-        moveorder_context.evaluate_moves_counter55(&mut ml, pos, &e.history);
+    let mut picker = if in_check {
+        MovePicker::new(moveorder_context)
     } else {
-        let static_eval = evaluate(e.evaluator.as_mut(), &stack.position);
+        let raw_eval = if tt_eval != transtable::EVAL_NONE {
+            tt_eval
+        } else {
+            evaluate(t.evaluator.as_mut(), &mut t.eval_cache, &stack.position)
+        };
+        let static_eval = corrected_eval(t, height, raw_eval);
         best = best.max(static_eval);
         if static_eval > alpha {
             alpha = static_eval;
@@ -486,28 +594,25 @@ fn qs(e: &mut Engine, mut alpha: isize, beta: isize, height: usize) -> Option<is
                 return Some(alpha);
             }
         }
-        ml.gen_captures(&stack.position);
-        moveorder::evaluate_captures(&mut ml);
-    }
-    ml.sort();
+        MovePicker::new_captures(moveorder_context)
+    };
 
-    for item in &ml.moves[..ml.size] {
-        let mv = item.mv;
-        if !in_check && !see::see_ge(&e.stack[height].position, mv, 0) {
+    while let Some(mv) = picker.next_move(&t.stack[height].position, &t.history) {
+        if !in_check && !see::see_ge(&t.stack[height].position, mv, 0) {
             continue;
         }
-        if !make_move(e, mv, height) {
+        if !make_move(t, mv, height) {
             continue;
         }
-        if is_timeout(e) {
+        if is_timeout(t) {
             return None;
         }
-        let score = -(qs(e, -beta, -alpha, height + 1)?);
-        unmake_move(e);
+        let score = -(qs(t, -beta, -alpha, height + 1)?);
+        unmake_move(t);
         best = best.max(score);
         if score > alpha {
             alpha = score;
-            //assign_pv(e, height, mv);
+            //assign_pv(t, height, mv);
             if alpha >= beta {
                 break;
             }
@@ -516,17 +621,17 @@ fn qs(e: &mut Engine, mut alpha: isize, beta: isize, height: usize) -> Option<is
     return Some(best);
 }
 
-fn update_killer(e: &mut Engine, height: usize, mv: Move) {
-    let stack = &mut e.stack[height];
+fn update_killer(t: &mut Thread, height: usize, mv: Move) {
+    let stack = &mut t.stack[height];
     if stack.killer1 != mv {
         stack.killer2 = stack.killer1;
         stack.killer1 = mv;
     }
 }
 
-fn is_repeat(e: &Engine, height: usize) -> bool {
-    let key = e.stack[height].position.key;
-    for item in e.stack[..height].iter().rev() {
+fn is_repeat(t: &Thread, height: usize) -> bool {
+    let key = t.stack[height].position.key;
+    for item in t.stack[..height].iter().rev() {
         let mv = item.current_mv;
         if mv.is_null() || mv.moving_piece() == Piece::PAWN || mv.captured_piece() != Piece::NONE {
             return false;
@@ -535,55 +640,82 @@ fn is_repeat(e: &Engine, height: usize) -> bool {
             return true;
         }
     }
-    return e.repeats.contains(&key);
+    return t.repeats.contains(&key);
 }
 
-fn evaluate(evaluator: &mut dyn IEvaluator, pos: &Position) -> isize {
+fn evaluate(evaluator: &mut dyn IEvaluator, eval_cache: &mut EvalCache, pos: &Position) -> isize {
+    if let Some(res) = eval_cache.read(pos.key) {
+        return res;
+    }
     let mut res = evaluator.quik_evaluate(pos).clamp(-20_000, 20_000);
     if pos.side_to_move == Side::BLACK {
         res = -res;
     }
+    eval_cache.update(pos.key, res);
     return res;
 }
 
-fn evaluate2(e: &mut Engine, height: usize) -> isize {
-    let pos = &e.stack[height].position;
-    let mut res = e.evaluator.quik_evaluate(pos).clamp(-20_000, 20_000);
-    if pos.side_to_move == Side::BLACK {
-        res = -res;
+// Оценка в кеше и в хеш-таблице не зависит от rule50: их ключ его не учитывает.
+// Правило 50 ходов (для NNUE) и поправка по пешечной структуре применяются после.
+fn corrected_eval(t: &Thread, height: usize, raw_eval: isize) -> isize {
+    let pos = &t.stack[height].position;
+    let mut eval = raw_eval;
+    if t.evaluator.rule50_scaling() {
+        eval = utils::scale_rule50(eval, pos);
     }
-    return res;
+    return t.correction_history.correct(pos, eval);
 }
 
-fn is_timeout(e: &mut Engine) -> bool {
-    e.nodes += 1;
-    const CHECK_NODES_MASK: u64 = (1_u64 << 11) - 1;
-    if e.nodes & CHECK_NODES_MASK == 0 {
-        return e.time_manager.check_timeout(e.nodes);
+fn evaluate2(t: &mut Thread, height: usize) -> isize {
+    return evaluate(
+        t.evaluator.as_mut(),
+        &mut t.eval_cache,
+        &t.stack[height].position,
+    );
+}
+
+const CHECK_NODES_MASK: u64 = (1_u64 << 11) - 1;
+
+fn is_timeout(t: &mut Thread) -> bool {
+    t.nodes += 1;
+    if t.nodes & CHECK_NODES_MASK == 0 {
+        // узлы всех потоков, чтобы "go nodes" работал и в Lazy SMP
+        let total = t
+            .total_nodes
+            .fetch_add(CHECK_NODES_MASK + 1, Ordering::Relaxed);
+        return t.time_manager.check_timeout(total + CHECK_NODES_MASK + 1);
     }
     return false;
 }
 
-fn make_move(e: &mut Engine, mv: Move, height: usize) -> bool {
-    let (parent, child) = get_pair_mut(&mut e.stack, height);
+fn nodes_searched(t: &Thread) -> u64 {
+    return t.total_nodes.load(Ordering::Relaxed) + (t.nodes & CHECK_NODES_MASK);
+}
+
+fn make_move(t: &mut Thread, mv: Move, height: usize) -> bool {
+    let (parent, child) = get_pair_mut(&mut t.stack, height);
+    let mut deltas = PieceDeltas::new();
     if mv.is_null() {
         parent.position.make_null_move(&mut child.position);
     } else {
-        if !parent.position.make_move(mv, &mut child.position) {
+        if !parent
+            .position
+            .make_move_with_deltas(mv, &mut child.position, &mut deltas)
+        {
             return false;
         }
     }
-    e.evaluator.make_move(&parent.position, mv);
+    t.evaluator.make_move(&child.position, &deltas);
     parent.current_mv = mv;
     return true;
 }
 
-fn unmake_move(e: &mut Engine) {
-    e.evaluator.unmake_move();
+fn unmake_move(t: &mut Thread) {
+    t.evaluator.unmake_move();
 }
 
-fn assign_pv(e: &mut Engine, height: usize, m: Move) {
-    let (parent, child) = get_pair_mut(&mut e.stack, height);
+fn assign_pv(t: &mut Thread, height: usize, m: Move) {
+    let (parent, child) = get_pair_mut(&mut t.stack, height);
     let child_size = child.pv_size;
     parent.pv_size = 1 + child_size;
     parent.pv[0] = m;
@@ -610,29 +742,31 @@ fn lmr_counter55(d: f64, m: f64) -> f64 {
 }
 
 fn get_moveorder_context(
-    e: &Engine,
+    t: &Thread,
     height: usize,
     trans_move: Move,
 ) -> moveorder::MoveOrderContext {
-    let side = e.stack[height].position.side_to_move;
-    let killer1 = e.stack[height].killer1;
-    let killer2 = e.stack[height].killer2;
-    let counter_move = if height >= 1 {
-        e.stack[height - 1].current_mv
+    let side = t.stack[height].position.side_to_move;
+    let killer1 = t.stack[height].killer1;
+    let killer2 = t.stack[height].killer2;
+    let prev_move = if height >= 1 {
+        t.stack[height - 1].current_mv
     } else {
         Move::NONE
     };
     let follow_move = if height >= 2 {
-        e.stack[height - 2].current_mv
+        t.stack[height - 2].current_mv
     } else {
         Move::NONE
     };
+    let counter_move = t.history.read_counter_move(side, prev_move);
     return moveorder::MoveOrderContext::new(
         side,
         trans_move,
         killer1,
         killer2,
-        counter_move,
+        prev_move,
         follow_move,
+        counter_move,
     );
 }
@@ -46,6 +46,12 @@ fn test_see() {
             mv: "e7e6",
             val: piece_value(Piece::PAWN) - piece_value(Piece::QUEEN),
         },
+        // рокировка записана как ход короля на поле ладьи, которое бьет слон
+        SeeTest {
+            fen: "b3k3/8/8/8/8/8/8/4K2R w K - 0 1",
+            mv: "e1g1",
+            val: 0,
+        },
     ];
     for test in tests.iter() {
         let pos = Position::from_fen(&test.fen);
@@ -71,6 +77,11 @@ pub fn see_ge(pos: &Position, mv: Move, threshold: isize) -> bool {
     let captured_piece = mv.captured_piece();
     let promotion_piece = mv.promotion();
 
+    // to - поле своей ладьи, размена нет
+    if mv.is_castling() {
+        return threshold <= 0;
+    }
+
     let mut next_victim = moving_piece;
     if promotion_piece != Piece::NONE {
         next_victim = promotion_piece;
@@ -1,10 +1,14 @@
 use crate::chess::Side;
-use crate::domain::{CancelToken, LimitsType, SearchInfo};
+use crate::domain::{CancelToken, LimitsType, PonderHitToken, SearchInfo, TournamentLimit};
 use std::time::{Duration, Instant};
 
 pub struct TimeManager {
     cancel: CancelToken,
     start: Instant,
+    side: Side,
+    // опция Ponder: соперник думает, пока думаем мы, можно тратить больше
+    ponder_enabled: bool,
+    ponderhit: Option<PonderHitToken>,
     fixed_nodes: Option<u64>,
     fixed_depth: Option<u32>,
     max_usage: Option<Duration>,
@@ -16,6 +20,9 @@ impl Default for TimeManager {
         TimeManager {
             cancel: CancelToken::new(),
             start: Instant::now(),
+            side: Side::WHITE,
+            ponder_enabled: false,
+            ponderhit: None,
             fixed_nodes: None,
             fixed_depth: None,
             max_usage: None,
@@ -25,59 +32,81 @@ impl Default for TimeManager {
 }
 
 impl TimeManager {
-    pub fn new(limits: LimitsType, cancel: CancelToken, side: Side) -> Self {
+    pub fn new(
+        limits: LimitsType,
+        cancel: CancelToken,
+        side: Side,
+        ponder_enabled: bool,
+        ponderhit: PonderHitToken,
+    ) -> Self {
         let start = Instant::now();
 
-        let tl = limits.tournament;
-        if tl.white_time.is_none() || tl.black_time.is_none() {
+        // до ponderhit ищем как go infinite
+        if limits.ponder {
             return TimeManager {
                 cancel,
                 start,
-                fixed_depth: limits.fixed_depth,
-                fixed_nodes: limits.fixed_nodes,
-                max_usage: limits.fixed_time,
-                ideal_usage: None,
+                side,
+                ponder_enabled,
+                ponderhit: Some(ponderhit),
+                ..Default::default()
             };
         }
 
-        let (main, inc) = if side == Side::WHITE {
-            (tl.white_time.unwrap(), tl.white_increment)
-        } else {
-            (tl.black_time.unwrap(), tl.black_increment)
-        };
-
-        let reserve = (main / 20).clamp(100, 1_000);
-        let main = (main - reserve).max(0);
+        if let Some((max_usage, ideal_usage)) =
+            tournament_usage(&limits.tournament, side, ponder_enabled)
+        {
+            return TimeManager {
+                cancel,
+                start,
+                side,
+                ponder_enabled,
+                ponderhit: None,
+                fixed_depth: None,
+                fixed_nodes: None,
+                max_usage: Some(max_usage),
+                ideal_usage: Some(ideal_usage),
+            };
+        }
 
-        let (max_usage, ideal_usage) = if let Some(moves) = tl.moves {
-            let mut moves = moves as u64;
-            if moves > 1 {
-                //moves = moves + 5;
-                moves = moves + (moves * 4 / 10).max(5);
-                moves = moves.min(50);
-            }
-            let ideal_usage = main / moves + inc;
-            let max_usage = 5 * main / moves + inc;
-            (max_usage, ideal_usage)
-        } else {
-            let ideal_usage = main / 50 + inc;
-            let max_usage = main / 10 + inc;
-            (max_usage, ideal_usage)
+        return TimeManager {
+            cancel,
+            start,
+            side,
+            ponder_enabled,
+            ponderhit: None,
+            fixed_depth: limits.fixed_depth,
+            fixed_nodes: limits.fixed_nodes,
+            max_usage: limits.fixed_time,
+            ideal_usage: None,
         };
-        let ideal_usage = ideal_usage.clamp(0, main);
-        let max_usage = max_usage.clamp(0, main);
+    }
 
+    // вспомогательные потоки Lazy SMP останавливает главный поток
+    pub fn helper(cancel: CancelToken) -> Self {
         return TimeManager {
             cancel,
-            start,
-            fixed_depth: None,
-            fixed_nodes: None,
-            max_usage: Some(Duration::from_millis(max_usage)),
-            ideal_usage: Some(Duration::from_millis(ideal_usage)),
+            ..Default::default()
         };
     }
 
-    pub fn check_timeout(&self, nodes: u64) -> bool {
+    // Время, потраченное на ponder, засчитывается: отсчет идет от начала поиска.
+    fn check_ponderhit(&mut self) {
+        if let Some(ponderhit) = &self.ponderhit {
+            if let Some(tl) = ponderhit.limits() {
+                if let Some((max_usage, ideal_usage)) =
+                    tournament_usage(&tl, self.side, self.ponder_enabled)
+                {
+                    self.max_usage = Some(max_usage);
+                    self.ideal_usage = Some(ideal_usage);
+                }
+                self.ponderhit = None;
+            }
+        }
+    }
+
+    pub fn check_timeout(&mut self, nodes: u64) -> bool {
+        self.check_ponderhit();
         if let Some(max_usage) = self.max_usage {
             if self.start.elapsed() >= max_usage {
                 self.cancel.cancel();
@@ -96,6 +125,7 @@ impl TimeManager {
     }
 
     pub fn iteration_complete(&mut self, si: &SearchInfo) {
+        self.check_ponderhit();
         if let Some(ideal_usage) = self.ideal_usage {
             if self.start.elapsed() >= ideal_usage {
                 self.cancel.cancel();
@@ -108,3 +138,115 @@ impl TimeManager {
         }
     }
 }
+
+// (max_usage, ideal_usage)
+fn tournament_usage(
+    tl: &TournamentLimit,
+    side: Side,
+    ponder_enabled: bool,
+) -> Option<(Duration, Duration)> {
+    if tl.white_time.is_none() || tl.black_time.is_none() {
+        return None;
+    }
+
+    let (main, inc) = if side == Side::WHITE {
+        (tl.white_time.unwrap(), tl.white_increment)
+    } else {
+        (tl.black_time.unwrap(), tl.black_increment)
+    };
+
+    let reserve = (main / 20).clamp(100, 1_000);
+    let main = main.saturating_sub(reserve);
+
+    let (max_usage, ideal_usage) = if let Some(moves) = tl.moves {
+        let mut moves = moves as u64;
+        if moves > 1 {
+            //moves = moves + 5;
+            moves = moves + (moves * 4 / 10).max(5);
+            moves = moves.min(50);
+        }
+        let ideal_usage = main / moves + inc;
+        let max_usage = 5 * main / moves + inc;
+        (max_usage, ideal_usage)
+    } else {
+        let ideal_usage = main / 50 + inc;
+        let max_usage = main / 10 + inc;
+        (max_usage, ideal_usage)
+    };
+    let ideal_usage = if ponder_enabled {
+        ideal_usage + ideal_usage / 4
+    } else {
+        ideal_usage
+    };
+    let ideal_usage = ideal_usage.clamp(0, main);
+    let max_usage = max_usage.clamp(0, main);
+
+    return Some((
+        Duration::from_millis(max_usage),
+        Duration::from_millis(ideal_usage),
+    ));
+}
+
+#[test]
+fn test_ponderhit() {
+    let ponderhit = PonderHitToken::new();
+    let limits = LimitsType {
+        ponder: true,
+        ..Default::default()
+    };
+    let mut tm = TimeManager::new(
+        limits,
+        CancelToken::new(),
+        Side::BLACK,
+        false,
+        ponderhit.clone(),
+    );
+    // до ponderhit ограничений нет
+    assert!(!tm.check_timeout(0));
+    assert!(tm.max_usage.is_none() && tm.ideal_usage.is_none());
+
+    let waiter = {
+        let ponderhit = ponderhit.clone();
+        std::thread::spawn(move || ponderhit.wait())
+    };
+    let tl = TournamentLimit {
+        white_time: Some(1_000),
+        black_time: Some(60_000),
+        black_increment: 500,
+        ..Default::default()
+    };
+    ponderhit.ponderhit(tl.clone());
+    waiter.join().unwrap();
+
+    // лимит за черных, как у go с теми же часами: резерв 1 секунда,
+    // max = 59000 / 10 + 500, ideal = 59000 / 50 + 500
+    assert!(!tm.check_timeout(0));
+    assert!(tm.ponderhit.is_none());
+    assert_eq!(tm.max_usage, Some(Duration::from_millis(6_400)));
+    assert_eq!(tm.ideal_usage, Some(Duration::from_millis(1_680)));
+    // время ponder засчитывается
+    tm.start -= Duration::from_millis(6_400);
+    assert!(tm.check_timeout(0));
+
+    // с опцией Ponder идеальное время на четверть больше
+    assert_eq!(
+        tournament_usage(&tl, Side::BLACK, true),
+        Some((Duration::from_millis(6_400), Duration::from_millis(2_100)))
+    );
+    // резерв больше оставшегося времени
+    let tl = TournamentLimit {
+        white_time: Some(50),
+        black_time: Some(50),
+        ..Default::default()
+    };
+    assert_eq!(
+        tournament_usage(&tl, Side::WHITE, false),
+        Some((Duration::ZERO, Duration::ZERO))
+    );
+
+    // stop будит ожидание ponderhit
+    let ponderhit = PonderHitToken::new();
+    ponderhit.stop();
+    ponderhit.wait();
+    assert!(ponderhit.limits().is_none());
+}
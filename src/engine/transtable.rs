@@ -1,31 +1,173 @@
 use crate::chess::Move;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 
 pub const BOUND_LOWER: usize = 1;
 pub const BOUND_UPPER: usize = 2;
 pub const BOUND_EXACT: usize = BOUND_LOWER | BOUND_UPPER;
+// Статическая оценка не записана (результат из эндшпильных таблиц)
+pub const EVAL_NONE: isize = i16::MIN as isize;
 
+#[test]
+fn test_trans_table_eval() {
+    let tt = TransTable::new(1);
+    let key = 0x1234_5678_9ABC_DEF0;
+    tt.update_old_policy(key, 5, -120, BOUND_LOWER, Move::NONE, -345);
+    let (depth, score, bound, _, eval, ok) = tt.read(key);
+    assert!(ok);
+    assert_eq!((depth, score, bound, eval), (5, -120, BOUND_LOWER, -345));
+
+    tt.update_old_policy(key, 6, 10, BOUND_EXACT, Move::NONE, EVAL_NONE);
+    assert_eq!(tt.read(key).4, EVAL_NONE);
+    // другой ключ в той же корзине
+    assert!(!tt.read(key ^ (1 << 50)).5);
+}
+
+#[test]
+fn test_trans_table_buckets() {
+    let tt = TransTable::new(1);
+    tt.inc_date();
+    // позиции одной корзины с разными старшими битами ключа
+    let key = |i: u64| 0x0000_5678_9ABC_DEF0 | (i << KEY_SHIFT);
+    for i in 0..BUCKET_SIZE as u64 {
+        tt.update_old_policy(key(i), 10 + i as isize, 0, BOUND_EXACT, Move::NONE, 0);
+    }
+    assert!((0..BUCKET_SIZE as u64).all(|i| tt.read(key(i)).5));
+    // вытесняется самая мелкая запись
+    tt.update_old_policy(key(100), 1, 0, BOUND_EXACT, Move::NONE, 0);
+    assert!(!tt.read(key(0)).5);
+    assert!(tt.read(key(1)).5 && tt.read(key(100)).5);
+
+    // запись прошлого поиска уступает место, даже если она глубже
+    tt.inc_date();
+    for i in 1..BUCKET_SIZE as u64 {
+        tt.read(key(i));
+    }
+    tt.update_old_policy(key(200), 1, 0, BOUND_EXACT, Move::NONE, 0);
+    assert!(!tt.read(key(100)).5 && tt.read(key(200)).5);
+
+    // повреждённая запись не читается (key(200) заняла место key(0) и key(100))
+    let entry = &tt.bucket(key(200)).entries[0];
+    let data = entry.data.load(Ordering::Relaxed);
+    entry.data.store(data ^ (1 << 30), Ordering::Relaxed);
+    assert!(!tt.read(key(200)).5);
+
+    // по одной записи в каждой корзине: занята четверть
+    tt.clear();
+    for i in 0..1_000 {
+        tt.update_old_policy(i, 1, 0, BOUND_EXACT, Move::NONE, 0);
+    }
+    assert_eq!(tt.hashfull(), 1000 / BUCKET_SIZE);
+}
+
+#[test]
+fn test_trans_table_file() {
+    let tt = TransTable::new(1);
+    tt.inc_date();
+    for i in 0..1_000 {
+        tt.update_old_policy(
+            i * 7919,
+            1 + i as isize % 20,
+            i as isize,
+            BOUND_LOWER,
+            Move::NONE,
+            -(i as isize),
+        );
+    }
+    let path = std::env::temp_dir().join(format!("counter-tt-{}.bin", std::process::id()));
+    tt.save_file(&path).unwrap();
+    let loaded = TransTable::load_file(&path);
+    let mut bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.size(), 1);
+    assert_eq!(loaded.current_date(), tt.current_date());
+    for i in 0..1_000 {
+        assert_eq!(loaded.read(i * 7919), tt.read(i * 7919));
+    }
+
+    // файл от другой упаковки записей
+    bytes[4] = (FILE_LAYOUT + 1) as u8;
+    let path = std::env::temp_dir().join(format!("counter-tt-layout-{}.bin", std::process::id()));
+    std::fs::write(&path, &bytes).unwrap();
+    let result = TransTable::load_file(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        result,
+        Err(HashFileError::IncompatibleLayout { .. })
+    ));
+}
+
+// Таблица общая для всех потоков поиска, блокировок нет.
+// Корзина занимает строку кеша: 4 записи по 16 байт.
+// Запись хранится как (data ^ key16 ^ eval, data): в первом слове старшие 16 бит
+// ключа, статическая оценка в младших 16 битах и нули между ними; если слова
+// записаны разными потоками, нули не сойдутся и запись не пройдёт проверку.
+// Младшие биты ключа задают номер корзины.
 pub struct TransTable {
     megabytes: usize,
-    entries: Vec<TransEntry>,
-    date: u16,
+    buckets: Vec<Bucket>,
+    date: AtomicU16,
+}
+
+const BUCKET_SIZE: usize = 4;
+const KEY_SHIFT: u32 = 48;
+const CHECK_MASK: u64 = 0x0000_FFFF_FFFF_0000;
+const DATE_MASK: u16 = 0x3FFF;
+
+#[derive(Default)]
+#[repr(align(64))]
+struct Bucket {
+    entries: [TransEntry; BUCKET_SIZE],
 }
 
 #[derive(Default)]
 struct TransEntry {
-    key: u32,
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+// bound у записанной позиции не нулевой, поэтому data == 0 - пустая запись
+#[derive(Default)]
+struct EntryData {
     mv: Move,
     date: u16,
     score: i16,
     depth: i8,
     bound: u8,
+    eval: i16,
+}
+
+impl EntryData {
+    fn pack(&self) -> u64 {
+        return (self.mv.raw() as u64 & 0xFF_FFFF)
+            ^ ((self.depth as u8 as u64) << 24)
+            ^ ((self.score as u16 as u64) << 32)
+            ^ ((self.bound as u64 & 3) << 48)
+            ^ (((self.date & DATE_MASK) as u64) << 50);
+    }
+
+    fn unpack(data: u64) -> EntryData {
+        return EntryData {
+            mv: Move::from_raw((data & 0xFF_FFFF) as u32),
+            depth: (data >> 24) as u8 as i8,
+            score: (data >> 32) as u16 as i16,
+            bound: ((data >> 48) & 3) as u8,
+            date: ((data >> 50) as u16) & DATE_MASK,
+            eval: EVAL_NONE as i16,
+        };
+    }
 }
 
 impl TransTable {
     pub fn new(megabytes: usize) -> Self {
         let mut tt = TransTable {
             megabytes: 0,
-            entries: Vec::new(),
-            date: 0,
+            buckets: Vec::new(),
+            date: AtomicU16::new(0),
         };
         tt.resize(megabytes);
         return tt;
@@ -36,101 +178,327 @@ impl TransTable {
     }
 
     pub fn resize(&mut self, megabytes: usize) {
-        let size = (1_usize << 20) * megabytes / std::mem::size_of::<TransEntry>();
-        if size == self.entries.len() {
+        let size = (1_usize << 20) * megabytes / std::mem::size_of::<Bucket>();
+        if size == self.buckets.len() {
             return;
         }
-        self.entries = Vec::new(); // clear large object in heap
+        self.buckets = Vec::new(); // clear large object in heap
         eprintln!("init trans table {megabytes}");
         let mut table = Vec::with_capacity(size);
         for _ in 0..size {
-            table.push(TransEntry::default());
+            table.push(Bucket::default());
         }
         self.megabytes = megabytes;
-        self.entries = table;
+        self.buckets = table;
     }
 
-    pub fn inc_date(&mut self) {
-        self.date += 1;
+    pub fn inc_date(&self) {
+        self.date.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn clear(&mut self) {
-        self.date = 0;
-        self.entries.fill_with(Default::default);
+    pub fn clear(&self) {
+        self.date.store(0, Ordering::Relaxed);
+        for entry in self.buckets.iter().flat_map(|b| &b.entries) {
+            entry.key.store(0, Ordering::Relaxed);
+            entry.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // Заполнение в промилле по первым 1000 записям: сколько из них записано
+    // или прочитано в текущем поиске
+    pub fn hashfull(&self) -> usize {
+        let date = self.current_date();
+        let buckets = &self.buckets[..self.buckets.len().min(1000 / BUCKET_SIZE)];
+        let used = buckets
+            .iter()
+            .flat_map(|b| &b.entries)
+            .filter(|entry| {
+                let data = entry.data.load(Ordering::Relaxed);
+                data != 0 && EntryData::unpack(data).date == date
+            })
+            .count();
+        return used * 1000 / (buckets.len() * BUCKET_SIZE).max(1);
+    }
+
+    fn current_date(&self) -> u16 {
+        return self.date.load(Ordering::Relaxed) & DATE_MASK;
+    }
+
+    fn bucket(&self, key: u64) -> &Bucket {
+        let index = key % (self.buckets.len() as u64);
+        return &self.buckets[index as usize];
+    }
+
+    // None, если ключ не совпал или запись повреждена другим потоком
+    fn load(entry: &TransEntry, key: u64) -> Option<EntryData> {
+        let data = entry.data.load(Ordering::Relaxed);
+        if data == 0 {
+            return None;
+        }
+        let check = entry.key.load(Ordering::Relaxed) ^ data;
+        if check & CHECK_MASK != 0 || check >> KEY_SHIFT != key >> KEY_SHIFT {
+            return None;
+        }
+        let mut item = EntryData::unpack(data);
+        item.eval = check as u16 as i16;
+        return Some(item);
+    }
+
+    fn store(entry: &TransEntry, key: u64, item: &EntryData) {
+        let data = item.pack();
+        let check = ((key >> KEY_SHIFT) << KEY_SHIFT) | (item.eval as u16 as u64);
+        entry.key.store(data ^ check, Ordering::Relaxed);
+        entry.data.store(data, Ordering::Relaxed);
+    }
+
+    fn find(bucket: &Bucket, key: u64) -> Option<(&TransEntry, EntryData)> {
+        for entry in &bucket.entries {
+            if let Some(item) = Self::load(entry, key) {
+                return Some((entry, item));
+            }
+        }
+        return None;
     }
 
-    //(depth, score, bound, move, ok)
-    pub fn read(&mut self, key: u64) -> (isize, isize, usize, Move, bool) {
-        let index = key % (self.entries.len() as u64);
-        let entry = &mut self.entries[index as usize];
-        if entry.key == (key >> 32) as u32 {
-            entry.date = self.date;
+    // Место для новой позиции: пустая запись, иначе запись с наименьшей
+    // глубиной, каждый поиск назад уменьшает глубину на 8
+    fn replacement<'a>(&self, bucket: &'a Bucket) -> &'a TransEntry {
+        let date = self.current_date();
+        let mut result = &bucket.entries[0];
+        let mut min_value = isize::MAX;
+        for entry in &bucket.entries {
+            let data = entry.data.load(Ordering::Relaxed);
+            if data == 0 {
+                return entry;
+            }
+            let item = EntryData::unpack(data);
+            let age = (date.wrapping_sub(item.date) & DATE_MASK) as isize;
+            let value = item.depth as isize - 8 * age;
+            if value < min_value {
+                min_value = value;
+                result = entry;
+            }
+        }
+        return result;
+    }
+
+    //(depth, score, bound, move, static eval, ok)
+    pub fn read(&self, key: u64) -> (isize, isize, usize, Move, isize, bool) {
+        if let Some((entry, mut item)) = Self::find(self.bucket(key), key) {
+            let date = self.current_date();
+            if item.date != date {
+                item.date = date;
+                Self::store(entry, key, &item);
+            }
             return (
-                entry.depth as isize,
-                entry.score as isize,
-                entry.bound as usize,
-                entry.mv,
+                item.depth as isize,
+                item.score as isize,
+                item.bound as usize,
+                item.mv,
+                item.eval as isize,
                 true,
             );
         } else {
-            return (0, 0, 0, Move::default(), false);
+            return (0, 0, 0, Move::default(), EVAL_NONE, false);
         }
     }
 
-    pub fn update(&mut self, key: u64, depth: isize, score: isize, bound: usize, mv: Move) {
-        let index = key % (self.entries.len() as u64);
-        let entry = &mut self.entries[index as usize];
-        let existing = entry.key == (key >> 32) as u32;
-        if existing {
-            if !(depth >= (entry.depth as isize) - 3 || bound == BOUND_EXACT) {
-                if mv != Move::NONE
-                    && !(entry.mv != Move::NONE && (entry.bound as usize & BOUND_LOWER) != 0)
-                {
-                    entry.mv = mv;
+    pub fn update(
+        &self,
+        key: u64,
+        depth: isize,
+        score: isize,
+        bound: usize,
+        mv: Move,
+        eval: isize,
+    ) {
+        let bucket = self.bucket(key);
+        let (entry, mut item) = match Self::find(bucket, key) {
+            Some((entry, mut item)) => {
+                if !(depth >= (item.depth as isize) - 3 || bound == BOUND_EXACT) {
+                    if mv != Move::NONE
+                        && !(item.mv != Move::NONE && (item.bound as usize & BOUND_LOWER) != 0)
+                    {
+                        item.mv = mv;
+                        Self::store(entry, key, &item);
+                    }
+                    return;
                 }
-                return;
-            }
-            if mv != Move::NONE {
-                entry.mv = mv;
-            }
-        } else {
-            if !(entry.date != self.date || depth >= entry.depth as isize) {
-                return;
+                if mv != Move::NONE {
+                    item.mv = mv;
+                }
+                (entry, item)
             }
-            entry.key = (key >> 32) as u32;
-            entry.mv = mv;
-        }
-        entry.date = self.date;
-        entry.depth = depth as i8;
-        entry.score = score as i16;
-        entry.bound = bound as u8;
+            None => (
+                self.replacement(bucket),
+                EntryData {
+                    mv: mv,
+                    ..Default::default()
+                },
+            ),
+        };
+        item.date = self.current_date();
+        item.depth = depth as i8;
+        item.score = score as i16;
+        item.bound = bound as u8;
+        item.eval = eval as i16;
+        Self::store(entry, key, &item);
     }
 
     pub fn update_old_policy(
-        &mut self,
+        &self,
         key: u64,
         depth: isize,
         score: isize,
         bound: usize,
         mv: Move,
+        eval: isize,
     ) {
-        let index = key % (self.entries.len() as u64);
-        let entry = &mut self.entries[index as usize];
-        let existing = entry.key == (key >> 32) as u32;
-        if existing {
-            if !(depth >= (entry.depth as isize) - 3 || bound == BOUND_EXACT) {
-                return;
+        let bucket = self.bucket(key);
+        let entry = match Self::find(bucket, key) {
+            Some((entry, item)) => {
+                if !(depth >= (item.depth as isize) - 3 || bound == BOUND_EXACT) {
+                    return;
+                }
+                entry
             }
-        } else {
-            if !(entry.date != self.date || depth >= entry.depth as isize) {
-                return;
+            None => self.replacement(bucket),
+        };
+        let item = EntryData {
+            mv: mv,
+            date: self.current_date(),
+            score: score as i16,
+            depth: depth as i8,
+            bound: bound as u8,
+            eval: eval as i16,
+        };
+        Self::store(entry, key, &item);
+    }
+}
+
+// Файл таблицы, поля заголовка little-endian:
+// magic[4], layout, bucket_size, entry_size, megabytes (u64), date;
+// затем слова key и data всех записей по порядку.
+// LAYOUT меняется вместе с упаковкой записи (EntryData, проверка ключа).
+const FILE_MAGIC: [u8; 4] = *b"CTTH";
+const FILE_LAYOUT: u32 = 1;
+const FILE_HEADER_SIZE: usize = 28;
+const BUCKET_BYTES: u64 = std::mem::size_of::<Bucket>() as u64;
+
+#[derive(Debug)]
+pub enum HashFileError {
+    Io(io::Error),
+    BadMagic,
+    IncompatibleLayout {
+        layout: u32,
+        bucket_size: u32,
+        entry_size: u32,
+    },
+    BadLength {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for HashFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashFileError::Io(err) => write!(f, "{}", err),
+            HashFileError::BadMagic => write!(f, "not a hash file"),
+            HashFileError::IncompatibleLayout {
+                layout,
+                bucket_size,
+                entry_size,
+            } => write!(
+                f,
+                "hash file layout {} ({}x{} bytes) does not match {} ({}x{} bytes)",
+                layout,
+                bucket_size,
+                entry_size,
+                FILE_LAYOUT,
+                BUCKET_SIZE,
+                std::mem::size_of::<TransEntry>()
+            ),
+            HashFileError::BadLength { expected, actual } => {
+                write!(f, "bad hash file size {} expected {}", actual, expected)
             }
         }
-        entry.date = self.date;
-        entry.key = (key >> 32) as u32;
-        entry.depth = depth as i8;
-        entry.score = score as i16;
-        entry.bound = bound as u8;
-        entry.mv = mv;
+    }
+}
+
+impl std::error::Error for HashFileError {}
+
+impl From<io::Error> for HashFileError {
+    fn from(err: io::Error) -> Self {
+        return HashFileError::Io(err);
+    }
+}
+
+impl TransTable {
+    pub fn save_file(&self, path: &Path) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        file.write_all(&FILE_MAGIC)?;
+        for x in [
+            FILE_LAYOUT,
+            BUCKET_SIZE as u32,
+            std::mem::size_of::<TransEntry>() as u32,
+        ] {
+            file.write_all(&x.to_le_bytes())?;
+        }
+        file.write_all(&(self.megabytes as u64).to_le_bytes())?;
+        file.write_all(&(self.date.load(Ordering::Relaxed) as u32).to_le_bytes())?;
+        for entry in self.buckets.iter().flat_map(|b| &b.entries) {
+            file.write_all(&entry.key.load(Ordering::Relaxed).to_le_bytes())?;
+            file.write_all(&entry.data.load(Ordering::Relaxed).to_le_bytes())?;
+        }
+        return file.flush();
+    }
+
+    // Новая таблица того размера, что записан в файле
+    pub fn load_file(path: &Path) -> Result<TransTable, HashFileError> {
+        let mut file = File::open(path)?;
+        let actual = file.metadata()?.len();
+        let mut header = [0_u8; FILE_HEADER_SIZE];
+        if actual < FILE_HEADER_SIZE as u64 {
+            return Err(HashFileError::BadMagic);
+        }
+        file.read_exact(&mut header)?;
+        if header[..4] != FILE_MAGIC {
+            return Err(HashFileError::BadMagic);
+        }
+        let field = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let (layout, bucket_size, entry_size) = (field(4), field(8), field(12));
+        if layout != FILE_LAYOUT
+            || bucket_size as usize != BUCKET_SIZE
+            || entry_size as usize != std::mem::size_of::<TransEntry>()
+        {
+            return Err(HashFileError::IncompatibleLayout {
+                layout: layout,
+                bucket_size: bucket_size,
+                entry_size: entry_size,
+            });
+        }
+        let megabytes = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let expected = FILE_HEADER_SIZE as u64 + (megabytes << 20) / BUCKET_BYTES * BUCKET_BYTES;
+        if actual != expected {
+            return Err(HashFileError::BadLength {
+                expected: expected,
+                actual: actual,
+            });
+        }
+
+        let tt = TransTable::new(megabytes as usize);
+        tt.date.store(field(24) as u16, Ordering::Relaxed);
+        let mut reader = io::BufReader::new(file);
+        let mut word = [0_u8; 8];
+        for entry in tt.buckets.iter().flat_map(|b| &b.entries) {
+            reader.read_exact(&mut word)?;
+            entry.key.store(u64::from_le_bytes(word), Ordering::Relaxed);
+            reader.read_exact(&mut word)?;
+            entry
+                .data
+                .store(u64::from_le_bytes(word), Ordering::Relaxed);
+        }
+        return Ok(tt);
     }
 }
@@ -8,6 +8,9 @@ pub const VALUE_MATE: isize = 30_000;
 pub const VALUE_INFINITY: isize = VALUE_MATE + 1;
 pub const VALUE_WIN: isize = VALUE_MATE - 2 * MAX_HEIGHT as isize;
 pub const VALUE_LOSS: isize = -VALUE_WIN;
+// выигрыш по эндшпильной таблице: выше любой оценки, но ниже мата
+pub const VALUE_TB_WIN: isize = VALUE_WIN - 1 - MAX_HEIGHT as isize;
+pub const VALUE_TB_LOSS: isize = -VALUE_TB_WIN;
 
 pub fn win_in(height: usize) -> isize {
     return VALUE_MATE - height as isize;
@@ -17,6 +20,14 @@ pub fn loss_in(height: usize) -> isize {
     return -VALUE_MATE + height as isize;
 }
 
+pub fn tb_win_in(height: usize) -> isize {
+    return VALUE_TB_WIN - height as isize;
+}
+
+pub fn tb_loss_in(height: usize) -> isize {
+    return VALUE_TB_LOSS + height as isize;
+}
+
 pub fn value_to_tt(v: isize, height: usize) -> isize {
     if v >= VALUE_WIN {
         return v + height as isize;
@@ -37,6 +48,11 @@ pub fn value_from_tt(v: isize, height: usize) -> isize {
     return v;
 }
 
+// Оценка стремится к нулю по мере приближения к правилу 50 ходов
+pub fn scale_rule50(eval: isize, p: &Position) -> isize {
+    return eval * (200 - p.rule50) / 200;
+}
+
 pub fn is_draw(p: &Position) -> bool {
     if p.rule50 > 100 {
         return true;
@@ -59,6 +75,14 @@ pub fn make_uci_score(v: isize) -> UciScore {
     }
 }
 
+pub fn uci_score_value(score: UciScore) -> isize {
+    match score {
+        UciScore::Mate(x) if x > 0 => VALUE_MATE - 2 * x + 1,
+        UciScore::Mate(x) => -VALUE_MATE - 2 * x,
+        UciScore::Centipawns(x) => x,
+    }
+}
+
 pub fn is_capture_or_promotion(mv: Move) -> bool {
     return mv.captured_piece() != Piece::NONE || mv.promotion() != Piece::NONE;
 }
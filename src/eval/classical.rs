@@ -0,0 +1,304 @@
+use super::TunableEvaluator;
+use crate::chess::{Piece, PieceDeltas, Position, Side, Square, bitboard};
+use crate::domain::IEvaluator;
+
+// Классическая оценка без сети: сумма параметров (middlegame, endgame),
+// каждый умножается на число срабатываний признака (белые со знаком +, черные -),
+// затем интерполяция по стадии игры.
+// Признаки собирает collect_features, веса подбирает `counter tune classical`.
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Score {
+    pub mg: isize,
+    pub eg: isize,
+}
+
+const fn s(mg: isize, eg: isize) -> Score {
+    return Score { mg: mg, eg: eg };
+}
+
+// Индексы параметров
+pub const MATERIAL: usize = 0; // пешка..ферзь
+pub const PST: usize = MATERIAL + 5; // пешка..король, поле с точки зрения своей стороны
+pub const KNIGHT_MOBILITY: usize = PST + 6 * 64;
+pub const BISHOP_MOBILITY: usize = KNIGHT_MOBILITY + 9;
+pub const ROOK_MOBILITY: usize = BISHOP_MOBILITY + 14;
+pub const QUEEN_MOBILITY: usize = ROOK_MOBILITY + 15;
+pub const DOUBLED_PAWN: usize = QUEEN_MOBILITY + 28;
+pub const ISOLATED_PAWN: usize = DOUBLED_PAWN + 1;
+pub const PASSED_PAWN: usize = ISOLATED_PAWN + 1; // по рангу пешки
+pub const KING_ATTACK: usize = PASSED_PAWN + 8; // конь, слон, ладья, ферзь
+pub const PAWN_SHIELD: usize = KING_ATTACK + 4;
+pub const BISHOP_PAIR: usize = PAWN_SHIELD + 1;
+pub const ROOK_OPEN_FILE: usize = BISHOP_PAIR + 1;
+pub const ROOK_SEMIOPEN_FILE: usize = ROOK_OPEN_FILE + 1;
+pub const PARAMS_SIZE: usize = ROOK_SEMIOPEN_FILE + 1;
+
+// Полная стадия: все фигуры на доске
+pub const MAX_PHASE: isize = 24;
+
+pub struct ClassicalEvaluationService {
+    params: Vec<Score>,
+}
+
+impl ClassicalEvaluationService {
+    pub fn new() -> Self {
+        return ClassicalEvaluationService::with_params(default_params());
+    }
+
+    pub fn with_params(params: Vec<Score>) -> Self {
+        debug_assert!(params.len() == PARAMS_SIZE);
+        return ClassicalEvaluationService { params: params };
+    }
+}
+
+#[allow(unused_variables)]
+impl IEvaluator for ClassicalEvaluationService {
+    fn box_clone(&self) -> Box<dyn IEvaluator> {
+        return Box::new(ClassicalEvaluationService::with_params(self.params.clone()));
+    }
+    fn init(&mut self, pos: &Position) {}
+    fn make_move(&mut self, pos: &Position, deltas: &PieceDeltas) {}
+    fn unmake_move(&mut self) {}
+    fn quik_evaluate(&mut self, pos: &Position) -> isize {
+        let mut total = Score::default();
+        collect_features(pos, &mut |index, count| {
+            total.mg += self.params[index].mg * count;
+            total.eg += self.params[index].eg * count;
+        });
+        let phase = phase(pos);
+        return (total.mg * phase + total.eg * (MAX_PHASE - phase)) / MAX_PHASE;
+    }
+}
+
+impl TunableEvaluator for ClassicalEvaluationService {
+    fn params(&self) -> &[Score] {
+        return &self.params;
+    }
+    fn set_params(&mut self, params: Vec<Score>) {
+        debug_assert!(params.len() == PARAMS_SIZE);
+        self.params = params;
+    }
+    fn features(&self, pos: &Position, add: &mut dyn FnMut(usize, isize)) {
+        collect_features(pos, &mut |index, count| add(index, count));
+    }
+}
+
+pub fn phase(pos: &Position) -> isize {
+    let phase = bitboard::pop_count(pos.knights | pos.bishops)
+        + 2 * bitboard::pop_count(pos.rooks)
+        + 4 * bitboard::pop_count(pos.queens);
+    return phase.min(MAX_PHASE);
+}
+
+// Вызывает add(индекс параметра, число срабатываний с точки зрения белых)
+pub fn collect_features(pos: &Position, add: &mut impl FnMut(usize, isize)) {
+    let all = pos.all_pieces();
+    let pawn_attacks = [
+        bitboard::up_left(pos.pawns & pos.white) | bitboard::up_right(pos.pawns & pos.white),
+        bitboard::down_left(pos.pawns & pos.black) | bitboard::down_right(pos.pawns & pos.black),
+    ];
+
+    for side in [Side::WHITE, Side::BLACK] {
+        let sign = if side == Side::WHITE { 1 } else { -1 };
+        let own = pos.colours(side);
+        let opp = pos.colours(side.opp());
+        let own_pawns = pos.pawns & own;
+        let opp_pawns = pos.pawns & opp;
+        let relative = |sq: Square| if side == Side::WHITE { sq } else { sq.flip() };
+
+        let mut bb = own;
+        while bb != 0 {
+            let sq = bitboard::first_one(bb);
+            bb &= bb - 1;
+            let piece = pos.piece_on_square(sq);
+            if piece != Piece::KING {
+                add(MATERIAL + piece as usize - 1, sign);
+            }
+            add(PST + (piece as usize - 1) * 64 + relative(sq).index(), sign);
+        }
+
+        // подвижность: поля без своих фигур и не под боем пешек соперника
+        let mobility_area = !own & !pawn_attacks[side.opp().index()];
+        let opp_king_zone = {
+            let king = pos.king_sq(side.opp());
+            bitboard::king_attacks(king) | king.to_bitboard()
+        };
+        for (piece, mobility, attack) in [
+            (Piece::KNIGHT, KNIGHT_MOBILITY, KING_ATTACK),
+            (Piece::BISHOP, BISHOP_MOBILITY, KING_ATTACK + 1),
+            (Piece::ROOK, ROOK_MOBILITY, KING_ATTACK + 2),
+            (Piece::QUEEN, QUEEN_MOBILITY, KING_ATTACK + 3),
+        ] {
+            let mut bb = pieces(pos, piece) & own;
+            while bb != 0 {
+                let sq = bitboard::first_one(bb);
+                bb &= bb - 1;
+                let attacks = match piece {
+                    Piece::KNIGHT => bitboard::knight_attacks(sq),
+                    Piece::BISHOP => bitboard::bishop_attacks(sq, all),
+                    Piece::ROOK => bitboard::rook_attacks(sq, all),
+                    _ => bitboard::queen_attacks(sq, all),
+                };
+                add(
+                    mobility + bitboard::pop_count(attacks & mobility_area) as usize,
+                    sign,
+                );
+                add(attack, sign * bitboard::pop_count(attacks & opp_king_zone));
+
+                if piece == Piece::ROOK {
+                    let file = bitboard::FILEMASK[sq.file() as usize];
+                    if file & pos.pawns == 0 {
+                        add(ROOK_OPEN_FILE, sign);
+                    } else if file & own_pawns == 0 {
+                        add(ROOK_SEMIOPEN_FILE, sign);
+                    }
+                }
+            }
+        }
+
+        if bitboard::multiple(pos.bishops & own) {
+            add(BISHOP_PAIR, sign);
+        }
+
+        // пешечная структура
+        for file in 0..8 {
+            let count = bitboard::pop_count(own_pawns & bitboard::FILEMASK[file]);
+            if count > 1 {
+                add(DOUBLED_PAWN, sign * (count - 1));
+            }
+        }
+        let mut bb = own_pawns;
+        while bb != 0 {
+            let sq = bitboard::first_one(bb);
+            bb &= bb - 1;
+            let file = bitboard::FILEMASK[sq.file() as usize];
+            let adjacent = bitboard::left(file) | bitboard::right(file);
+            if own_pawns & adjacent == 0 {
+                add(ISOLATED_PAWN, sign);
+            }
+            let front = if side == Side::WHITE {
+                !((1_u64 << (8 * (sq.rank() as usize + 1))) - 1)
+            } else {
+                (1_u64 << (8 * sq.rank() as usize)) - 1
+            };
+            if opp_pawns & front & (file | adjacent) == 0 {
+                add(PASSED_PAWN + relative(sq).rank() as usize, sign);
+            }
+        }
+
+        // пешки перед своим королем
+        let king = pos.king_sq(side).to_bitboard();
+        let king_files = king | bitboard::left(king) | bitboard::right(king);
+        let shield = if side == Side::WHITE {
+            bitboard::up(king_files) | bitboard::up(bitboard::up(king_files))
+        } else {
+            bitboard::down(king_files) | bitboard::down(bitboard::down(king_files))
+        };
+        add(PAWN_SHIELD, sign * bitboard::pop_count(own_pawns & shield));
+    }
+}
+
+fn pieces(pos: &Position, piece: Piece) -> u64 {
+    match piece {
+        Piece::PAWN => pos.pawns,
+        Piece::KNIGHT => pos.knights,
+        Piece::BISHOP => pos.bishops,
+        Piece::ROOK => pos.rooks,
+        Piece::QUEEN => pos.queens,
+        Piece::KING => pos.kings,
+        Piece::NONE => 0,
+    }
+}
+
+// Начальные веса, подобранные вручную
+pub fn default_params() -> Vec<Score> {
+    let mut params = vec![Score::default(); PARAMS_SIZE];
+    params[MATERIAL..PST].copy_from_slice(&[
+        s(100, 120),
+        s(320, 300),
+        s(330, 320),
+        s(480, 540),
+        s(950, 1000),
+    ]);
+
+    for i in 0..64 {
+        let (file, rank) = ((i % 8) as isize, (i / 8) as isize);
+        // 0 на краю доски, 3 в центре
+        let center = file.min(7 - file).min(rank).min(7 - rank);
+        let pst = |piece: Piece| PST + (piece as usize - 1) * 64 + i;
+
+        if rank != 0 && rank != 7 {
+            let central_file = if file == 3 || file == 4 { 10 } else { 0 };
+            params[pst(Piece::PAWN)] = s(5 * (rank - 1) + central_file, 10 * (rank - 1));
+        }
+        params[pst(Piece::KNIGHT)] = s(10 * center - 20, 8 * center - 15);
+        params[pst(Piece::BISHOP)] = s(5 * center, 5 * center);
+        params[pst(Piece::ROOK)] = s(if rank == 6 { 15 } else { 0 }, 0);
+        params[pst(Piece::QUEEN)] = s(3 * center, 6 * center);
+        let shelter = if rank == 0 && file != 3 && file != 4 && file != 5 {
+            20
+        } else {
+            0
+        };
+        params[pst(Piece::KING)] = s(shelter - 15 * rank, 12 * center - 20);
+    }
+
+    for (start, size, center, mg, eg) in [
+        (KNIGHT_MOBILITY, 9, 4, 4, 4),
+        (BISHOP_MOBILITY, 14, 6, 5, 5),
+        (ROOK_MOBILITY, 15, 7, 2, 4),
+        (QUEEN_MOBILITY, 28, 13, 1, 2),
+    ] {
+        for count in 0..size {
+            params[start + count as usize] = s(mg * (count - center), eg * (count - center));
+        }
+    }
+
+    params[DOUBLED_PAWN] = s(-10, -20);
+    params[ISOLATED_PAWN] = s(-10, -15);
+    params[PASSED_PAWN..KING_ATTACK].copy_from_slice(&[
+        s(0, 0),
+        s(5, 10),
+        s(10, 20),
+        s(20, 35),
+        s(35, 60),
+        s(60, 100),
+        s(100, 150),
+        s(0, 0),
+    ]);
+    params[KING_ATTACK..PAWN_SHIELD].copy_from_slice(&[s(8, 0), s(8, 0), s(12, 0), s(20, 0)]);
+    params[PAWN_SHIELD] = s(10, 0);
+    params[BISHOP_PAIR] = s(30, 50);
+    params[ROOK_OPEN_FILE] = s(20, 10);
+    params[ROOK_SEMIOPEN_FILE] = s(10, 5);
+    return params;
+}
+
+#[test]
+fn test_classical_symmetry() {
+    unsafe { crate::chess::init() };
+    let mut eval = ClassicalEvaluationService::new();
+    for (fen, mirrored) in [
+        (
+            Position::INITIAL_POSITION_FEN,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1",
+        ),
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQkq - 0 1",
+        ),
+        (
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "8/4p1p1/8/1r3P1K/kp5R/3P4/2P5/8 b - - 0 1",
+        ),
+    ] {
+        let pos = Position::from_fen(fen).unwrap();
+        let mirrored = Position::from_fen(mirrored).unwrap();
+        assert_eq!(eval.evaluate(&pos), -eval.evaluate(&mirrored), "{}", fen);
+    }
+    assert_eq!(
+        eval.evaluate(&Position::from_fen(Position::INITIAL_POSITION_FEN).unwrap()),
+        0
+    );
+}
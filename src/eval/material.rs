@@ -1,4 +1,6 @@
-use crate::chess::{Move, Position, bitboard};
+use super::TunableEvaluator;
+use super::classical::{self, MAX_PHASE, Score};
+use crate::chess::{PieceDeltas, Position, Side, bitboard};
 
 // Удобен, чтобы оценить performance движка без затрат на оценку.
 // Тактические тесты решает даже на такой оценочной функции.
@@ -12,8 +14,11 @@ impl MaterialEvaluationService {
 
 #[allow(unused_variables)]
 impl crate::domain::IEvaluator for MaterialEvaluationService {
+    fn box_clone(&self) -> Box<dyn crate::domain::IEvaluator> {
+        return Box::new(MaterialEvaluationService::new());
+    }
     fn init(&mut self, pos: &Position) {}
-    fn make_move(&mut self, pos: &Position, mv: Move) {}
+    fn make_move(&mut self, pos: &Position, deltas: &PieceDeltas) {}
     fn unmake_move(&mut self) {}
     fn quik_evaluate(&mut self, pos: &Position) -> isize {
         let val = 100
@@ -34,3 +39,70 @@ impl crate::domain::IEvaluator for MaterialEvaluationService {
         return val;
     }
 }
+
+// Материал и таблицы полей с интерполяцией по стадии игры.
+// Параметры совпадают с первой частью параметров классической оценки.
+pub struct PstEvaluationService {
+    params: Vec<Score>,
+}
+
+const PST_PARAMS_SIZE: usize = classical::PST + 6 * 64;
+
+impl PstEvaluationService {
+    pub fn new() -> Self {
+        return PstEvaluationService {
+            params: classical::default_params()[..PST_PARAMS_SIZE].to_vec(),
+        };
+    }
+}
+
+#[allow(unused_variables)]
+impl crate::domain::IEvaluator for PstEvaluationService {
+    fn box_clone(&self) -> Box<dyn crate::domain::IEvaluator> {
+        return Box::new(PstEvaluationService {
+            params: self.params.clone(),
+        });
+    }
+    fn init(&mut self, pos: &Position) {}
+    fn make_move(&mut self, pos: &Position, deltas: &PieceDeltas) {}
+    fn unmake_move(&mut self) {}
+    fn quik_evaluate(&mut self, pos: &Position) -> isize {
+        let mut total = Score::default();
+        self.features(pos, &mut |index, count| {
+            total.mg += self.params[index].mg * count;
+            total.eg += self.params[index].eg * count;
+        });
+        let phase = classical::phase(pos);
+        return (total.mg * phase + total.eg * (MAX_PHASE - phase)) / MAX_PHASE;
+    }
+}
+
+impl TunableEvaluator for PstEvaluationService {
+    fn params(&self) -> &[Score] {
+        return &self.params;
+    }
+    fn set_params(&mut self, params: Vec<Score>) {
+        debug_assert!(params.len() == PST_PARAMS_SIZE);
+        self.params = params;
+    }
+    fn features(&self, pos: &Position, add: &mut dyn FnMut(usize, isize)) {
+        let mut bb = pos.all_pieces();
+        while bb != 0 {
+            let sq = bitboard::first_one(bb);
+            bb &= bb - 1;
+            let (side, piece) = pos.side_piece_on_square(sq).unwrap();
+            let (sign, sq) = if side == Side::WHITE {
+                (1, sq)
+            } else {
+                (-1, sq.flip())
+            };
+            if piece != crate::chess::Piece::KING {
+                add(classical::MATERIAL + piece as usize - 1, sign);
+            }
+            add(
+                classical::PST + (piece as usize - 1) * 64 + sq.index(),
+                sign,
+            );
+        }
+    }
+}
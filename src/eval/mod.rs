@@ -1,12 +1,48 @@
+pub mod classical;
 pub mod material;
 pub mod nnue;
 
+use crate::chess::Position;
 use crate::domain::IEvaluator;
+use classical::Score;
 
-pub fn make_eval(name: &str) -> Option<Box<dyn IEvaluator>> {
+pub const EVALUATORS: [&str; 4] = ["nnue", "qnnue", "classical", "material"];
+
+// eval_file - веса сети, для classical и material не используется
+pub fn make_eval(name: &str, eval_file: &str) -> Result<Box<dyn IEvaluator>, String> {
+    match name {
+        "material" => return Ok(Box::new(material::MaterialEvaluationService::new())),
+        "classical" => return Ok(Box::new(classical::ClassicalEvaluationService::new())),
+        "nnue" | "" => return nnue::make_evaluator(eval_file),
+        "qnnue" => {
+            return Ok(Box::new(nnue::QuantizedNnueEvaluationService::new(
+                eval_file,
+            )?));
+        }
+        _ => Err(format!("unknown evaluator {}", name)),
+    }
+}
+
+// Оценка, линейная по параметрам в каждой стадии игры:
+// (mg * phase + eg * (MAX_PHASE - phase)) / MAX_PHASE, где mg и eg - суммы
+// параметров, умноженных на число срабатываний признаков.
+// Веса подбирает `counter tune`.
+pub trait TunableEvaluator: IEvaluator {
+    fn params(&self) -> &[Score];
+    fn set_params(&mut self, params: Vec<Score>);
+    // Признаки с точки зрения белых: add(индекс параметра, число срабатываний)
+    fn features(&self, pos: &Position, add: &mut dyn FnMut(usize, isize));
+}
+
+pub const TUNABLE_EVALUATORS: [&str; 2] = ["pst", "classical"];
+
+pub fn make_tunable(name: &str) -> Result<Box<dyn TunableEvaluator>, String> {
     match name {
-        "material" => return Some(Box::new(material::MaterialEvaluationService::new())),
-        "nnue" | "" => return Some(Box::new(nnue::NnueEvaluationService::new())),
-        _ => None,
+        "pst" => return Ok(Box::new(material::PstEvaluationService::new())),
+        "classical" => return Ok(Box::new(classical::ClassicalEvaluationService::new())),
+        _ => Err(format!(
+            "unknown tunable evaluator {}, expected one of {:?}",
+            name, TUNABLE_EVALUATORS
+        )),
     }
 }
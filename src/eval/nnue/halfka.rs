@@ -0,0 +1,179 @@
+// Сеть с входами по корзинам короля (HalfKA): у каждой стороны свой аккумулятор,
+// в котором фигуры видны с ее стороны доски (для черных доска отражается).
+// Выходной слой получает аккумуляторы в порядке: сторона, которая ходит, затем соперник.
+
+use super::{
+    HIDDEN_SIZE, UPDATE_ACTION_ADD, Update, calc_updates, output_bucket, scale_eval, simd,
+};
+use crate::chess::{Piece, PieceDelta, PieceDeltas, Position, Side, Square, bitboard};
+use crate::domain::IEvaluator;
+use std::sync::Arc;
+
+pub const KING_BUCKETS: usize = 4;
+pub const INPUT_SIZE: usize = KING_BUCKETS * 64 * 12;
+
+// Корзина по полю своего короля (с точки зрения стороны)
+#[rustfmt::skip]
+const KING_BUCKET: [usize; 64] = [
+    0, 0, 0, 0, 1, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3,
+];
+
+type Accumulators = [[f32; HIDDEN_SIZE]; Side::SIDE_NB];
+
+pub struct HalfKaEvaluationService {
+    weights: Arc<HalfKaWeights>,
+    accumulators: Vec<Accumulators>,
+    current: usize,
+    pub(super) kernels: &'static simd::Kernels,
+}
+
+impl HalfKaEvaluationService {
+    pub fn with_weights(weights: Arc<HalfKaWeights>) -> Self {
+        return HalfKaEvaluationService {
+            weights: weights,
+            accumulators: (0..128)
+                .map(|_| [[0_f32; HIDDEN_SIZE]; Side::SIDE_NB])
+                .collect(),
+            current: 0,
+            kernels: simd::kernels(),
+        };
+    }
+
+    fn refresh(&mut self, perspective: Side, pos: &Position) {
+        let king = pos.king_sq(perspective);
+        let accumulator = &mut self.accumulators[self.current][perspective.index()];
+        accumulator.copy_from_slice(self.weights.hidden_biases());
+        let mut bb = pos.all_pieces();
+        while bb != 0 {
+            let sq = bitboard::first_one(bb);
+            bb &= bb - 1;
+            if let Some((side, piece)) = pos.side_piece_on_square(sq) {
+                let input = feature_index(perspective, king, side, piece, sq);
+                (self.kernels.add)(accumulator, self.weights.hidden_weights(input));
+            }
+        }
+    }
+}
+
+impl IEvaluator for HalfKaEvaluationService {
+    fn box_clone(&self) -> Box<dyn IEvaluator> {
+        return Box::new(HalfKaEvaluationService::with_weights(self.weights.clone()));
+    }
+    fn rule50_scaling(&self) -> bool {
+        return true;
+    }
+    fn init(&mut self, pos: &Position) {
+        self.current = 0;
+        self.refresh(Side::WHITE, pos);
+        self.refresh(Side::BLACK, pos);
+    }
+    fn make_move(&mut self, pos: &Position, deltas: &PieceDeltas) {
+        let (a, b) = self.accumulators.split_at_mut(self.current + 1);
+        b[0].copy_from_slice(&a[self.current]);
+        self.current += 1;
+
+        let mut updates = [Update::NONE; 4];
+        let updates_size = calc_updates(deltas, &mut updates);
+
+        for perspective in [Side::WHITE, Side::BLACK] {
+            // король сменил корзину: меняются все входы этой стороны
+            let king_bucket_changed = deltas.as_slice().iter().any(|&d| {
+                matches!(d, PieceDelta::Move(side, Piece::KING, from, to)
+                    if side == perspective
+                        && king_bucket(perspective, from) != king_bucket(perspective, to))
+            });
+            if king_bucket_changed {
+                self.refresh(perspective, pos);
+                continue;
+            }
+
+            let king = pos.king_sq(perspective);
+            let accumulator = &mut self.accumulators[self.current][perspective.index()];
+            for u in &updates[..updates_size] {
+                let input = feature_index(perspective, king, u.side, u.piece, u.square);
+                if u.coeff == UPDATE_ACTION_ADD {
+                    (self.kernels.add)(accumulator, self.weights.hidden_weights(input));
+                } else {
+                    (self.kernels.sub)(accumulator, self.weights.hidden_weights(input));
+                }
+            }
+        }
+    }
+    fn unmake_move(&mut self) {
+        self.current -= 1;
+    }
+
+    fn quik_evaluate(&mut self, p: &Position) -> isize {
+        let accumulators = &self.accumulators[self.current];
+        let bucket = output_bucket(self.weights.output_buckets, p);
+        let (own_weights, opp_weights) = self.weights.output_weights(bucket).split_at(HIDDEN_SIZE);
+        let dot = self.kernels.dot_relu;
+        let output = dot(&accumulators[p.side_to_move.index()], own_weights)
+            + dot(&accumulators[p.side_to_move.opp().index()], opp_weights);
+
+        let eval = (output + self.weights.output_bias(bucket)).clamp(-15_000.0, 15_000.0) as isize;
+        let eval = if p.side_to_move == Side::WHITE {
+            eval
+        } else {
+            -eval
+        };
+        return scale_eval(eval, p, self.weights.output_buckets);
+    }
+}
+
+fn king_bucket(perspective: Side, king: Square) -> usize {
+    let king = if perspective == Side::WHITE {
+        king
+    } else {
+        king.flip()
+    };
+    return KING_BUCKET[king.index()];
+}
+
+fn feature_index(perspective: Side, king: Square, side: Side, piece: Piece, sq: Square) -> usize {
+    let sq = if perspective == Side::WHITE {
+        sq
+    } else {
+        sq.flip()
+    };
+    let piece_index = if side == perspective {
+        (piece as usize) - 1
+    } else {
+        (piece as usize) + 5
+    };
+    return king_bucket(perspective, king) * 64 * 12 + (piece_index << 6) + sq.index();
+}
+
+pub struct HalfKaWeights {
+    pub weights: Vec<f32>,
+    pub output_buckets: usize,
+}
+
+impl HalfKaWeights {
+    fn hidden_weights(&self, input: usize) -> &[f32] {
+        let start = input * HIDDEN_SIZE;
+        return &self.weights[start..start + HIDDEN_SIZE];
+    }
+
+    fn hidden_biases(&self) -> &[f32] {
+        const START: usize = INPUT_SIZE * HIDDEN_SIZE;
+        return &self.weights[START..START + HIDDEN_SIZE];
+    }
+
+    fn output_weights(&self, bucket: usize) -> &[f32] {
+        let start = (1 + INPUT_SIZE) * HIDDEN_SIZE + bucket * 2 * HIDDEN_SIZE;
+        return &self.weights[start..start + 2 * HIDDEN_SIZE];
+    }
+
+    fn output_bias(&self, bucket: usize) -> f32 {
+        let start = (1 + INPUT_SIZE) * HIDDEN_SIZE + self.output_buckets * 2 * HIDDEN_SIZE;
+        return self.weights[start + bucket];
+    }
+}
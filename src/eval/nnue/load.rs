@@ -1,8 +1,11 @@
-use super::TOTAL_SIZE;
-use std::fs::File;
-use std::io::Read;
+use super::{Architecture, HIDDEN_SIZE, Network, OUTPUT_BUCKETS};
+use std::fmt;
 use std::path::{Path, PathBuf};
 
+#[cfg(feature = "embedded-net")]
+pub static EMBEDDED_NET: &[u8] =
+    include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/n-30-5268.nn"));
+
 pub fn find_file(name: &str) -> Option<PathBuf> {
     // find in folders: <working_dir>; <exe_dir>; <user_folder>/chess;
 
@@ -30,20 +33,189 @@ pub fn find_file(name: &str) -> Option<PathBuf> {
     return None;
 }
 
-pub fn load_weights(path: &Path) -> Result<Vec<f32>, std::io::Error> {
-    let mut file = File::open(path)?;
+pub fn load_weights(path: &Path) -> Result<Network, LoadError> {
+    let bytes = std::fs::read(path)?;
+    return parse_weights(&bytes);
+}
+
+// Заголовок файла весов, все поля little-endian:
+// v2: magic[4], version, architecture, input_size, hidden_size, output_size, checksum;
+// v1: то же без architecture (всегда Plain).
+// output_size - число выходов: 1 или OUTPUT_BUCKETS (по числу фигур).
+// checksum - FNV-1a весов.
+// Версия 0 - старый формат без заголовка: 24 байта пропускаются.
+pub const MAGIC: [u8; 4] = *b"CNNU";
+pub const VERSION: u32 = 2;
+const LEGACY_HEADER_SIZE: usize = 24;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    UnknownArchitecture(u32),
+    ArchitectureMismatch {
+        architecture: Architecture,
+        input_size: u32,
+        hidden_size: u32,
+        output_size: u32,
+    },
+    BadLength {
+        expected: usize,
+        actual: usize,
+    },
+    BadChecksum,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::BadMagic => write!(f, "not a nnue weights file"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported nnue file version {}", version)
+            }
+            LoadError::UnknownArchitecture(id) => write!(f, "unknown nnue architecture {}", id),
+            LoadError::ArchitectureMismatch {
+                architecture,
+                input_size,
+                hidden_size,
+                output_size,
+            } => write!(
+                f,
+                "nnue {:?} sizes {}x{}x{} does not match {}x{}x1 or {}x{}x{}",
+                architecture,
+                input_size,
+                hidden_size,
+                output_size,
+                architecture.input_size(),
+                HIDDEN_SIZE,
+                architecture.input_size(),
+                HIDDEN_SIZE,
+                OUTPUT_BUCKETS
+            ),
+            LoadError::BadLength { expected, actual } => {
+                write!(f, "bad nnue file size {} expected {}", actual, expected)
+            }
+            LoadError::BadChecksum => write!(f, "nnue checksum mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
 
-    //skip 24 bytes
-    let mut buf = [0_u8; 24];
-    file.read_exact(&mut buf)?;
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        return LoadError::Io(err);
+    }
+}
 
-    let mut buf = [0_u8; 4];
-    let mut res = Vec::with_capacity(TOTAL_SIZE);
-    for _ in 0..TOTAL_SIZE {
-        file.read_exact(&mut buf)?;
-        let w = f32::from_ne_bytes(buf);
-        res.push(w);
+pub fn parse_weights(bytes: &[u8]) -> Result<Network, LoadError> {
+    let field = |i: usize| -> Option<u32> {
+        let x = bytes.get(4 * i..4 * i + 4)?;
+        return Some(u32::from_le_bytes(x.try_into().unwrap()));
+    };
+    let bad_length = |expected: usize| LoadError::BadLength {
+        expected: expected,
+        actual: bytes.len(),
+    };
+
+    if bytes.len() < 4 || bytes[..4] != MAGIC {
+        let expected = LEGACY_HEADER_SIZE + 4 * Architecture::Plain.total_size(1);
+        if bytes.len() != expected {
+            return Err(LoadError::BadMagic);
+        }
+        return Ok(Network {
+            architecture: Architecture::Plain,
+            output_buckets: 1,
+            weights: decode(&bytes[LEGACY_HEADER_SIZE..]),
+        });
     }
 
-    return Ok(res);
+    // номера полей заголовка после version
+    let (architecture, first, header_size) = match field(1) {
+        Some(1) => (Architecture::Plain, 2, 24),
+        Some(2) => {
+            let id = field(2).ok_or(bad_length(28))?;
+            let architecture =
+                Architecture::from_id(id).ok_or(LoadError::UnknownArchitecture(id))?;
+            (architecture, 3, 28)
+        }
+        Some(version) => return Err(LoadError::UnsupportedVersion(version)),
+        None => return Err(bad_length(24)),
+    };
+    if bytes.len() < header_size {
+        return Err(bad_length(header_size));
+    }
+    let (input_size, hidden_size, output_size) = (
+        field(first).unwrap(),
+        field(first + 1).unwrap(),
+        field(first + 2).unwrap(),
+    );
+    if input_size as usize != architecture.input_size()
+        || hidden_size as usize != HIDDEN_SIZE
+        || (output_size != 1 && output_size as usize != OUTPUT_BUCKETS)
+    {
+        return Err(LoadError::ArchitectureMismatch {
+            architecture: architecture,
+            input_size: input_size,
+            hidden_size: hidden_size,
+            output_size: output_size,
+        });
+    }
+    let output_buckets = output_size as usize;
+    let expected = header_size + 4 * architecture.total_size(output_buckets);
+    if bytes.len() != expected {
+        return Err(bad_length(expected));
+    }
+
+    let data = &bytes[header_size..];
+    if checksum(data) != field(first + 3).unwrap() {
+        return Err(LoadError::BadChecksum);
+    }
+    return Ok(Network {
+        architecture: architecture,
+        output_buckets: output_buckets,
+        weights: decode(data),
+    });
+}
+
+fn decode(data: &[u8]) -> Vec<f32> {
+    return data
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+        .collect();
+}
+
+pub fn encode_weights(network: &Network) -> Vec<u8> {
+    let architecture = network.architecture;
+    debug_assert!(network.weights.len() == architecture.total_size(network.output_buckets));
+    let mut data = Vec::with_capacity(4 * network.weights.len());
+    for w in &network.weights {
+        data.extend_from_slice(&w.to_le_bytes());
+    }
+    let mut res = Vec::with_capacity(28 + data.len());
+    res.extend_from_slice(&MAGIC);
+    for x in [
+        VERSION,
+        architecture.id(),
+        architecture.input_size() as u32,
+        HIDDEN_SIZE as u32,
+        network.output_buckets as u32,
+        checksum(&data),
+    ] {
+        res.extend_from_slice(&x.to_le_bytes());
+    }
+    res.extend_from_slice(&data);
+    return res;
+}
+
+// FNV-1a
+fn checksum(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811C_9DC5;
+    for &b in data {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    return hash;
 }
@@ -1,37 +1,190 @@
+mod halfka;
 mod load;
+mod quantized;
+mod simd;
+#[cfg(test)]
+mod tests;
+pub mod train;
 
-use crate::chess::{Move, Piece, Position, Side, Square, bitboard};
+use crate::chess::{Piece, PieceDelta, PieceDeltas, Position, Side, Square, bitboard};
 use crate::domain::IEvaluator;
+use std::sync::Arc;
+
+pub use halfka::HalfKaEvaluationService;
+pub use quantized::QuantizedNnueEvaluationService;
+
+pub const DEFAULT_EVAL_FILE: &str = "n-30-5268.nn";
 
 const INPUT_SIZE: usize = 64 * 12;
 const HIDDEN_SIZE: usize = 512;
-const TOTAL_SIZE: usize = (1 + INPUT_SIZE) * HIDDEN_SIZE + (1 + HIDDEN_SIZE);
+// Выходов у сети с корзинами по числу фигур на доске
+pub const OUTPUT_BUCKETS: usize = 8;
+
+// Пределы весов сети, которую можно квантовать (qnnue): в них держит веса
+// обучение (train.rs), под них выбраны масштабы quantized.rs.
+// Скрытый слой вместе с bias, выходные веса в сантипешках.
+const HIDDEN_WEIGHT_LIMIT: f32 = 0.25;
+const OUTPUT_WEIGHT_LIMIT: f32 = 63.0;
+
+// Архитектура сети, записывается в заголовке файла весов
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Architecture {
+    // 768 входов, один аккумулятор
+    Plain,
+    // входы по корзинам короля, аккумуляторы обеих сторон в порядке хода
+    HalfKa,
+}
+
+impl Architecture {
+    fn from_id(id: u32) -> Option<Architecture> {
+        match id {
+            0 => Some(Architecture::Plain),
+            1 => Some(Architecture::HalfKa),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u32 {
+        return self as u32;
+    }
+
+    const fn input_size(self) -> usize {
+        match self {
+            Architecture::Plain => INPUT_SIZE,
+            Architecture::HalfKa => halfka::INPUT_SIZE,
+        }
+    }
+
+    // входов одного выходного нейрона
+    const fn output_width(self) -> usize {
+        match self {
+            Architecture::Plain => HIDDEN_SIZE,
+            Architecture::HalfKa => 2 * HIDDEN_SIZE,
+        }
+    }
+
+    // скрытый слой, bias, веса всех выходов, bias всех выходов
+    const fn total_size(self, output_buckets: usize) -> usize {
+        return (1 + self.input_size()) * HIDDEN_SIZE + output_buckets * (self.output_width() + 1);
+    }
+}
+
+// Веса сети, как они записаны в файле
+#[derive(Debug, PartialEq)]
+pub struct Network {
+    pub architecture: Architecture,
+    // 1 или OUTPUT_BUCKETS
+    pub output_buckets: usize,
+    pub weights: Vec<f32>,
+}
+
+// Оценка по файлу сети: архитектура берется из заголовка
+pub fn make_evaluator(eval_file: &str) -> Result<Box<dyn IEvaluator>, String> {
+    let network = load_network(eval_file)?;
+    match network.architecture {
+        Architecture::Plain => {
+            return Ok(Box::new(NnueEvaluationService::with_weights(Arc::new(
+                Weights {
+                    weights: network.weights,
+                    output_buckets: network.output_buckets,
+                },
+            ))));
+        }
+        Architecture::HalfKa => {
+            return Ok(Box::new(HalfKaEvaluationService::with_weights(Arc::new(
+                halfka::HalfKaWeights {
+                    weights: network.weights,
+                    output_buckets: network.output_buckets,
+                },
+            ))));
+        }
+    }
+}
+
+fn load_network(name: &str) -> Result<Network, String> {
+    #[cfg(feature = "embedded-net")]
+    if name == DEFAULT_EVAL_FILE && load::find_file(name).is_none() {
+        let network = load::parse_weights(load::EMBEDDED_NET)
+            .map_err(|e| format!("failed load embedded nnue: {}", e))?;
+        eprintln!("load embedded nnue");
+        return Ok(network);
+    }
+    let filepath = load::find_file(name).ok_or(format!("file not found {}", name))?;
+    let network = load::load_weights(&filepath)
+        .map_err(|e| format!("failed load nnue weight file {}: {}", filepath.display(), e))?;
+    eprintln!("load nnue {}", filepath.display());
+    return Ok(network);
+}
 
 pub struct NnueEvaluationService {
-    weights: Weights,
+    weights: Arc<Weights>,
     hidden_outputs: Vec<[f32; HIDDEN_SIZE]>,
+    // изменения входов по ply: аккумулятор считается только при оценке
+    pending: Vec<PendingUpdates>,
     current_hidden: usize,
+    kernels: &'static simd::Kernels,
+}
+
+#[derive(Clone, Copy)]
+struct PendingUpdates {
+    updates: [Update; 4],
+    size: usize,
+    applied: bool,
 }
 
 impl NnueEvaluationService {
-    pub fn new() -> Self {
-        let name = "n-30-5268.nn";
-        let filepath = load::find_file(name).expect(&format!("file not found {}", name));
-        let raw_weights = load::load_weights(&filepath).expect("failed load nnue weight file");
-        eprintln!("load nnue {}", filepath.display());
+    fn with_weights(weights: Arc<Weights>) -> Self {
         return NnueEvaluationService {
-            weights: Weights {
-                weights: raw_weights,
-            },
+            weights: weights,
             hidden_outputs: (0..128).map(|_| [0_f32; HIDDEN_SIZE]).collect(),
+            pending: vec![
+                PendingUpdates {
+                    updates: [Update::NONE; 4],
+                    size: 0,
+                    applied: false,
+                };
+                128
+            ],
             current_hidden: 0,
+            kernels: simd::kernels(),
         };
     }
+
+    // Досчитываем аккумуляторы от последнего посчитанного ply до текущего
+    fn materialize(&mut self) {
+        let mut first = self.current_hidden;
+        while !self.pending[first].applied {
+            first -= 1;
+        }
+        for ply in first + 1..=self.current_hidden {
+            let (a, b) = self.hidden_outputs.split_at_mut(ply);
+            let hidden_outputs = &mut b[0];
+            hidden_outputs.copy_from_slice(&a[ply - 1]);
+
+            let pending = &mut self.pending[ply];
+            for u in &pending.updates[..pending.size] {
+                if u.coeff == UPDATE_ACTION_ADD {
+                    (self.kernels.add)(hidden_outputs, self.weights.hidden_weights(u.input()));
+                } else {
+                    (self.kernels.sub)(hidden_outputs, self.weights.hidden_weights(u.input()));
+                }
+            }
+            pending.applied = true;
+        }
+    }
 }
 
 impl IEvaluator for NnueEvaluationService {
+    fn box_clone(&self) -> Box<dyn IEvaluator> {
+        // веса общие, аккумуляторы у каждого потока свои
+        return Box::new(NnueEvaluationService::with_weights(self.weights.clone()));
+    }
+    fn rule50_scaling(&self) -> bool {
+        return true;
+    }
     fn init(&mut self, pos: &Position) {
         self.current_hidden = 0;
+        self.pending[0].applied = true;
         let hidden_outputs = &mut self.hidden_outputs[self.current_hidden];
         hidden_outputs.copy_from_slice(self.weights.hidden_biases());
         let mut bb = pos.all_pieces();
@@ -40,157 +193,125 @@ impl IEvaluator for NnueEvaluationService {
             bb &= bb - 1;
             if let Some((side, piece)) = pos.side_piece_on_square(sq) {
                 let input = calc_net_input_index(side, piece, sq);
-                for (h, w) in hidden_outputs
-                    .iter_mut()
-                    .zip(self.weights.hidden_weights(input))
-                {
-                    *h += w;
-                }
+                (self.kernels.add)(hidden_outputs, self.weights.hidden_weights(input));
             }
         }
     }
-    #[allow(invalid_value)]
-    fn make_move(&mut self, pos: &Position, mv: Move) {
-        let (a, b) = self.hidden_outputs.split_at_mut(self.current_hidden + 1);
-        b[0].copy_from_slice(&a[self.current_hidden]);
+    fn make_move(&mut self, _pos: &Position, deltas: &PieceDeltas) {
         self.current_hidden += 1;
-        if mv.is_null() {
-            return;
-        }
+        let pending = &mut self.pending[self.current_hidden];
+        pending.size = calc_updates(deltas, &mut pending.updates);
+        pending.applied = false;
+    }
+    fn unmake_move(&mut self) {
+        self.current_hidden -= 1;
+    }
 
-        // unpack move
-        let side = pos.side_to_move;
-        let from = mv.from();
-        let to = mv.to();
-        let moving_piece = mv.moving_piece();
-        let captured_piece = mv.captured_piece();
-        let promotion_piece = mv.promotion();
+    fn quik_evaluate(&mut self, p: &Position) -> isize {
+        self.materialize();
+        let bucket = output_bucket(self.weights.output_buckets, p);
+        let output = (self.kernels.dot_relu)(
+            &self.hidden_outputs[self.current_hidden],
+            self.weights.output_weights(bucket),
+        );
 
-        // generate updates
-        let mut updates: [Update; 4] = unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-        let mut updates_size = 0;
+        let eval = (output + self.weights.output_bias(bucket)).clamp(-15_000.0, 15_000.0) as isize;
+        return scale_eval(eval, p, self.weights.output_buckets);
+    }
+}
 
-        updates[updates_size] = Update {
-            input: calc_net_input_index(side, moving_piece, from),
-            coeff: UPDATE_ACTION_REMOVE,
-        };
-        updates_size += 1;
+// Выход сети по числу фигур на доске: 2..=5 фигур - 0, ..., 30..=32 - 7
+fn output_bucket(output_buckets: usize, p: &Position) -> usize {
+    if output_buckets == 1 {
+        return 0;
+    }
+    let pieces = p.all_pieces().count_ones() as usize;
+    return ((pieces - 2) / 4).min(output_buckets - 1);
+}
 
-        if captured_piece != Piece::NONE {
-            let mut cap_sq = to;
-            if moving_piece == Piece::PAWN && Some(to) == pos.ep_square {
-                cap_sq = to.forward(side.opp());
-            }
+// Масштабирование по материалу для сети с одним выходом, которая не различает
+// стадии игры. Правило 50 ходов учитывает поиск, чтобы оценку можно было кешировать.
+fn scale_eval(eval: isize, p: &Position, output_buckets: usize) -> isize {
+    if output_buckets == 1 {
+        return eval * material_scale(p) / 160;
+    }
+    return eval;
+}
 
-            updates[updates_size] = Update {
-                input: calc_net_input_index(side.opp(), captured_piece, cap_sq),
-                coeff: UPDATE_ACTION_REMOVE,
-            };
-            updates_size += 1;
-        }
+// Множитель оценки по материалу без пешек, в 1/160
+fn material_scale(p: &Position) -> isize {
+    let np_material = (4 * (p.knights | p.bishops).count_ones()
+        + 6 * p.rooks.count_ones()
+        + 12 * p.queens.count_ones()) as isize;
+    return 160 + np_material;
+}
 
-        let piece_after_move = if promotion_piece != Piece::NONE {
-            promotion_piece
-        } else {
-            moving_piece
-        };
+// Изменения входов сети по изменениям фигур при ходе
+fn calc_updates(deltas: &PieceDeltas, updates: &mut [Update; 4]) -> usize {
+    let mut updates_size = 0;
+    let mut push = |side: Side, piece: Piece, square: Square, coeff: isize| {
         updates[updates_size] = Update {
-            input: calc_net_input_index(side, piece_after_move, to),
-            coeff: UPDATE_ACTION_ADD,
+            side: side,
+            piece: piece,
+            square: square,
+            coeff: coeff,
         };
         updates_size += 1;
-
-        if moving_piece == Piece::KING {
-            let mut is_catlingg = false;
-            let mut rook_remove_sq = Square::A1;
-            let mut rook_add_sq = Square::A1;
-            if from == Square::E1 {
-                if to == Square::G1 {
-                    is_catlingg = true;
-                    rook_remove_sq = Square::H1;
-                    rook_add_sq = Square::F1;
-                } else if to == Square::C1 {
-                    is_catlingg = true;
-                    rook_remove_sq = Square::A1;
-                    rook_add_sq = Square::D1;
-                }
-            } else if from == Square::E8 {
-                if to == Square::G8 {
-                    is_catlingg = true;
-                    rook_remove_sq = Square::H8;
-                    rook_add_sq = Square::F8;
-                } else if to == Square::C8 {
-                    is_catlingg = true;
-                    rook_remove_sq = Square::A8;
-                    rook_add_sq = Square::D8;
-                }
-            }
-            if is_catlingg {
-                updates[updates_size] = Update {
-                    input: calc_net_input_index(side, Piece::ROOK, rook_remove_sq),
-                    coeff: UPDATE_ACTION_REMOVE,
-                };
-                updates_size += 1;
-                updates[updates_size] = Update {
-                    input: calc_net_input_index(side, Piece::ROOK, rook_add_sq),
-                    coeff: UPDATE_ACTION_ADD,
-                };
-                updates_size += 1;
-            }
-        }
-
-        // apply updates, SIMD
-        let hidden_outputs = &mut b[0];
-        for u in &updates[..updates_size] {
-            if u.coeff == UPDATE_ACTION_ADD {
-                hidden_outputs
-                    .iter_mut()
-                    .zip(self.weights.hidden_weights(u.input))
-                    .for_each(|(h, &w)| *h += w);
-            } else {
-                hidden_outputs
-                    .iter_mut()
-                    .zip(self.weights.hidden_weights(u.input))
-                    .for_each(|(h, &w)| *h -= w);
+    };
+    for &delta in deltas.as_slice() {
+        match delta {
+            PieceDelta::Add(side, piece, sq) => push(side, piece, sq, UPDATE_ACTION_ADD),
+            PieceDelta::Remove(side, piece, sq) => push(side, piece, sq, UPDATE_ACTION_REMOVE),
+            PieceDelta::Move(side, piece, from, to) => {
+                push(side, piece, from, UPDATE_ACTION_REMOVE);
+                push(side, piece, to, UPDATE_ACTION_ADD);
             }
         }
     }
-    fn unmake_move(&mut self) {
-        self.current_hidden -= 1;
-    }
-
-    fn quik_evaluate(&mut self, p: &Position) -> isize {
-        let output: f32 = self.hidden_outputs[self.current_hidden]
-            .iter()
-            .zip(self.weights.output_weights())
-            .fold(0_f32, |acc, (&x, &w)| {
-                acc.algebraic_add(x.max(0_f32) * w) // SIMD
-            });
-
-        let mut eval = (output + self.weights.output_bias()).clamp(-15_000.0, 15_000.0) as isize;
-
-        let np_material = (4 * (p.knights | p.bishops).count_ones()
-            + 6 * p.rooks.count_ones()
-            + 12 * p.queens.count_ones()) as isize;
-        eval = eval * (160 + np_material) / 160;
-        eval = eval * (200 - p.rule50) / 200;
-        return eval;
-    }
+    return updates_size;
 }
 
 const UPDATE_ACTION_ADD: isize = 1;
 const UPDATE_ACTION_REMOVE: isize = -1;
 
+#[derive(Clone, Copy)]
 struct Update {
-    input: usize,
+    side: Side,
+    piece: Piece,
+    square: Square,
     coeff: isize,
 }
 
+impl Update {
+    const NONE: Update = Update {
+        side: Side::WHITE,
+        piece: Piece::NONE,
+        square: Square::A1,
+        coeff: 0,
+    };
+
+    fn input(&self) -> usize {
+        return calc_net_input_index(self.side, self.piece, self.square);
+    }
+}
+
 struct Weights {
     weights: Vec<f32>,
+    output_buckets: usize,
 }
 
 impl Weights {
+    fn load(name: &str) -> Result<Self, String> {
+        let network = load_network(name)?;
+        if network.architecture != Architecture::Plain {
+            return Err(format!("{} is not a plain 768 input network", name));
+        }
+        return Ok(Weights {
+            weights: network.weights,
+            output_buckets: network.output_buckets,
+        });
+    }
+
     pub fn hidden_weights(&self, input: usize) -> &[f32] {
         let start = input * HIDDEN_SIZE;
         return &self.weights[start..start + HIDDEN_SIZE];
@@ -202,15 +323,14 @@ impl Weights {
         return &self.weights[START..END];
     }
 
-    pub fn output_weights(&self) -> &[f32] {
-        const START: usize = (1 + INPUT_SIZE) * HIDDEN_SIZE;
-        const END: usize = START + HIDDEN_SIZE;
-        return &self.weights[START..END];
+    pub fn output_weights(&self, bucket: usize) -> &[f32] {
+        let start = (1 + INPUT_SIZE) * HIDDEN_SIZE + bucket * HIDDEN_SIZE;
+        return &self.weights[start..start + HIDDEN_SIZE];
     }
 
-    pub fn output_bias(&self) -> f32 {
-        const INDEX: usize = (1 + INPUT_SIZE) * HIDDEN_SIZE + HIDDEN_SIZE;
-        return self.weights[INDEX];
+    pub fn output_bias(&self, bucket: usize) -> f32 {
+        let start = (1 + INPUT_SIZE) * HIDDEN_SIZE + self.output_buckets * HIDDEN_SIZE;
+        return self.weights[start + bucket];
     }
 }
 
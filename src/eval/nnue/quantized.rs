@@ -0,0 +1,188 @@
+// Та же сеть в целых числах: аккумуляторы i16, выходной слой i16 x i16 -> i64.
+// Веса квантуются при загрузке из float-файла.
+
+use super::{
+    HIDDEN_SIZE, HIDDEN_WEIGHT_LIMIT, INPUT_SIZE, OUTPUT_WEIGHT_LIMIT, UPDATE_ACTION_ADD, Update,
+    Weights, calc_net_input_index, calc_updates, output_bucket, scale_eval, simd,
+};
+use crate::chess::{PieceDeltas, Position, bitboard};
+use crate::domain::IEvaluator;
+use std::sync::Arc;
+
+// Масштаб скрытого слоя. Сумма bias и 32 весов входов должна помещаться в i16:
+// аккумулятор считается без проверки переполнения, quantize это проверяет.
+const QA: i32 = 1024;
+// Масштаб выходных весов
+const QB: i32 = 32;
+// На доске не больше 32 фигур - столько входов одновременно
+const MAX_ACTIVE_INPUTS: usize = 32;
+
+// Сеть в пределах весов обучения квантуется всегда
+const _: () =
+    assert!((MAX_ACTIVE_INPUTS + 1) as f32 * HIDDEN_WEIGHT_LIMIT * QA as f32 <= i16::MAX as f32);
+const _: () = assert!(OUTPUT_WEIGHT_LIMIT * QB as f32 <= i16::MAX as f32);
+// ReLU без ограничения сверху, как во float-сети: активация до 2^15, вес до 2^11,
+// сумма 512 произведений до 2^35 - считаем в i64
+
+pub struct QuantizedNnueEvaluationService {
+    weights: Arc<QuantizedWeights>,
+    hidden_outputs: Vec<[i16; HIDDEN_SIZE]>,
+    current_hidden: usize,
+    pub(super) kernels: &'static simd::Kernels,
+}
+
+impl QuantizedNnueEvaluationService {
+    pub fn new(eval_file: &str) -> Result<Self, String> {
+        let weights = QuantizedWeights::quantize(&Weights::load(eval_file)?)
+            .map_err(|e| format!("failed quantize nnue {}: {}", eval_file, e))?;
+        return Ok(QuantizedNnueEvaluationService::with_weights(Arc::new(
+            weights,
+        )));
+    }
+
+    fn with_weights(weights: Arc<QuantizedWeights>) -> Self {
+        return QuantizedNnueEvaluationService {
+            weights: weights,
+            hidden_outputs: (0..128).map(|_| [0_i16; HIDDEN_SIZE]).collect(),
+            current_hidden: 0,
+            kernels: simd::kernels(),
+        };
+    }
+
+    #[cfg(test)]
+    pub(super) fn from_float(weights: &Weights) -> Result<Self, String> {
+        let weights = QuantizedWeights::quantize(weights)?;
+        return Ok(QuantizedNnueEvaluationService::with_weights(Arc::new(
+            weights,
+        )));
+    }
+}
+
+impl IEvaluator for QuantizedNnueEvaluationService {
+    fn box_clone(&self) -> Box<dyn IEvaluator> {
+        return Box::new(QuantizedNnueEvaluationService::with_weights(
+            self.weights.clone(),
+        ));
+    }
+    fn rule50_scaling(&self) -> bool {
+        return true;
+    }
+    fn init(&mut self, pos: &Position) {
+        self.current_hidden = 0;
+        let hidden_outputs = &mut self.hidden_outputs[self.current_hidden];
+        hidden_outputs.copy_from_slice(&self.weights.hidden_biases);
+        let mut bb = pos.all_pieces();
+        while bb != 0 {
+            let sq = bitboard::first_one(bb);
+            bb &= bb - 1;
+            if let Some((side, piece)) = pos.side_piece_on_square(sq) {
+                let input = calc_net_input_index(side, piece, sq);
+                (self.kernels.add_i16)(hidden_outputs, self.weights.hidden_weights(input));
+            }
+        }
+    }
+    fn make_move(&mut self, _pos: &Position, deltas: &PieceDeltas) {
+        let (a, b) = self.hidden_outputs.split_at_mut(self.current_hidden + 1);
+        b[0].copy_from_slice(&a[self.current_hidden]);
+        self.current_hidden += 1;
+
+        let mut updates = [Update::NONE; 4];
+        let updates_size = calc_updates(deltas, &mut updates);
+
+        let hidden_outputs = &mut b[0];
+        for u in &updates[..updates_size] {
+            if u.coeff == UPDATE_ACTION_ADD {
+                (self.kernels.add_i16)(hidden_outputs, self.weights.hidden_weights(u.input()));
+            } else {
+                (self.kernels.sub_i16)(hidden_outputs, self.weights.hidden_weights(u.input()));
+            }
+        }
+    }
+    fn unmake_move(&mut self) {
+        self.current_hidden -= 1;
+    }
+
+    fn quik_evaluate(&mut self, p: &Position) -> isize {
+        let bucket = output_bucket(self.weights.output_buckets, p);
+        let start = bucket * HIDDEN_SIZE;
+        let output = (self.kernels.dot_relu_i16)(
+            &self.hidden_outputs[self.current_hidden],
+            &self.weights.output_weights[start..start + HIDDEN_SIZE],
+        );
+
+        let eval = ((output + self.weights.output_biases[bucket] as i64) / (QA * QB) as i64)
+            .clamp(-15_000, 15_000);
+        return scale_eval(eval as isize, p, self.weights.output_buckets);
+    }
+}
+
+struct QuantizedWeights {
+    hidden_weights: Vec<i16>,
+    hidden_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_biases: Vec<i32>,
+    output_buckets: usize,
+}
+
+impl QuantizedWeights {
+    // Ошибка, если вес не помещается в целый тип или аккумулятор может переполниться
+    fn quantize(weights: &Weights) -> Result<Self, String> {
+        let quantize = |w: f32, scale: i32| -> Result<i16, String> {
+            let x = (w * scale as f32).round();
+            if !(i16::MIN as f32..=i16::MAX as f32).contains(&x) {
+                return Err(format!("weight {} out of range", w));
+            }
+            return Ok(x as i16);
+        };
+        let hidden_weights = (0..INPUT_SIZE)
+            .flat_map(|input| weights.hidden_weights(input))
+            .map(|&w| quantize(w, QA))
+            .collect::<Result<Vec<_>, _>>()?;
+        let hidden_biases = weights
+            .hidden_biases()
+            .iter()
+            .map(|&w| quantize(w, QA))
+            .collect::<Result<Vec<_>, _>>()?;
+        let buckets = 0..weights.output_buckets;
+        let output_weights = buckets
+            .clone()
+            .flat_map(|bucket| weights.output_weights(bucket))
+            .map(|&w| quantize(w, QB))
+            .collect::<Result<Vec<_>, _>>()?;
+        let output_biases = buckets
+            .map(|bucket| {
+                let x = (weights.output_bias(bucket) * (QA * QB) as f32).round();
+                if !(i32::MIN as f32..=i32::MAX as f32).contains(&x) {
+                    return Err(format!("output bias {} out of range", x));
+                }
+                return Ok(x as i32);
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // худший случай для нейрона: bias и 32 самых больших по модулю веса
+        let mut column = vec![0_i32; INPUT_SIZE];
+        for (h, &bias) in hidden_biases.iter().enumerate() {
+            for (input, x) in column.iter_mut().enumerate() {
+                *x = (hidden_weights[input * HIDDEN_SIZE + h] as i32).abs();
+            }
+            column.sort_unstable_by(|a, b| b.cmp(a));
+            let worst = (bias as i32).abs() + column[..MAX_ACTIVE_INPUTS].iter().sum::<i32>();
+            if worst > i16::MAX as i32 {
+                return Err(format!("hidden neuron {} may overflow i16 ({})", h, worst));
+            }
+        }
+
+        return Ok(QuantizedWeights {
+            hidden_weights: hidden_weights,
+            hidden_biases: hidden_biases,
+            output_weights: output_weights,
+            output_biases: output_biases,
+            output_buckets: weights.output_buckets,
+        });
+    }
+
+    fn hidden_weights(&self, input: usize) -> &[i16] {
+        let start = input * HIDDEN_SIZE;
+        return &self.hidden_weights[start..start + HIDDEN_SIZE];
+    }
+}
@@ -0,0 +1,332 @@
+// Ядра сети (сложение весов в аккумулятор и выходной слой) с выбором набора
+// инструкций при запуске. Скалярное произведение во всех вариантах считается
+// в одном порядке: 16 частичных сумм и одинаковая свертка, без FMA, поэтому
+// оценка не зависит от процессора. Целочисленные ядра (qnnue) точные.
+
+use std::sync::OnceLock;
+
+const LANES: usize = 16;
+
+#[derive(Clone, Copy)]
+pub struct Kernels {
+    pub name: &'static str,
+    pub add: fn(&mut [f32], &[f32]),
+    pub sub: fn(&mut [f32], &[f32]),
+    // сумма max(x, 0) * w
+    pub dot_relu: fn(&[f32], &[f32]) -> f32,
+    // то же в i16, сумма в i64; переполнение i16 исключает квантование
+    pub add_i16: fn(&mut [i16], &[i16]),
+    pub sub_i16: fn(&mut [i16], &[i16]),
+    pub dot_relu_i16: fn(&[i16], &[i16]) -> i64,
+}
+
+pub fn kernels() -> &'static Kernels {
+    static KERNELS: OnceLock<Kernels> = OnceLock::new();
+    return KERNELS.get_or_init(|| {
+        let kernels = *available().last().unwrap();
+        eprintln!("nnue kernels {}", kernels.name);
+        kernels
+    });
+}
+
+// Варианты, которые поддерживает процессор, от медленного к быстрому
+pub fn available() -> Vec<Kernels> {
+    let mut result = vec![SCALAR];
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SSE2 есть на любом x86_64
+        result.push(x86::SSE2);
+        if is_x86_feature_detected!("avx2") {
+            result.push(x86::AVX2);
+            // целочисленные ядра у AVX512 из AVX2
+            if is_x86_feature_detected!("avx512f") {
+                result.push(x86::AVX512);
+            }
+        }
+    }
+    return result;
+}
+
+const SCALAR: Kernels = Kernels {
+    name: "scalar",
+    add: add_scalar,
+    sub: sub_scalar,
+    dot_relu: dot_relu_scalar,
+    add_i16: add_i16_scalar,
+    sub_i16: sub_i16_scalar,
+    dot_relu_i16: dot_relu_i16_scalar,
+};
+
+fn add_scalar(acc: &mut [f32], w: &[f32]) {
+    for (h, &w) in acc.iter_mut().zip(w) {
+        *h += w;
+    }
+}
+
+fn sub_scalar(acc: &mut [f32], w: &[f32]) {
+    for (h, &w) in acc.iter_mut().zip(w) {
+        *h -= w;
+    }
+}
+
+fn dot_relu_scalar(x: &[f32], w: &[f32]) -> f32 {
+    debug_assert!(x.len().is_multiple_of(LANES));
+    let mut lanes = [0_f32; LANES];
+    for (x, w) in x.chunks_exact(LANES).zip(w.chunks_exact(LANES)) {
+        for i in 0..LANES {
+            lanes[i] += x[i].max(0_f32) * w[i];
+        }
+    }
+    return reduce(&lanes);
+}
+
+fn add_i16_scalar(acc: &mut [i16], w: &[i16]) {
+    for (h, &w) in acc.iter_mut().zip(w) {
+        *h += w;
+    }
+}
+
+fn sub_i16_scalar(acc: &mut [i16], w: &[i16]) {
+    for (h, &w) in acc.iter_mut().zip(w) {
+        *h -= w;
+    }
+}
+
+fn dot_relu_i16_scalar(x: &[i16], w: &[i16]) -> i64 {
+    return x
+        .iter()
+        .zip(w)
+        .map(|(&x, &w)| x.max(0) as i64 * w as i64)
+        .sum();
+}
+
+fn reduce(lanes: &[f32; LANES]) -> f32 {
+    return lanes.iter().fold(0_f32, |acc, &x| acc + x);
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{Kernels, LANES, reduce};
+    use std::arch::x86_64::*;
+
+    // Регистры шириной width * 4 байт, по LANES / width регистров частичных сумм
+    macro_rules! kernels {
+        ($name:ident, $feature:literal, $width:literal, $t:ty,
+         $load:ident, $store:ident, $add:ident, $sub:ident, $mul:ident, $max:ident, $zero:ident,
+         $i16:ident) => {
+            pub const $name: Kernels = Kernels {
+                name: $feature,
+                add: |acc, w| unsafe { imp::add(acc, w) },
+                sub: |acc, w| unsafe { imp::sub(acc, w) },
+                dot_relu: |x, w| unsafe { imp::dot_relu(x, w) },
+                add_i16: |acc, w| unsafe { $i16::add(acc, w) },
+                sub_i16: |acc, w| unsafe { $i16::sub(acc, w) },
+                dot_relu_i16: |x, w| unsafe { $i16::dot_relu(x, w) },
+            };
+
+            mod imp {
+                use super::*;
+
+                const REGS: usize = LANES / $width;
+
+                #[target_feature(enable = $feature)]
+                pub unsafe fn add(acc: &mut [f32], w: &[f32]) {
+                    assert!(acc.len() == w.len() && acc.len().is_multiple_of($width));
+                    for i in (0..acc.len()).step_by($width) {
+                        unsafe {
+                            let p = acc.as_mut_ptr().add(i);
+                            $store(p, $add($load(p), $load(w.as_ptr().add(i))));
+                        }
+                    }
+                }
+
+                #[target_feature(enable = $feature)]
+                pub unsafe fn sub(acc: &mut [f32], w: &[f32]) {
+                    assert!(acc.len() == w.len() && acc.len().is_multiple_of($width));
+                    for i in (0..acc.len()).step_by($width) {
+                        unsafe {
+                            let p = acc.as_mut_ptr().add(i);
+                            $store(p, $sub($load(p), $load(w.as_ptr().add(i))));
+                        }
+                    }
+                }
+
+                #[target_feature(enable = $feature)]
+                pub unsafe fn dot_relu(x: &[f32], w: &[f32]) -> f32 {
+                    assert!(x.len() == w.len() && x.len().is_multiple_of(LANES));
+                    let zero = $zero();
+                    let mut sums: [$t; REGS] = [zero; REGS];
+                    for i in (0..x.len()).step_by(LANES) {
+                        for (r, sum) in sums.iter_mut().enumerate() {
+                            let j = i + r * $width;
+                            unsafe {
+                                let x = $max($load(x.as_ptr().add(j)), zero);
+                                *sum = $add(*sum, $mul(x, $load(w.as_ptr().add(j))));
+                            }
+                        }
+                    }
+                    let mut lanes = [0_f32; LANES];
+                    for (r, &sum) in sums.iter().enumerate() {
+                        unsafe { $store(lanes.as_mut_ptr().add(r * $width), sum) };
+                    }
+                    return reduce(&lanes);
+                }
+            }
+        };
+    }
+
+    // Регистры по width значений i16. Произведения попарно складываются в i32
+    // (madd, не больше 2 * 32767^2 < 2^31) и накапливаются в i64 (widen_add).
+    macro_rules! kernels_i16 {
+        ($feature:literal, $width:literal, $t:ty, $load:ident, $store:ident,
+         $add:ident, $sub:ident, $max:ident, $madd:ident, $zero:ident) => {
+            pub mod imp_i16 {
+                use super::*;
+
+                #[target_feature(enable = $feature)]
+                pub unsafe fn add(acc: &mut [i16], w: &[i16]) {
+                    assert!(acc.len() == w.len() && acc.len().is_multiple_of($width));
+                    for i in (0..acc.len()).step_by($width) {
+                        unsafe {
+                            let p = acc.as_mut_ptr().add(i) as *mut $t;
+                            $store(p, $add($load(p), $load(w.as_ptr().add(i) as *const $t)));
+                        }
+                    }
+                }
+
+                #[target_feature(enable = $feature)]
+                pub unsafe fn sub(acc: &mut [i16], w: &[i16]) {
+                    assert!(acc.len() == w.len() && acc.len().is_multiple_of($width));
+                    for i in (0..acc.len()).step_by($width) {
+                        unsafe {
+                            let p = acc.as_mut_ptr().add(i) as *mut $t;
+                            $store(p, $sub($load(p), $load(w.as_ptr().add(i) as *const $t)));
+                        }
+                    }
+                }
+
+                #[target_feature(enable = $feature)]
+                pub unsafe fn dot_relu(x: &[i16], w: &[i16]) -> i64 {
+                    assert!(x.len() == w.len() && x.len().is_multiple_of($width));
+                    let zero = $zero();
+                    let mut sum = zero;
+                    for i in (0..x.len()).step_by($width) {
+                        unsafe {
+                            let x = $max($load(x.as_ptr().add(i) as *const $t), zero);
+                            let w = $load(w.as_ptr().add(i) as *const $t);
+                            sum = widen_add(sum, $madd(x, w));
+                        }
+                    }
+                    let mut lanes = [0_i64; $width / 4];
+                    unsafe { $store(lanes.as_mut_ptr() as *mut $t, sum) };
+                    return lanes.iter().sum();
+                }
+            }
+        };
+    }
+
+    mod sse2 {
+        use super::*;
+
+        // sum + i32 из x, расширенные до i64
+        #[target_feature(enable = "sse2")]
+        fn widen_add(sum: __m128i, x: __m128i) -> __m128i {
+            let sign = _mm_srai_epi32(x, 31);
+            let lo = _mm_unpacklo_epi32(x, sign);
+            let hi = _mm_unpackhi_epi32(x, sign);
+            return _mm_add_epi64(sum, _mm_add_epi64(lo, hi));
+        }
+
+        kernels_i16!(
+            "sse2",
+            8,
+            __m128i,
+            _mm_loadu_si128,
+            _mm_storeu_si128,
+            _mm_add_epi16,
+            _mm_sub_epi16,
+            _mm_max_epi16,
+            _mm_madd_epi16,
+            _mm_setzero_si128
+        );
+
+        kernels!(
+            SSE2,
+            "sse2",
+            4,
+            __m128,
+            _mm_loadu_ps,
+            _mm_storeu_ps,
+            _mm_add_ps,
+            _mm_sub_ps,
+            _mm_mul_ps,
+            _mm_max_ps,
+            _mm_setzero_ps,
+            imp_i16
+        );
+    }
+
+    mod avx2 {
+        use super::*;
+
+        #[target_feature(enable = "avx2")]
+        fn widen_add(sum: __m256i, x: __m256i) -> __m256i {
+            let lo = _mm256_cvtepi32_epi64(_mm256_castsi256_si128(x));
+            let hi = _mm256_cvtepi32_epi64(_mm256_extracti128_si256::<1>(x));
+            return _mm256_add_epi64(sum, _mm256_add_epi64(lo, hi));
+        }
+
+        kernels_i16!(
+            "avx2",
+            16,
+            __m256i,
+            _mm256_loadu_si256,
+            _mm256_storeu_si256,
+            _mm256_add_epi16,
+            _mm256_sub_epi16,
+            _mm256_max_epi16,
+            _mm256_madd_epi16,
+            _mm256_setzero_si256
+        );
+
+        kernels!(
+            AVX2,
+            "avx2",
+            8,
+            __m256,
+            _mm256_loadu_ps,
+            _mm256_storeu_ps,
+            _mm256_add_ps,
+            _mm256_sub_ps,
+            _mm256_mul_ps,
+            _mm256_max_ps,
+            _mm256_setzero_ps,
+            imp_i16
+        );
+    }
+
+    mod avx512 {
+        use super::*;
+        // AVX512 проверяется только вместе с AVX2
+        use super::avx2::imp_i16 as avx2_i16;
+
+        kernels!(
+            AVX512,
+            "avx512f",
+            16,
+            __m512,
+            _mm512_loadu_ps,
+            _mm512_storeu_ps,
+            _mm512_add_ps,
+            _mm512_sub_ps,
+            _mm512_mul_ps,
+            _mm512_max_ps,
+            _mm512_setzero_ps,
+            avx2_i16
+        );
+    }
+
+    pub use avx2::AVX2;
+    pub use avx512::AVX512;
+    pub use sse2::SSE2;
+}
@@ -0,0 +1,393 @@
+use super::halfka::HalfKaWeights;
+use super::{
+    Architecture, HIDDEN_SIZE, HalfKaEvaluationService, INPUT_SIZE, Network, NnueEvaluationService,
+    OUTPUT_BUCKETS, QuantizedNnueEvaluationService, Weights, load, simd,
+};
+use crate::chess::{MoveList, PieceDeltas, Position, XorshiftRng};
+use crate::domain::IEvaluator;
+use load::LoadError;
+use std::sync::Arc;
+
+// Допустимое расхождение целочисленной оценки с float, в сантипешках
+const TOLERANCE: isize = 5;
+
+fn uniform(rng: &mut XorshiftRng, max: f32) -> f32 {
+    let x = (rng.next() >> 40) as f32 / (1 << 24) as f32;
+    return (2.0 * x - 1.0) * max;
+}
+
+// Случайные веса того же порядка, что и в n-30-5268.nn
+fn random_weights(architecture: Architecture, output_buckets: usize) -> Vec<f32> {
+    let mut rng = XorshiftRng::with_seed(5268);
+    let hidden_end = (1 + architecture.input_size()) * HIDDEN_SIZE;
+    return (0..architecture.total_size(output_buckets))
+        .map(|i| {
+            if i < hidden_end {
+                uniform(&mut rng, 0.1)
+            } else {
+                uniform(&mut rng, 50.0)
+            }
+        })
+        .collect();
+}
+
+// Случайная партия из нескольких позиций: оценка после инкрементальных
+// обновлений в make_move сравнивается с expected
+fn play_random_games(
+    evaluator: &mut dyn IEvaluator,
+    mut expected: impl FnMut(&Position) -> isize,
+    tolerance: isize,
+) {
+    let fens = [
+        Position::INITIAL_POSITION_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "bqnbnrkr/pppppppp/8/8/8/8/PPPPPPPP/BQNBNRKR w HFhf - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+    let mut rng = XorshiftRng::with_seed(1);
+    let mut deltas = PieceDeltas::new();
+    for fen in fens {
+        let mut pos = Position::from_fen(fen).unwrap();
+        evaluator.init(&pos);
+        for _ in 0..80 {
+            let expected = expected(&pos);
+            let actual = evaluator.quik_evaluate(&pos);
+            assert!(
+                (expected - actual).abs() <= tolerance,
+                "{} expected {} actual {}",
+                fen,
+                expected,
+                actual
+            );
+
+            let mut ml = MoveList::new();
+            ml.gen_legal_moves(&pos);
+            if ml.size == 0 {
+                break;
+            }
+            let mv = ml.moves[rng.next() as usize % ml.size].mv;
+            let mut child = pos.clone();
+            pos.make_move_with_deltas(mv, &mut child, &mut deltas);
+            evaluator.make_move(&child, &deltas);
+            pos = child;
+        }
+    }
+}
+
+#[test]
+fn test_quantized_nnue() {
+    unsafe { crate::chess::init() };
+    for output_buckets in [1, OUTPUT_BUCKETS] {
+        let weights = Weights {
+            weights: random_weights(Architecture::Plain, output_buckets),
+            output_buckets: output_buckets,
+        };
+        let mut quantized = QuantizedNnueEvaluationService::from_float(&weights).unwrap();
+        let mut float = NnueEvaluationService::with_weights(Arc::new(weights));
+        play_random_games(&mut quantized, |pos| float.evaluate(pos), TOLERANCE);
+    }
+}
+
+// Активации больше 2.0: целочисленная сеть не должна их ограничивать
+#[test]
+fn test_quantized_nnue_large_activations() {
+    unsafe { crate::chess::init() };
+    let mut weights = random_weights(Architecture::Plain, 1);
+    for b in &mut weights[INPUT_SIZE * HIDDEN_SIZE..(INPUT_SIZE + 1) * HIDDEN_SIZE] {
+        *b = 4.0 * b.abs() / 0.1;
+    }
+    let weights = Weights {
+        weights: weights,
+        output_buckets: 1,
+    };
+    let mut quantized = QuantizedNnueEvaluationService::from_float(&weights).unwrap();
+    let mut float = NnueEvaluationService::with_weights(Arc::new(weights));
+    play_random_games(&mut quantized, |pos| float.evaluate(pos), TOLERANCE);
+}
+
+// Веса, которые не помещаются в i16 или переполняют аккумулятор, не квантуются
+#[test]
+fn test_quantized_nnue_overflow() {
+    let quantize = |weights: Vec<f32>| {
+        QuantizedNnueEvaluationService::from_float(&Weights {
+            weights: weights,
+            output_buckets: 1,
+        })
+    };
+    assert!(quantize(random_weights(Architecture::Plain, 1)).is_ok());
+
+    let mut weights = random_weights(Architecture::Plain, 1);
+    weights[5 * HIDDEN_SIZE + 7] = 40.0;
+    assert!(quantize(weights).is_err());
+
+    let mut weights = random_weights(Architecture::Plain, 1);
+    weights[INPUT_SIZE * HIDDEN_SIZE] = 0.0;
+    // каждый вес помещается в i16, но сумма 32 весов - нет
+    for input in 0..32 {
+        weights[input * HIDDEN_SIZE] = 1.0;
+    }
+    assert!(quantize(weights).is_err());
+
+    let mut weights = random_weights(Architecture::Plain, 1);
+    weights[(INPUT_SIZE + 1) * HIDDEN_SIZE] = 2_000.0;
+    assert!(quantize(weights).is_err());
+}
+
+// Сеть из корня проекта (в репозиторий не входит):
+// cargo test test_quantized_nnue_default_net -- --ignored
+#[test]
+#[ignore = "needs n-30-5268.nn in the project root"]
+fn test_quantized_nnue_default_net() {
+    unsafe { crate::chess::init() };
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(super::DEFAULT_EVAL_FILE);
+    let network = load::load_weights(&path).unwrap();
+    let weights = Weights {
+        weights: network.weights,
+        output_buckets: network.output_buckets,
+    };
+    let mut quantized = QuantizedNnueEvaluationService::from_float(&weights).unwrap();
+    let mut float = NnueEvaluationService::with_weights(Arc::new(weights));
+    play_random_games(&mut quantized, |pos| float.evaluate(pos), TOLERANCE);
+}
+
+#[test]
+fn test_lazy_nnue() {
+    unsafe { crate::chess::init() };
+    let weights = Arc::new(Weights {
+        weights: random_weights(Architecture::Plain, 1),
+        output_buckets: 1,
+    });
+    let mut lazy = NnueEvaluationService::with_weights(weights.clone());
+    let mut refresh = NnueEvaluationService::with_weights(weights);
+
+    // оцениваем не каждый ход, а перед ходом пробуем и отменяем другой,
+    // чтобы в стеке оставались неприменённые изменения
+    let mut rng = XorshiftRng::with_seed(2);
+    let mut deltas = PieceDeltas::new();
+    let mut pos =
+        Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    lazy.init(&pos);
+    for ply in 0..60 {
+        if ply % 3 == 0 {
+            let expected = refresh.evaluate(&pos);
+            assert!((expected - lazy.quik_evaluate(&pos)).abs() <= 1);
+        }
+
+        let mut ml = MoveList::new();
+        ml.gen_legal_moves(&pos);
+        if ml.size == 0 {
+            break;
+        }
+        let mut child = pos.clone();
+        let tried = ml.moves[rng.next() as usize % ml.size].mv;
+        pos.make_move_with_deltas(tried, &mut child, &mut deltas);
+        lazy.make_move(&child, &deltas);
+        lazy.unmake_move();
+
+        let mv = ml.moves[rng.next() as usize % ml.size].mv;
+        pos.make_move_with_deltas(mv, &mut child, &mut deltas);
+        lazy.make_move(&child, &deltas);
+        pos = child;
+    }
+}
+
+#[test]
+fn test_halfka_nnue() {
+    unsafe { crate::chess::init() };
+    let weights = Arc::new(HalfKaWeights {
+        weights: random_weights(Architecture::HalfKa, OUTPUT_BUCKETS),
+        output_buckets: OUTPUT_BUCKETS,
+    });
+    let mut incremental = HalfKaEvaluationService::with_weights(weights.clone());
+    let mut refresh = HalfKaEvaluationService::with_weights(weights);
+    // полный пересчет отличается только порядком сложения float,
+    // но после округления и масштабирования оценки это до 2 сантипешек
+    play_random_games(&mut incremental, |pos| refresh.evaluate(pos), 2);
+
+    // оценка симметрична при отражении доски и смене цвета
+    let pos =
+        Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let mirrored =
+        Position::from_fen("r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQkq - 0 1")
+            .unwrap();
+    assert_eq!(refresh.evaluate(&pos), -refresh.evaluate(&mirrored));
+}
+
+#[test]
+fn test_simd_kernels() {
+    unsafe { crate::chess::init() };
+    let weights = Arc::new(Weights {
+        weights: random_weights(Architecture::Plain, 1),
+        output_buckets: 1,
+    });
+    let halfka_weights = Arc::new(HalfKaWeights {
+        weights: random_weights(Architecture::HalfKa, OUTPUT_BUCKETS),
+        output_buckets: OUTPUT_BUCKETS,
+    });
+
+    // позиции из случайных партий от начальных позиций Chess960
+    let mut rng = XorshiftRng::with_seed(3);
+    let mut positions = Vec::new();
+    for line in include_str!("../../tests/chess960.epd").lines().step_by(97) {
+        let mut pos = Position::from_fen(line.split(';').next().unwrap()).unwrap();
+        for _ in 0..40 {
+            let mut ml = MoveList::new();
+            ml.gen_legal_moves(&pos);
+            if ml.size == 0 {
+                break;
+            }
+            let mut child = pos.clone();
+            pos.make_move(ml.moves[rng.next() as usize % ml.size].mv, &mut child);
+            positions.push(child.clone());
+            pos = child;
+        }
+    }
+
+    let evaluate = |kernels: &'static simd::Kernels| -> Vec<isize> {
+        let mut nnue = NnueEvaluationService::with_weights(weights.clone());
+        nnue.kernels = kernels;
+        let mut halfka = HalfKaEvaluationService::with_weights(halfka_weights.clone());
+        halfka.kernels = kernels;
+        let mut quantized = QuantizedNnueEvaluationService::from_float(&weights).unwrap();
+        quantized.kernels = kernels;
+        return positions
+            .iter()
+            .flat_map(|pos| {
+                [
+                    nnue.evaluate(pos),
+                    halfka.evaluate(pos),
+                    quantized.evaluate(pos),
+                ]
+            })
+            .collect();
+    };
+    let available: &'static [simd::Kernels] = simd::available().leak();
+    let expected = evaluate(&available[0]);
+    for kernels in &available[1..] {
+        assert!(expected == evaluate(kernels), "{}", kernels.name);
+    }
+
+    // крайние значения i16: пара произведений почти 2^31, сумма около 2^38
+    let x: Vec<i16> = (0..HIDDEN_SIZE)
+        .map(|i| [i16::MAX, i16::MAX, i16::MIN, 1][i % 4])
+        .collect();
+    let w: Vec<i16> = (0..HIDDEN_SIZE)
+        .map(|i| [i16::MAX, i16::MAX, i16::MAX, i16::MIN][i % 4])
+        .collect();
+    let max = i16::MAX as i64;
+    let dot = (available[0].dot_relu_i16)(&x, &w);
+    assert_eq!(dot, 128 * (2 * max * max + i16::MIN as i64));
+    let mut rng = XorshiftRng::with_seed(7);
+    let random: Vec<i16> = (0..HIDDEN_SIZE)
+        .map(|_| (rng.next() >> 48) as i16 / 4)
+        .collect();
+    for kernels in &available[1..] {
+        assert_eq!((kernels.dot_relu_i16)(&x, &w), dot, "{}", kernels.name);
+        let mut acc = random.clone();
+        (kernels.add_i16)(&mut acc, &random);
+        (kernels.sub_i16)(&mut acc, &random[..]);
+        assert_eq!(acc, random, "{}", kernels.name);
+    }
+}
+
+#[test]
+fn test_output_buckets() {
+    unsafe { crate::chess::init() };
+    // выходные веса нулевые, bias выхода равен 100 * номер корзины
+    let mut weights = random_weights(Architecture::Plain, OUTPUT_BUCKETS);
+    let output_start = (1 + Architecture::Plain.input_size()) * HIDDEN_SIZE;
+    let bias_start = output_start + OUTPUT_BUCKETS * HIDDEN_SIZE;
+    weights[output_start..bias_start].fill(0_f32);
+    for (bucket, bias) in weights[bias_start..].iter_mut().enumerate() {
+        *bias = 100.0 * bucket as f32;
+    }
+    let mut nnue = NnueEvaluationService::with_weights(Arc::new(Weights {
+        weights: weights,
+        output_buckets: OUTPUT_BUCKETS,
+    }));
+
+    for (fen, expected) in [
+        (Position::INITIAL_POSITION_FEN, 700),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 200),
+        // оценка кешируется по ключу позиции, rule50 учитывает поиск
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 90 1", 200),
+        ("8/8/8/4k3/8/8/3QK3/8 w - - 0 1", 0),
+    ] {
+        let pos = Position::from_fen(fen).unwrap();
+        assert_eq!(nnue.evaluate(&pos), expected, "{}", fen);
+    }
+}
+
+#[test]
+fn test_weights_file() {
+    let network = Network {
+        architecture: Architecture::Plain,
+        output_buckets: 1,
+        weights: random_weights(Architecture::Plain, 1),
+    };
+    let bytes = load::encode_weights(&network);
+    assert_eq!(load::parse_weights(&bytes).unwrap(), network);
+
+    // старый формат: 24 байта без заголовка (заголовок v2 - 28 байт)
+    let mut legacy = bytes[4..].to_vec();
+    legacy[..24].fill(0);
+    assert_eq!(load::parse_weights(&legacy).unwrap(), network);
+
+    let halfka = Network {
+        architecture: Architecture::HalfKa,
+        output_buckets: OUTPUT_BUCKETS,
+        weights: random_weights(Architecture::HalfKa, OUTPUT_BUCKETS),
+    };
+    let bytes_halfka = load::encode_weights(&halfka);
+    assert_eq!(load::parse_weights(&bytes_halfka).unwrap(), halfka);
+    // архитектура в заголовке не соответствует размеру входов
+    let mut wrong_architecture = bytes_halfka.clone();
+    wrong_architecture[8] = 0;
+    assert!(matches!(
+        load::parse_weights(&wrong_architecture),
+        Err(LoadError::ArchitectureMismatch { .. })
+    ));
+
+    let truncated = &bytes[..bytes.len() - 1];
+    assert!(matches!(
+        load::parse_weights(truncated),
+        Err(LoadError::BadLength { .. })
+    ));
+    assert!(matches!(
+        load::parse_weights(&legacy[..legacy.len() - 4]),
+        Err(LoadError::BadMagic)
+    ));
+
+    let mut version = bytes.clone();
+    version[4] = 3;
+    assert!(matches!(
+        load::parse_weights(&version),
+        Err(LoadError::UnsupportedVersion(3))
+    ));
+
+    let mut hidden = bytes.clone();
+    hidden[16..20].copy_from_slice(&256_u32.to_le_bytes());
+    assert!(matches!(
+        load::parse_weights(&hidden),
+        Err(LoadError::ArchitectureMismatch {
+            hidden_size: 256,
+            ..
+        })
+    ));
+
+    let mut outputs = bytes.clone();
+    outputs[20..24].copy_from_slice(&3_u32.to_le_bytes());
+    assert!(matches!(
+        load::parse_weights(&outputs),
+        Err(LoadError::ArchitectureMismatch { output_size: 3, .. })
+    ));
+
+    let mut corrupted = bytes.clone();
+    corrupted[1_000] ^= 1;
+    assert!(matches!(
+        load::parse_weights(&corrupted),
+        Err(LoadError::BadChecksum)
+    ));
+}
@@ -0,0 +1,405 @@
+// Обучение сети 768x512x1 на CPU: mini-batch градиентный спуск (Adam),
+// батч делится между потоками, градиенты потоков складываются.
+// Внутри выход сети - логит: sigmoid(выход * масштаб оценки движком).
+// При записи в файл выходной слой делится на SIGMOID_SCALE, и сеть
+// считает оценку в сантипешках, как n-30-5268.nn.
+
+use super::{
+    Architecture, HIDDEN_SIZE, HIDDEN_WEIGHT_LIMIT, INPUT_SIZE, Network, OUTPUT_WEIGHT_LIMIT,
+    calc_net_input_index, load, material_scale, simd,
+};
+use crate::chess::{Position, XorshiftRng, bitboard};
+use std::path::Path;
+
+// Сантипешки в логит вероятности результата
+pub const SIGMOID_SCALE: f32 = 3.5 / 512.0;
+
+const SIZE: usize = Architecture::Plain.total_size(1);
+// начало выходного слоя
+const OUTPUT_START: usize = (1 + INPUT_SIZE) * HIDDEN_SIZE;
+const OUTPUT_BIAS: usize = OUTPUT_START + HIDDEN_SIZE;
+
+// Ограничения весов, при которых сеть можно квантовать (qnnue)
+const HIDDEN_CLIP: f32 = HIDDEN_WEIGHT_LIMIT;
+const OUTPUT_CLIP: f32 = OUTPUT_WEIGHT_LIMIT * SIGMOID_SCALE;
+
+// Позиция для обучения: входы сети и ожидаемая вероятность победы белых
+#[derive(Clone)]
+pub struct Sample {
+    inputs: [u16; 32],
+    size: u8,
+    // множитель, на который движок умножает выход сети: scale_eval и правило 50 ходов
+    scale: f32,
+    target: f32,
+}
+
+impl Sample {
+    pub fn new(pos: &Position, target: f32) -> Sample {
+        let mut sample = Sample {
+            inputs: [0; 32],
+            size: 0,
+            scale: material_scale(pos) as f32 / 160.0 * (200 - pos.rule50) as f32 / 200.0,
+            target: target,
+        };
+        let mut bb = pos.all_pieces();
+        while bb != 0 {
+            let sq = bitboard::first_one(bb);
+            bb &= bb - 1;
+            if let Some((side, piece)) = pos.side_piece_on_square(sq) {
+                sample.inputs[sample.size as usize] = calc_net_input_index(side, piece, sq) as u16;
+                sample.size += 1;
+            }
+        }
+        return sample;
+    }
+
+    fn inputs(&self) -> &[u16] {
+        return &self.inputs[..self.size as usize];
+    }
+}
+
+pub struct Trainer {
+    weights: Vec<f32>,
+    // градиенты по частям батча, у каждого потока свой буфер
+    gradients: Vec<Vec<f32>>,
+    m: Vec<f32>,
+    v: Vec<f32>,
+    t: i32,
+}
+
+impl Trainer {
+    const BETA1: f32 = 0.9;
+    const BETA2: f32 = 0.999;
+    const EPSILON: f32 = 1e-8;
+
+    // Случайные начальные веса
+    pub fn new(seed: u64) -> Trainer {
+        let mut rng = XorshiftRng::with_seed(seed);
+        let mut uniform = |max: f32| {
+            let x = (rng.next() >> 40) as f32 / (1 << 24) as f32;
+            (2.0 * x - 1.0) * max
+        };
+        let mut weights = vec![0_f32; SIZE];
+        for (i, w) in weights.iter_mut().enumerate() {
+            if i < OUTPUT_START - HIDDEN_SIZE {
+                *w = uniform(0.1);
+            } else if (OUTPUT_START..OUTPUT_BIAS).contains(&i) {
+                *w = uniform(1.0 / (HIDDEN_SIZE as f32).sqrt());
+            }
+        }
+        return Trainer::with_weights(weights);
+    }
+
+    // Продолжение обучения сети из файла
+    pub fn load(path: &Path) -> Result<Trainer, String> {
+        let network = load::load_weights(path).map_err(|e| e.to_string())?;
+        return Trainer::from_network(network).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    // Веса вне ограничений первый же шаг обучения обрезал бы молча
+    fn from_network(network: Network) -> Result<Trainer, String> {
+        if network.architecture != Architecture::Plain || network.output_buckets != 1 {
+            return Err(String::from("not a 768x512x1 network"));
+        }
+        for (i, &w) in network.weights.iter().enumerate() {
+            let limit = if i < OUTPUT_START {
+                HIDDEN_WEIGHT_LIMIT
+            } else if i < OUTPUT_BIAS {
+                OUTPUT_WEIGHT_LIMIT
+            } else {
+                continue;
+            };
+            // выходные веса при записи делятся на SIGMOID_SCALE с округлением
+            if w.abs() > limit * (1.0 + 1e-5) {
+                return Err(format!(
+                    "weight {} = {} exceeds training limit {}",
+                    i, w, limit
+                ));
+            }
+        }
+        let mut weights = network.weights;
+        for w in &mut weights[OUTPUT_START..] {
+            *w *= SIGMOID_SCALE;
+        }
+        return Ok(Trainer::with_weights(weights));
+    }
+
+    fn with_weights(weights: Vec<f32>) -> Trainer {
+        let threads = std::thread::available_parallelism().map_or(1, |x| x.get());
+        return Trainer {
+            weights: weights,
+            gradients: vec![vec![0_f32; SIZE]; threads],
+            m: vec![0_f32; SIZE],
+            v: vec![0_f32; SIZE],
+            t: 0,
+        };
+    }
+
+    // Веса в формате файла сети, оценка в сантипешках
+    pub fn network(&self) -> Network {
+        let mut weights = self.weights.clone();
+        for w in &mut weights[OUTPUT_START..] {
+            *w /= SIGMOID_SCALE;
+        }
+        return Network {
+            architecture: Architecture::Plain,
+            output_buckets: 1,
+            weights: weights,
+        };
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        return std::fs::write(path, load::encode_weights(&self.network()));
+    }
+
+    // Эпоха: перемешиваем позиции и проходим их батчами, возвращаем среднюю ошибку
+    pub fn train_epoch(
+        &mut self,
+        samples: &mut [Sample],
+        batch_size: usize,
+        learning_rate: f32,
+        rng: &mut XorshiftRng,
+    ) -> f64 {
+        for i in (1..samples.len()).rev() {
+            samples.swap(i, rng.next() as usize % (i + 1));
+        }
+        let mut loss = 0_f64;
+        for batch in samples.chunks(batch_size) {
+            loss += self.train_batch(batch, learning_rate) * batch.len() as f64;
+        }
+        return loss / samples.len().max(1) as f64;
+    }
+
+    fn train_batch(&mut self, batch: &[Sample], learning_rate: f32) -> f64 {
+        let loss = self.gradient(batch);
+        self.step(learning_rate);
+        return loss;
+    }
+
+    // Средняя ошибка без обучения, например на отложенных позициях
+    pub fn loss(&self, samples: &[Sample]) -> f64 {
+        let threads = self.gradients.len();
+        let chunk_size = samples.len().div_ceil(threads).max(1);
+        let weights = &self.weights;
+        let loss: f64 = std::thread::scope(|scope| {
+            let handles: Vec<_> = samples
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        let mut hidden = [0_f32; HIDDEN_SIZE];
+                        let mut loss = 0_f64;
+                        for sample in chunk {
+                            let prob =
+                                sigmoid(forward(weights, sample, &mut hidden) * sample.scale);
+                            loss += ((prob - sample.target) as f64).powi(2);
+                        }
+                        loss
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        return loss / samples.len().max(1) as f64;
+    }
+
+    // Средняя ошибка на батче, ее градиент остается в gradients[0]
+    fn gradient(&mut self, batch: &[Sample]) -> f64 {
+        let chunk_size = batch.len().div_ceil(self.gradients.len()).max(1);
+        let weights = &self.weights;
+        let loss: f64 = std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .gradients
+                .iter_mut()
+                .zip(batch.chunks(chunk_size))
+                .map(|(gradient, chunk)| {
+                    scope.spawn(move || {
+                        gradient.fill(0_f32);
+                        let mut loss = 0_f64;
+                        for sample in chunk {
+                            loss += backward(weights, sample, gradient);
+                        }
+                        loss
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        let used = batch.len().div_ceil(chunk_size);
+        let (total, rest) = self.gradients.split_first_mut().unwrap();
+        for gradient in &rest[..used - 1] {
+            for (t, &g) in total.iter_mut().zip(gradient.iter()) {
+                *t += g;
+            }
+        }
+        let n = batch.len() as f32;
+        for g in total.iter_mut() {
+            *g /= n;
+        }
+        return loss / batch.len() as f64;
+    }
+
+    fn step(&mut self, learning_rate: f32) {
+        self.t += 1;
+        let correction1 = 1.0 - Self::BETA1.powi(self.t);
+        let correction2 = 1.0 - Self::BETA2.powi(self.t);
+        for (i, &g) in self.gradients[0].iter().enumerate() {
+            self.m[i] = Self::BETA1 * self.m[i] + (1.0 - Self::BETA1) * g;
+            self.v[i] = Self::BETA2 * self.v[i] + (1.0 - Self::BETA2) * g * g;
+            let m = self.m[i] / correction1;
+            let v = self.v[i] / correction2;
+            let w = self.weights[i] - learning_rate * m / (v.sqrt() + Self::EPSILON);
+            self.weights[i] = if i < OUTPUT_START {
+                w.clamp(-HIDDEN_CLIP, HIDDEN_CLIP)
+            } else if i < OUTPUT_BIAS {
+                w.clamp(-OUTPUT_CLIP, OUTPUT_CLIP)
+            } else {
+                w
+            };
+        }
+    }
+}
+
+// Выход сети в единицах логита без масштаба, hidden - скрытый слой до ReLU
+fn forward(weights: &[f32], sample: &Sample, hidden: &mut [f32; HIDDEN_SIZE]) -> f32 {
+    let kernels = simd::kernels();
+    hidden.copy_from_slice(&weights[OUTPUT_START - HIDDEN_SIZE..OUTPUT_START]);
+    for &input in sample.inputs() {
+        let start = input as usize * HIDDEN_SIZE;
+        (kernels.add)(hidden, &weights[start..start + HIDDEN_SIZE]);
+    }
+    return (kernels.dot_relu)(hidden, &weights[OUTPUT_START..OUTPUT_BIAS]) + weights[OUTPUT_BIAS];
+}
+
+// Ошибка на позиции, ее градиент прибавляется к gradient
+fn backward(weights: &[f32], sample: &Sample, gradient: &mut [f32]) -> f64 {
+    let mut hidden = [0_f32; HIDDEN_SIZE];
+    let prob = sigmoid(forward(weights, sample, &mut hidden) * sample.scale);
+    let diff = prob - sample.target;
+    let d = 2.0 * diff * prob * (1.0 - prob) * sample.scale;
+    if d == 0.0 {
+        return (diff * diff) as f64;
+    }
+
+    gradient[OUTPUT_BIAS] += d;
+    // градиент по скрытому слою записываем на место его значений
+    let output_weights = &weights[OUTPUT_START..OUTPUT_BIAS];
+    let output_gradient = &mut gradient[OUTPUT_START..OUTPUT_BIAS];
+    for i in 0..HIDDEN_SIZE {
+        if hidden[i] > 0.0 {
+            output_gradient[i] += d * hidden[i];
+            hidden[i] = d * output_weights[i];
+        } else {
+            hidden[i] = 0.0;
+        }
+    }
+    for (g, &h) in gradient[OUTPUT_START - HIDDEN_SIZE..OUTPUT_START]
+        .iter_mut()
+        .zip(hidden.iter())
+    {
+        *g += h;
+    }
+    for &input in sample.inputs() {
+        let start = input as usize * HIDDEN_SIZE;
+        for (g, &h) in gradient[start..start + HIDDEN_SIZE]
+            .iter_mut()
+            .zip(hidden.iter())
+        {
+            *g += h;
+        }
+    }
+    return (diff * diff) as f64;
+}
+
+pub fn sigmoid(x: f32) -> f32 {
+    return 1.0 / (1.0 + (-x).exp());
+}
+
+#[test]
+fn test_trainer_limits() {
+    // сеть на границе ограничений загружается, за границей - нет
+    let mut trainer = Trainer::new(1);
+    trainer.weights[5] = HIDDEN_CLIP;
+    trainer.weights[OUTPUT_START - 1] = -HIDDEN_CLIP;
+    trainer.weights[OUTPUT_START + 3] = -OUTPUT_CLIP;
+    trainer.weights[OUTPUT_BIAS] = 500.0 * SIGMOID_SCALE;
+    assert!(Trainer::from_network(trainer.network()).is_ok());
+
+    for (index, w) in [(5, 0.3), (OUTPUT_START - 1, -0.3), (OUTPUT_START + 3, 70.0)] {
+        let mut network = trainer.network();
+        network.weights[index] = w;
+        assert!(Trainer::from_network(network).is_err(), "{}", index);
+    }
+}
+
+#[test]
+fn test_train() {
+    use super::{NnueEvaluationService, Weights, scale_eval};
+    use crate::chess::{MoveList, Piece, Side, Square};
+    use crate::domain::IEvaluator;
+    use std::sync::Arc;
+
+    unsafe { crate::chess::init() };
+    // позиции из случайных партий, результат - вероятность по классической оценке
+    let mut classical = crate::eval::make_eval("classical", "").unwrap();
+    let mut rng = XorshiftRng::with_seed(19);
+    let mut positions = Vec::new();
+    let mut samples = Vec::new();
+    for _ in 0..8 {
+        let mut pos = Position::from_fen(Position::INITIAL_POSITION_FEN).unwrap();
+        for _ in 0..60 {
+            let mut ml = MoveList::new();
+            ml.gen_legal_moves(&pos);
+            if ml.size == 0 {
+                break;
+            }
+            let mut child = pos.clone();
+            pos.make_move(ml.moves[rng.next() as usize % ml.size].mv, &mut child);
+            pos = child;
+            let target = sigmoid(SIGMOID_SCALE * classical.evaluate(&pos) as f32);
+            samples.push(Sample::new(&pos, target));
+            positions.push(pos.clone());
+        }
+    }
+
+    // сеть из файла считает то же, что и сеть при обучении
+    let mut trainer = Trainer::new(19);
+    let network = trainer.network();
+    let mut nnue = NnueEvaluationService::with_weights(Arc::new(Weights {
+        weights: network.weights,
+        output_buckets: 1,
+    }));
+    let mut hidden = [0_f32; HIDDEN_SIZE];
+    for (pos, sample) in positions.iter().zip(&samples) {
+        let output = forward(&trainer.weights, sample, &mut hidden) / SIGMOID_SCALE;
+        let expected = scale_eval(output as isize, pos, 1);
+        assert!((nnue.evaluate(pos) - expected).abs() <= 1);
+    }
+
+    // градиент совпадает с разностной производной: bias выхода, выходной вес,
+    // вес белого короля на e1
+    let loss = trainer.gradient(&samples);
+    let king = calc_net_input_index(Side::WHITE, Piece::KING, Square::E1);
+    for index in [OUTPUT_BIAS, OUTPUT_START + 7, king * HIDDEN_SIZE + 3] {
+        let analytic = trainer.gradients[0][index] as f64;
+        let eps = 1e-2;
+        trainer.weights[index] += eps;
+        let plus = trainer.loss(&samples);
+        trainer.weights[index] -= 2.0 * eps;
+        let minus = trainer.loss(&samples);
+        trainer.weights[index] += eps;
+        let numeric = (plus - minus) / (2.0 * eps as f64);
+        assert!(
+            (numeric - analytic).abs() < 0.05 * analytic.abs() + 1e-6,
+            "{} numeric {} analytic {}",
+            index,
+            numeric,
+            analytic
+        );
+    }
+
+    for _ in 0..20 {
+        trainer.train_epoch(&mut samples, 64, 1e-3, &mut rng);
+    }
+    assert!(trainer.loss(&samples) < loss);
+}
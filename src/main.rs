@@ -1,14 +1,15 @@
 #![allow(dead_code)]
-#![feature(float_algebraic)]
 #![allow(clippy::redundant_field_names)]
 #![allow(clippy::needless_return)]
 #![allow(clippy::upper_case_acronyms)]
 #![allow(clippy::collapsible_if)]
 
+mod book;
 mod chess;
 mod domain;
 mod engine;
 mod eval;
+mod tablebase;
 mod tests;
 mod uci;
 
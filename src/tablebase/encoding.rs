@@ -0,0 +1,159 @@
+// Таблицы для вычисления индекса позиции в файле Syzygy.
+// Порт инициализации из Stockfish (tbprobe.cpp).
+
+pub const TB_PIECES: usize = 7;
+
+pub struct IndexTables {
+    pub map_b1h1h7: [u64; 64],
+    pub map_a1d1d4: [u64; 64],
+    pub map_kk: [[u64; 64]; 10],
+    pub binomial: [[u64; 64]; 6],
+    pub map_pawns: [u64; 64],
+    pub lead_pawn_idx: [[u64; 64]; 6],
+    pub lead_pawns_size: [[u64; 4]; 6],
+}
+
+pub static INDEX_TABLES: IndexTables = init_index_tables();
+
+pub const fn file_of(sq: usize) -> usize {
+    return sq & 7;
+}
+
+pub const fn rank_of(sq: usize) -> usize {
+    return sq >> 3;
+}
+
+pub const fn off_a1h8(sq: usize) -> isize {
+    return rank_of(sq) as isize - file_of(sq) as isize;
+}
+
+pub const fn flip_file(sq: usize) -> usize {
+    return sq ^ 7;
+}
+
+pub const fn flip_rank(sq: usize) -> usize {
+    return sq ^ 56;
+}
+
+pub const fn edge_distance(file: usize) -> usize {
+    if file < 7 - file { file } else { 7 - file }
+}
+
+const fn near_king(s1: usize, s2: usize) -> bool {
+    let df = file_of(s1) as isize - file_of(s2) as isize;
+    let dr = rank_of(s1) as isize - rank_of(s2) as isize;
+    return df >= -1 && df <= 1 && dr >= -1 && dr <= 1;
+}
+
+const fn init_index_tables() -> IndexTables {
+    let mut res = IndexTables {
+        map_b1h1h7: [0; 64],
+        map_a1d1d4: [0; 64],
+        map_kk: [[0; 64]; 10],
+        binomial: [[0; 64]; 6],
+        map_pawns: [0; 64],
+        lead_pawn_idx: [[0; 64]; 6],
+        lead_pawns_size: [[0; 4]; 6],
+    };
+
+    // map_b1h1h7 encodes a square below a1-h8 diagonal to 0..27
+    let mut code = 0;
+    let mut s = 0;
+    while s < 64 {
+        if off_a1h8(s) < 0 {
+            res.map_b1h1h7[s] = code;
+            code += 1;
+        }
+        s += 1;
+    }
+
+    // map_a1d1d4 encodes a square in the a1-d1-d4 triangle to 0..9,
+    // diagonal squares are encoded as last ones
+    let mut code = 0;
+    let mut s = 0;
+    while s <= 27 {
+        if off_a1h8(s) < 0 && file_of(s) <= 3 {
+            res.map_a1d1d4[s] = code;
+            code += 1;
+        }
+        s += 1;
+    }
+    let mut s = 0;
+    while s <= 27 {
+        if off_a1h8(s) == 0 && file_of(s) <= 3 {
+            res.map_a1d1d4[s] = code;
+            code += 1;
+        }
+        s += 1;
+    }
+
+    // map_kk encodes all the 462 legal positions of two kings where the first
+    // is in the a1-d1-d4 triangle; both kings on the diagonal are encoded as last ones
+    let mut code = 0;
+    let mut pass = 0;
+    while pass < 2 {
+        let mut idx = 0;
+        while idx < 10 {
+            let mut s1 = 0;
+            while s1 <= 27 {
+                if res.map_a1d1d4[s1] == idx as u64 && (idx != 0 || s1 == 1) {
+                    let mut s2 = 0;
+                    while s2 < 64 {
+                        let both_on_diagonal = off_a1h8(s1) == 0 && off_a1h8(s2) == 0;
+                        if near_king(s1, s2) || off_a1h8(s1) == 0 && off_a1h8(s2) > 0 {
+                            // illegal position or first on diagonal, second above
+                        } else if both_on_diagonal == (pass == 1) {
+                            res.map_kk[idx][s2] = code;
+                            code += 1;
+                        }
+                        s2 += 1;
+                    }
+                }
+                s1 += 1;
+            }
+            idx += 1;
+        }
+        pass += 1;
+    }
+
+    res.binomial[0][0] = 1;
+    let mut n = 1;
+    while n < 64 {
+        let mut k = 0;
+        while k < 6 && k <= n {
+            let a = if k > 0 { res.binomial[k - 1][n - 1] } else { 0 };
+            let b = if k < n { res.binomial[k][n - 1] } else { 0 };
+            res.binomial[k][n] = a + b;
+            k += 1;
+        }
+        n += 1;
+    }
+
+    // map_pawns encodes squares a2-h7 to 0..47, the pawn with highest value
+    // is the leading pawn: the one nearest the edge and with lowest rank
+    let mut used_squares = 0;
+    let mut lead_pawns_cnt = 1;
+    while lead_pawns_cnt <= 5 {
+        let mut f = 0;
+        while f <= 3 {
+            let mut idx = 0;
+            let mut r = 1;
+            while r <= 6 {
+                let sq = r * 8 + f;
+                if lead_pawns_cnt == 1 {
+                    res.map_pawns[sq] = 47 - used_squares;
+                    res.map_pawns[flip_file(sq)] = 46 - used_squares;
+                    used_squares += 2;
+                }
+                res.lead_pawn_idx[lead_pawns_cnt][sq] = idx;
+                idx += res.binomial[lead_pawns_cnt - 1][res.map_pawns[sq] as usize];
+                r += 1;
+            }
+            res.lead_pawns_size[lead_pawns_cnt][f] = idx;
+            f += 1;
+        }
+        lead_pawns_cnt += 1;
+    }
+
+    return res;
+}
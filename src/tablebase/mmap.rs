@@ -0,0 +1,111 @@
+// Файл таблицы, отображенный в память только для чтения. Страницы читает ОС
+// при обращении, поэтому первое пробирование не читает весь файл посреди поиска.
+// Без mmap (не unix или 32 бита) файл читается целиком.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+pub struct Mmap {
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    ptr: *const u8,
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    len: usize,
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    bytes: Vec<u8>,
+}
+
+// Отображение только читается
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_SHARED: c_int = 1;
+
+    unsafe extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl Mmap {
+    pub fn open(path: &Path) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Mmap {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len: 0,
+            });
+        }
+        // после mmap файл можно закрыть, отображение остается
+        let ptr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(Mmap {
+            ptr: ptr as *const u8,
+            len: len,
+        });
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { sys::munmap(self.ptr as *mut _, self.len) };
+        }
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        return unsafe { std::slice::from_raw_parts(self.ptr, self.len) };
+    }
+}
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+impl Mmap {
+    pub fn open(path: &Path) -> io::Result<Mmap> {
+        let mut bytes = Vec::new();
+        io::Read::read_to_end(&mut File::open(path)?, &mut bytes)?;
+        return Ok(Mmap { bytes: bytes });
+    }
+}
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        return &self.bytes;
+    }
+}
@@ -0,0 +1,380 @@
+// Эндшпильные таблицы Syzygy.
+// Пробирование повторяет tbprobe.cpp из Stockfish.
+
+mod encoding;
+mod mmap;
+mod table;
+#[cfg(test)]
+mod tests;
+
+use crate::chess::{Move, MoveList, Piece, Position, Side, bitboard};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use table::{Table, TableType, TableValue};
+
+pub const WDL_LOSS: isize = -2;
+pub const WDL_BLESSED_LOSS: isize = -1;
+pub const WDL_DRAW: isize = 0;
+pub const WDL_CURSED_WIN: isize = 1;
+pub const WDL_WIN: isize = 2;
+
+const MAX_DTZ: isize = 1 << 18;
+
+pub struct Tablebases {
+    tables: HashMap<u64, Arc<Table>>,
+    max_pieces: usize,
+}
+
+#[derive(PartialEq)]
+enum ProbeState {
+    Ok,
+    // лучший ход взятие или ход пешкой, DTZ для позиции не хранится
+    ZeroingBestMove,
+}
+
+impl Tablebases {
+    pub fn new() -> Self {
+        return Tablebases {
+            tables: HashMap::new(),
+            max_pieces: 0,
+        };
+    }
+
+    // path - список каталогов через ':' (';' в Windows)
+    pub fn load(path: &str) -> Self {
+        let mut res = Tablebases::new();
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        for dir in path.split(separator) {
+            if dir.is_empty() || dir == "<empty>" {
+                continue;
+            }
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                eprintln!("failed read tablebase directory {dir}");
+                continue;
+            };
+            for entry in entries.flatten() {
+                res.add(&entry.path());
+            }
+        }
+        eprintln!(
+            "found {} tablebases, max pieces {}",
+            res.tables.iter().filter(|&(&key, t)| key == t.key).count(),
+            res.max_pieces
+        );
+        return res;
+    }
+
+    fn add(&mut self, path: &Path) {
+        if path.extension().is_none_or(|ext| ext != "rtbw") {
+            return;
+        }
+        let Some(counts) = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .and_then(parse_table_name)
+        else {
+            return;
+        };
+        let table = Arc::new(Table::new(&counts, path.to_path_buf()));
+        if self.tables.contains_key(&table.key) {
+            return;
+        }
+        self.max_pieces = self.max_pieces.max(table.piece_count);
+        self.tables.insert(table.key2, table.clone());
+        self.tables.insert(table.key, table);
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        return self.max_pieces;
+    }
+
+    // Таблицы не учитывают рокировки.
+    pub fn can_probe(&self, pos: &Position) -> bool {
+        return self.max_pieces != 0
+            && pos.castling_rights == 0
+            && bitboard::pop_count(pos.all_pieces()) as usize <= self.max_pieces;
+    }
+
+    // WDL с точки зрения стороны, делающей ход: -2..2
+    pub fn probe_wdl(&self, pos: &Position) -> Option<isize> {
+        return self.search(pos, false).map(|(wdl, _)| wdl);
+    }
+
+    // DTZ в полуходах со знаком результата, 0 для ничьей
+    pub fn probe_dtz(&self, pos: &Position) -> Option<isize> {
+        let (wdl, state) = self.search(pos, true)?;
+        if wdl == WDL_DRAW {
+            return Some(0);
+        }
+        // DTZ хранит произвольное значение, если лучший ход обнуляющий
+        if state == ProbeState::ZeroingBestMove {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(pos, TableType::Dtz, wdl) {
+            TableValue::Value(dtz) => {
+                let dtz = if wdl == WDL_BLESSED_LOSS || wdl == WDL_CURSED_WIN {
+                    dtz + 100
+                } else {
+                    dtz
+                };
+                return Some(dtz * wdl.signum());
+            }
+            TableValue::Fail => return None,
+            TableValue::ChangeStm => (),
+        }
+
+        // Таблица хранит другую сторону: ищем на один полуход,
+        // выбираем выигрывающий ход с минимальным DTZ.
+        let mut min_dtz = 0xFFFF;
+        let mut ml = MoveList::new();
+        ml.gen_legal_moves(pos);
+        let mut child = pos.clone();
+        for item in &ml.moves[..ml.size] {
+            let mv = item.mv;
+            let zeroing = is_zeroing(mv);
+            pos.make_move(mv, &mut child);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&child, false)?.0)
+            } else {
+                -self.probe_dtz(&child)?
+            };
+            if dtz == 1 && child.is_check() && !has_legal_moves(&child) {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        // нет ходов - мат
+        return Some(if min_dtz == 0xFFFF { -1 } else { min_dtz });
+    }
+
+    // Оставляет ходы корня, сохраняющие лучший результат.
+    // Если нет DTZ, ходы ранжируются по WDL.
+    pub fn root_probe(&self, pos: &Position, moves: &[Move], repeats: &[u64]) -> Option<Vec<Move>> {
+        if !self.can_probe(pos) || moves.is_empty() {
+            return None;
+        }
+        let ranks = self
+            .root_ranks_dtz(pos, moves, repeats)
+            .or_else(|| self.root_ranks_wdl(pos, moves))?;
+        let best = *ranks.iter().max().unwrap();
+        let res = moves
+            .iter()
+            .zip(ranks.iter())
+            .filter(|&(_, &rank)| rank == best)
+            .map(|(&mv, _)| mv)
+            .collect();
+        return Some(res);
+    }
+
+    fn root_ranks_dtz(
+        &self,
+        pos: &Position,
+        moves: &[Move],
+        repeats: &[u64],
+    ) -> Option<Vec<isize>> {
+        let table = self.tables.get(&material_key(pos))?;
+        if !table.has_dtz() {
+            return None;
+        }
+        let cnt50 = pos.rule50;
+        let rep = repeats.contains(&pos.key);
+        let mut ranks = Vec::with_capacity(moves.len());
+        let mut child = pos.clone();
+        for &mv in moves {
+            if !pos.make_move(mv, &mut child) {
+                return None;
+            }
+            let mut dtz = if child.rule50 == 0 {
+                dtz_before_zeroing(-self.probe_wdl(&child)?)
+            } else if repeats.contains(&child.key) || child.rule50 > 100 {
+                0
+            } else {
+                let dtz = -self.probe_dtz(&child)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && child.is_check() && !has_legal_moves(&child) {
+                dtz = 1;
+            }
+            // Выигрыши, успевающие до правила 50 ходов, лучше остальных.
+            // Проигрыши: чем дальше обнуление, тем лучше.
+            let rank = if dtz > 0 {
+                if dtz + cnt50 <= 99 && !rep {
+                    MAX_DTZ - dtz
+                } else {
+                    MAX_DTZ / 2 - (dtz + cnt50)
+                }
+            } else if dtz < 0 {
+                if -dtz * 2 + cnt50 < 100 {
+                    -MAX_DTZ - dtz
+                } else {
+                    -MAX_DTZ / 2 + (-dtz + cnt50)
+                }
+            } else {
+                0
+            };
+            ranks.push(rank);
+        }
+        return Some(ranks);
+    }
+
+    fn root_ranks_wdl(&self, pos: &Position, moves: &[Move]) -> Option<Vec<isize>> {
+        let mut ranks = Vec::with_capacity(moves.len());
+        let mut child = pos.clone();
+        for &mv in moves {
+            if !pos.make_move(mv, &mut child) {
+                return None;
+            }
+            let wdl = -self.probe_wdl(&child)?;
+            // спасенный проигрыш и испорченный выигрыш при правиле 50 ходов - ничьи
+            let rank = match wdl {
+                WDL_WIN => 2,
+                WDL_LOSS => -2,
+                _ => 0,
+            };
+            ranks.push(rank);
+        }
+        return Some(ranks);
+    }
+
+    // Поиск по взятиям (и ходам пешек для DTZ): таблицы не учитывают взятие на проходе,
+    // а для обнуляющего лучшего хода DTZ не хранится.
+    fn search(&self, pos: &Position, check_zeroing_moves: bool) -> Option<(isize, ProbeState)> {
+        let mut best_value = WDL_LOSS;
+        let mut ml = MoveList::new();
+        ml.gen_legal_moves(pos);
+        let mut move_count = 0;
+        let mut child = pos.clone();
+        for item in &ml.moves[..ml.size] {
+            let mv = item.mv;
+            if mv.captured_piece() == Piece::NONE
+                && (!check_zeroing_moves || mv.moving_piece() != Piece::PAWN)
+            {
+                continue;
+            }
+            move_count += 1;
+            pos.make_move(mv, &mut child);
+            let value = -self.search(&child, false)?.0;
+            if value > best_value {
+                best_value = value;
+                if value >= WDL_WIN {
+                    return Some((value, ProbeState::ZeroingBestMove));
+                }
+            }
+        }
+
+        // Все ходы уже просмотрены, значение в таблице может быть неверным.
+        let no_more_moves = move_count != 0 && move_count == ml.size;
+        let value = if no_more_moves {
+            best_value
+        } else {
+            match self.probe_table(pos, TableType::Wdl, 0) {
+                TableValue::Value(value) => value,
+                _ => return None,
+            }
+        };
+
+        if best_value >= value {
+            let state = if best_value > WDL_DRAW || no_more_moves {
+                ProbeState::ZeroingBestMove
+            } else {
+                ProbeState::Ok
+            };
+            return Some((best_value, state));
+        }
+        return Some((value, ProbeState::Ok));
+    }
+
+    fn probe_table(&self, pos: &Position, table_type: TableType, wdl: isize) -> TableValue {
+        // KvK
+        if pos.all_pieces() == pos.kings {
+            return TableValue::Value(WDL_DRAW);
+        }
+        return match self.tables.get(&material_key(pos)) {
+            Some(table) => table.probe(pos, table_type, wdl),
+            None => TableValue::Fail,
+        };
+    }
+}
+
+fn is_zeroing(mv: Move) -> bool {
+    return mv.captured_piece() != Piece::NONE || mv.moving_piece() == Piece::PAWN;
+}
+
+fn has_legal_moves(pos: &Position) -> bool {
+    let mut ml = MoveList::new();
+    ml.gen_legal_moves(pos);
+    return ml.size != 0;
+}
+
+fn dtz_before_zeroing(wdl: isize) -> isize {
+    return match wdl {
+        WDL_WIN => 1,
+        WDL_CURSED_WIN => 101,
+        WDL_BLESSED_LOSS => -101,
+        WDL_LOSS => -1,
+        _ => 0,
+    };
+}
+
+// Количество фигур каждой стороны упаковано по 4 бита.
+fn counts_key(white: &[usize; Piece::PIECE_NB], black: &[usize; Piece::PIECE_NB]) -> u64 {
+    let mut key = 0_u64;
+    for piece in Piece::PAWN as usize..=Piece::KING as usize {
+        key |= (white[piece] as u64) << (4 * piece);
+        key |= (black[piece] as u64) << (4 * (piece + Piece::PIECE_NB));
+    }
+    return key;
+}
+
+fn material_key(pos: &Position) -> u64 {
+    let mut counts = [[0; Piece::PIECE_NB]; Side::SIDE_NB];
+    for side in [Side::WHITE, Side::BLACK] {
+        let own = pos.colours(side);
+        let pieces = [
+            (Piece::PAWN, pos.pawns),
+            (Piece::KNIGHT, pos.knights),
+            (Piece::BISHOP, pos.bishops),
+            (Piece::ROOK, pos.rooks),
+            (Piece::QUEEN, pos.queens),
+            (Piece::KING, pos.kings),
+        ];
+        for (piece, b) in pieces {
+            counts[side.index()][piece as usize] = bitboard::pop_count(b & own) as usize;
+        }
+    }
+    return counts_key(&counts[0], &counts[1]);
+}
+
+// "KRPvKR" -> количество фигур белых и черных
+fn parse_table_name(name: &str) -> Option<[[usize; Piece::PIECE_NB]; Side::SIDE_NB]> {
+    let (white, black) = name.split_once('v')?;
+    let mut counts = [[0; Piece::PIECE_NB]; Side::SIDE_NB];
+    for (side, s) in [white, black].into_iter().enumerate() {
+        for ch in s.chars() {
+            let piece = match ch {
+                'P' => Piece::PAWN,
+                'N' => Piece::KNIGHT,
+                'B' => Piece::BISHOP,
+                'R' => Piece::ROOK,
+                'Q' => Piece::QUEEN,
+                'K' => Piece::KING,
+                _ => return None,
+            };
+            counts[side][piece as usize] += 1;
+        }
+        if counts[side][Piece::KING as usize] != 1 {
+            return None;
+        }
+    }
+    let piece_count: usize = counts.iter().flatten().sum();
+    if piece_count > encoding::TB_PIECES {
+        return None;
+    }
+    return Some(counts);
+}
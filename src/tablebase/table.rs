@@ -0,0 +1,726 @@
+// Разбор и распаковка файлов Syzygy (.rtbw/.rtbz).
+// Формат и алгоритмы повторяют tbprobe.cpp из Stockfish.
+
+use super::encoding::{self, INDEX_TABLES, TB_PIECES};
+use super::mmap::Mmap;
+use crate::chess::{Piece, Position, Side, bitboard};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+pub const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// Флаги PairsData
+pub const FLAG_STM: u8 = 1;
+pub const FLAG_MAPPED: u8 = 2;
+pub const FLAG_WIN_PLIES: u8 = 4;
+pub const FLAG_LOSS_PLIES: u8 = 8;
+pub const FLAG_WIDE: u8 = 16;
+pub const FLAG_SINGLE_VALUE: u8 = 128;
+
+// Флаги заголовка файла
+pub const HEADER_SPLIT: u8 = 1;
+pub const HEADER_HAS_PAWNS: u8 = 2;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TableType {
+    Wdl,
+    Dtz,
+}
+
+#[derive(Default, Clone)]
+struct PairsData {
+    flags: u8,
+    size_of_block: usize,
+    span: usize,
+    num_blocks: usize,
+    min_sym_len: usize,
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_length: usize,
+    block_length_size: usize,
+    data: usize,
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    pieces: [u8; TB_PIECES],
+    map_idx: [usize; 4],
+}
+
+struct TableData {
+    bytes: Mmap,
+    // [сторона][вертикаль ведущей пешки]
+    items: Vec<Vec<PairsData>>,
+    map: usize,
+}
+
+// Описание окончания, например KRvK. Файлы отображаются в память при первом обращении.
+pub struct Table {
+    pub key: u64,
+    pub key2: u64,
+    pub piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pawn_count: [usize; 2],
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<TableData>>,
+    dtz: OnceLock<Option<TableData>>,
+}
+
+// Результат чтения таблицы. ChangeStm: DTZ хранит только другую сторону.
+pub enum TableValue {
+    Value(isize),
+    ChangeStm,
+    Fail,
+}
+
+impl Table {
+    // counts[side][piece]
+    pub fn new(counts: &[[usize; Piece::PIECE_NB]; 2], wdl_path: PathBuf) -> Self {
+        let has_pawns = counts[0][Piece::PAWN as usize] + counts[1][Piece::PAWN as usize] > 0;
+        let mut has_unique_pieces = false;
+        for side_counts in counts.iter() {
+            for &n in &side_counts[Piece::PAWN as usize..Piece::KING as usize] {
+                if n == 1 {
+                    has_unique_pieces = true;
+                }
+            }
+        }
+        // ведущий цвет - сторона с меньшим числом пешек
+        let white_pawns = counts[0][Piece::PAWN as usize];
+        let black_pawns = counts[1][Piece::PAWN as usize];
+        let c = black_pawns == 0 || white_pawns != 0 && black_pawns >= white_pawns;
+        let pawn_count = if c {
+            [white_pawns, black_pawns]
+        } else {
+            [black_pawns, white_pawns]
+        };
+        let dtz_path = wdl_path.with_extension("rtbz");
+        return Table {
+            key: super::counts_key(&counts[0], &counts[1]),
+            key2: super::counts_key(&counts[1], &counts[0]),
+            piece_count: counts.iter().flatten().sum(),
+            has_pawns: has_pawns,
+            has_unique_pieces: has_unique_pieces,
+            pawn_count: pawn_count,
+            wdl_path: wdl_path,
+            dtz_path: if dtz_path.exists() {
+                Some(dtz_path)
+            } else {
+                None
+            },
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        };
+    }
+
+    pub fn has_dtz(&self) -> bool {
+        return self.dtz_path.is_some();
+    }
+
+    fn data(&self, table_type: TableType) -> Option<&TableData> {
+        let (cell, path) = match table_type {
+            TableType::Wdl => (&self.wdl, Some(&self.wdl_path)),
+            TableType::Dtz => (&self.dtz, self.dtz_path.as_ref()),
+        };
+        return cell
+            .get_or_init(|| {
+                let path = path?;
+                let res = Mmap::open(path)
+                    .ok()
+                    .and_then(|bytes| self.parse(bytes, table_type));
+                if res.is_none() {
+                    eprintln!("failed load tablebase {}", path.display());
+                }
+                res
+            })
+            .as_ref();
+    }
+
+    fn sides(&self, table_type: TableType) -> usize {
+        if table_type == TableType::Wdl && self.key != self.key2 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn parse(&self, bytes: Mmap, table_type: TableType) -> Option<TableData> {
+        let magic = match table_type {
+            TableType::Wdl => WDL_MAGIC,
+            TableType::Dtz => DTZ_MAGIC,
+        };
+        if bytes.len() < 5 || bytes[..4] != magic {
+            return None;
+        }
+        let r = Reader { bytes: &bytes };
+
+        let mut pos = 4;
+        let header = r.u8(pos)?;
+        if (header & HEADER_HAS_PAWNS != 0) != self.has_pawns {
+            return None;
+        }
+        pos += 1;
+
+        let sides = self.sides(table_type);
+        let max_file = if self.has_pawns { 3 } else { 0 };
+        let pp = self.has_pawns && self.pawn_count[1] != 0;
+        let mut items = vec![vec![PairsData::default(); max_file + 1]; sides];
+
+        for f in 0..=max_file {
+            let b0 = r.u8(pos)?;
+            let b1 = if pp { r.u8(pos + 1)? } else { 0xFF };
+            let order = [[b0 & 0xF, b1 & 0xF], [b0 >> 4, b1 >> 4]];
+            pos += 1 + pp as usize;
+            for k in 0..self.piece_count {
+                let b = r.u8(pos)?;
+                for (i, side_items) in items.iter_mut().enumerate() {
+                    side_items[f].pieces[k] = if i != 0 { b >> 4 } else { b & 0xF };
+                }
+                pos += 1;
+            }
+            for (i, side_items) in items.iter_mut().enumerate() {
+                self.set_groups(&mut side_items[f], order[i], f);
+            }
+        }
+        pos += pos & 1;
+
+        for f in 0..=max_file {
+            for side_items in items.iter_mut() {
+                pos = set_sizes(&r, &mut side_items[f], pos)?;
+            }
+        }
+
+        let map = pos;
+        if table_type == TableType::Dtz {
+            for d in items[0].iter_mut() {
+                if d.flags & FLAG_MAPPED != 0 {
+                    if d.flags & FLAG_WIDE != 0 {
+                        pos += pos & 1;
+                        for i in 0..4 {
+                            d.map_idx[i] = (pos - map) / 2 + 1;
+                            pos += 2 * r.u16(pos)? as usize + 2;
+                        }
+                    } else {
+                        for i in 0..4 {
+                            d.map_idx[i] = pos - map + 1;
+                            pos += r.u8(pos)? as usize + 1;
+                        }
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for f in 0..=max_file {
+            for side_items in items.iter_mut() {
+                let d = &mut side_items[f];
+                d.sparse_index = pos;
+                pos += d.sparse_index_size * 6;
+            }
+        }
+        for f in 0..=max_file {
+            for side_items in items.iter_mut() {
+                let d = &mut side_items[f];
+                d.block_length = pos;
+                pos += d.block_length_size * 2;
+            }
+        }
+        for f in 0..=max_file {
+            for side_items in items.iter_mut() {
+                let d = &mut side_items[f];
+                pos = (pos + 0x3F) & !0x3F;
+                d.data = pos;
+                pos += d.num_blocks * d.size_of_block;
+            }
+        }
+        if pos > bytes.len() {
+            return None;
+        }
+
+        return Some(TableData {
+            bytes: bytes,
+            items: items,
+            map: map,
+        });
+    }
+
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], f: usize) {
+        let mut n = 0;
+        let mut first_len: isize = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        d.group_len[n] = 1;
+        // Number of pieces per group is stored in group_len, for instance in KRKN
+        // the encoder will default on '111', so group_len will be (3, 1).
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let pp = self.has_pawns && self.pawn_count[1] != 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+        let mut idx = 1_u64;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                // leading pawns or pieces
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    INDEX_TABLES.lead_pawns_size[d.group_len[0]][f]
+                } else if self.has_unique_pieces {
+                    31_332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                // remaining pawns
+                d.group_idx[1] = idx;
+                idx *= INDEX_TABLES.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                // remaining pieces
+                d.group_idx[next] = idx;
+                idx *= INDEX_TABLES.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    // wdl нужен только для DTZ: по нему выбирается карта значений
+    pub fn probe(&self, pos: &Position, table_type: TableType, wdl: isize) -> TableValue {
+        let Some(data) = self.data(table_type) else {
+            return TableValue::Fail;
+        };
+        let Some((stm, tb_file, idx)) = self.index(pos, data, table_type) else {
+            return TableValue::ChangeStm;
+        };
+        let r = Reader { bytes: &data.bytes };
+        let d = &data.items[stm % data.items.len()][tb_file];
+        let Some(value) = decompress_pairs(&r, d, idx) else {
+            return TableValue::Fail;
+        };
+        if table_type == TableType::Wdl {
+            return TableValue::Value(value as isize - 2);
+        }
+        return match map_dtz_score(&r, data, d, value, wdl) {
+            Some(value) => TableValue::Value(value),
+            None => TableValue::Fail,
+        };
+    }
+
+    // (сторона, вертикаль, индекс); None, если DTZ хранит только другую сторону
+    fn index(
+        &self,
+        pos: &Position,
+        data: &TableData,
+        table_type: TableType,
+    ) -> Option<(usize, usize, u64)> {
+        let mut squares = [0_usize; TB_PIECES];
+        let mut pieces = [0_u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns_cnt = 0;
+        let mut lead_pawns = 0_u64;
+        let mut tb_file = 0;
+
+        // If both sides have the same pieces, the table stores only the
+        // 'white to move' case, so we switch colors for black to move.
+        let symmetric_black_to_move = self.key == self.key2 && pos.side_to_move == Side::BLACK;
+        // Tables are calculated for white as the stronger side.
+        let black_stronger = super::material_key(pos) != self.key;
+        let flip = symmetric_black_to_move || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip as usize) ^ pos.side_to_move.index();
+
+        let tables = &INDEX_TABLES;
+        if self.has_pawns {
+            // Pawns of the reference colour are the leading ones in all four tables.
+            let pc = data.items[0][0].pieces[0] ^ flip_color;
+            let side = if pc & 8 != 0 {
+                Side::BLACK
+            } else {
+                Side::WHITE
+            };
+            lead_pawns = pos.pawns & pos.colours(side);
+            let mut b = lead_pawns;
+            while b != 0 {
+                squares[size] = bitboard::first_one(b).index() ^ flip_squares;
+                size += 1;
+                b &= b - 1;
+            }
+            lead_pawns_cnt = size;
+            let mut max_index = 0;
+            for i in 1..lead_pawns_cnt {
+                if tables.map_pawns[squares[i]] > tables.map_pawns[squares[max_index]] {
+                    max_index = i;
+                }
+            }
+            squares.swap(0, max_index);
+            tb_file = encoding::edge_distance(encoding::file_of(squares[0]));
+        }
+
+        // DTZ tables are one-sided
+        if table_type == TableType::Dtz {
+            let flags = data.items[0][tb_file].flags;
+            if (flags & FLAG_STM) as usize != stm && (self.key != self.key2 || self.has_pawns) {
+                return None;
+            }
+        }
+
+        let mut b = pos.all_pieces() ^ lead_pawns;
+        while b != 0 {
+            let sq = bitboard::first_one(b);
+            b &= b - 1;
+            let (side, piece) = pos.side_piece_on_square(sq).unwrap();
+            squares[size] = sq.index() ^ flip_squares;
+            pieces[size] = (piece as u8 + 8 * side.index() as u8) ^ flip_color;
+            size += 1;
+        }
+
+        let d = &data.items[stm % data.items.len()][tb_file];
+
+        // Reorder the pieces to have the same sequence as the one stored in pieces.
+        for i in lead_pawns_cnt..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // The square of the lead piece must be in the triangle A1-D1-D4.
+        if encoding::file_of(squares[0]) > 3 {
+            for sq in squares[..size].iter_mut() {
+                *sq = encoding::flip_file(*sq);
+            }
+        }
+
+        let mut idx: u64;
+        if self.has_pawns {
+            idx = tables.lead_pawn_idx[lead_pawns_cnt][squares[0]];
+            squares[1..lead_pawns_cnt].sort_by_key(|&sq| tables.map_pawns[sq]);
+            for (i, &sq) in squares[..lead_pawns_cnt].iter().enumerate().skip(1) {
+                idx += tables.binomial[i][tables.map_pawns[sq] as usize];
+            }
+        } else {
+            // Without pawns the leading piece is also below RANK_5.
+            if encoding::rank_of(squares[0]) > 3 {
+                for sq in squares[..size].iter_mut() {
+                    *sq = encoding::flip_rank(*sq);
+                }
+            }
+            // The first piece of the leading group not on the A1-H8 diagonal
+            // is mapped below the diagonal.
+            for i in 0..d.group_len[0] {
+                let off = encoding::off_a1h8(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for sq in squares[i..size].iter_mut() {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let adjust1 = (squares[1] > squares[0]) as usize;
+                let adjust2 =
+                    (squares[2] > squares[0]) as usize + (squares[2] > squares[1]) as usize;
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                idx = if encoding::off_a1h8(s0) != 0 {
+                    (tables.map_a1d1d4[s0] * 63 + (s1 - adjust1) as u64) * 62
+                        + (s2 - adjust2) as u64
+                } else if encoding::off_a1h8(s1) != 0 {
+                    (6 * 63 + encoding::rank_of(s0) as u64 * 28 + tables.map_b1h1h7[s1]) * 62
+                        + (s2 - adjust2) as u64
+                } else if encoding::off_a1h8(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + encoding::rank_of(s0) as u64 * 7 * 28
+                        + (encoding::rank_of(s1) - adjust1) as u64 * 28
+                        + tables.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + encoding::rank_of(s0) as u64 * 7 * 6
+                        + (encoding::rank_of(s1) - adjust1) as u64 * 6
+                        + (encoding::rank_of(s2) - adjust2) as u64
+                };
+            } else {
+                idx = tables.map_kk[tables.map_a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        // Encode remaining pawns and then pieces according to square, in ascending order.
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] != 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let group_end = group_start + d.group_len[next];
+            squares[group_start..group_end].sort();
+            let mut n = 0_u64;
+            for i in 0..d.group_len[next] {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|&&s| sq > s).count();
+                let k = sq - adjust - if remaining_pawns { 8 } else { 0 };
+                n += tables.binomial[i + 1][k];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start = group_end;
+            next += 1;
+        }
+
+        return Some((stm, tb_file, idx));
+    }
+}
+
+fn set_sizes(r: &Reader, d: &mut PairsData, mut pos: usize) -> Option<usize> {
+    d.flags = r.u8(pos)?;
+    pos += 1;
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        // здесь хранится единственное значение
+        d.min_sym_len = r.u8(pos)? as usize;
+        return Some(pos + 1);
+    }
+
+    let n = d
+        .group_len
+        .iter()
+        .position(|&x| x == 0)
+        .unwrap_or(TB_PIECES);
+    let tb_size = d.group_idx[n];
+
+    d.size_of_block = 1 << r.u8(pos)?;
+    d.span = 1 << r.u8(pos + 1)?;
+    d.sparse_index_size = tb_size.div_ceil(d.span as u64) as usize;
+    let padding = r.u8(pos + 2)? as usize;
+    d.num_blocks = r.u32(pos + 3)? as usize;
+    d.block_length_size = d.num_blocks + padding;
+    let max_sym_len = r.u8(pos + 7)? as usize;
+    d.min_sym_len = r.u8(pos + 8)? as usize;
+    pos += 9;
+    if d.min_sym_len == 0 || max_sym_len < d.min_sym_len || max_sym_len > 32 {
+        return None;
+    }
+    d.lowest_sym = pos;
+    let base_size = max_sym_len - d.min_sym_len + 1;
+    d.base64 = vec![0; base_size];
+
+    // Canonical Huffman code: longer symbols have a lower numeric value.
+    for i in (0..base_size - 1).rev() {
+        let a = r.u16(d.lowest_sym + 2 * i)? as u64;
+        let b = r.u16(d.lowest_sym + 2 * (i + 1))? as u64;
+        d.base64[i] = d.base64[i + 1].wrapping_add(a).wrapping_sub(b) / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        *base <<= 64 - i - d.min_sym_len;
+    }
+
+    pos += base_size * 2;
+    let sym_count = r.u16(pos)? as usize;
+    pos += 2;
+    d.btree = pos;
+    d.symlen = vec![0; sym_count];
+    let mut visited = vec![false; sym_count];
+    for sym in 0..sym_count {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(r, d, sym, &mut visited)?;
+        }
+    }
+    return Some(pos + sym_count * 3 + (sym_count & 1));
+}
+
+// Recursive Pairing: символ раскрывается в пару символов left, right
+fn set_symlen(r: &Reader, d: &mut PairsData, sym: usize, visited: &mut [bool]) -> Option<u8> {
+    visited[sym] = true;
+    let (left, right) = r.lr(d.btree + 3 * sym)?;
+    if right == 0xFFF {
+        return Some(0);
+    }
+    if left >= visited.len() || right >= visited.len() {
+        return None;
+    }
+    if !visited[left] {
+        d.symlen[left] = set_symlen(r, d, left, visited)?;
+    }
+    if !visited[right] {
+        d.symlen[right] = set_symlen(r, d, right, visited)?;
+    }
+    return Some(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1));
+}
+
+fn decompress_pairs(r: &Reader, d: &PairsData, idx: u64) -> Option<usize> {
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        return Some(d.min_sym_len);
+    }
+
+    // SparseIndex[k] хранит блок и смещение значения с индексом k * span + span / 2
+    let k = (idx / d.span as u64) as usize;
+    if k >= d.sparse_index_size {
+        return None;
+    }
+    let mut block = r.u32(d.sparse_index + 6 * k)? as usize;
+    let mut offset = r.u16(d.sparse_index + 6 * k + 4)? as isize;
+    offset += (idx % d.span as u64) as isize - (d.span / 2) as isize;
+
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += r.u16(d.block_length + 2 * block)? as isize + 1;
+    }
+    loop {
+        if block >= d.block_length_size {
+            return None;
+        }
+        let len = r.u16(d.block_length + 2 * block)? as isize;
+        if offset <= len {
+            break;
+        }
+        offset -= len + 1;
+        block += 1;
+    }
+    if block >= d.num_blocks {
+        return None;
+    }
+
+    let mut ptr = d.data + block * d.size_of_block;
+    let mut buf64 = r.u64_be(ptr)?;
+    ptr += 8;
+    let mut buf64_size = 64;
+    let mut sym;
+    loop {
+        let mut len = 0;
+        while len + 1 < d.base64.len() && buf64 < d.base64[len] {
+            len += 1;
+        }
+        sym = ((buf64 - d.base64[len]) >> (64 - len - d.min_sym_len)) as usize;
+        sym += r.u16(d.lowest_sym + 2 * len)? as usize;
+        if sym >= d.symlen.len() {
+            return None;
+        }
+        if offset < d.symlen[sym] as isize + 1 {
+            break;
+        }
+        offset -= d.symlen[sym] as isize + 1;
+        len += d.min_sym_len;
+        buf64 <<= len;
+        buf64_size -= len;
+        if buf64_size <= 32 {
+            buf64_size += 32;
+            buf64 |= (r.u32_be(ptr)? as u64) << (64 - buf64_size);
+            ptr += 4;
+        }
+    }
+
+    while d.symlen[sym] != 0 {
+        let (left, right) = r.lr(d.btree + 3 * sym)?;
+        if offset < d.symlen[left] as isize + 1 {
+            sym = left;
+        } else {
+            offset -= d.symlen[left] as isize + 1;
+            sym = right;
+        }
+    }
+    let (value, _) = r.lr(d.btree + 3 * sym)?;
+    return Some(value);
+}
+
+fn map_dtz_score(
+    r: &Reader,
+    data: &TableData,
+    d: &PairsData,
+    value: usize,
+    wdl: isize,
+) -> Option<isize> {
+    const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+    let mut value = value;
+    if d.flags & FLAG_MAPPED != 0 {
+        let idx = d.map_idx[WDL_MAP[(wdl + 2) as usize]];
+        value = if d.flags & FLAG_WIDE != 0 {
+            r.u16(data.map + 2 * (idx + value))? as usize
+        } else {
+            r.u8(data.map + idx + value)? as usize
+        };
+    }
+    // DTZ tables store distance to zero in number of moves or plies.
+    let mut value = value as isize;
+    if wdl == 2 && d.flags & FLAG_WIN_PLIES == 0
+        || wdl == -2 && d.flags & FLAG_LOSS_PLIES == 0
+        || wdl == 1
+        || wdl == -1
+    {
+        value *= 2;
+    }
+    return Some(value + 1);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn u8(&self, pos: usize) -> Option<u8> {
+        return self.bytes.get(pos).copied();
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let b = self.bytes.get(pos..pos + 2)?;
+        return Some(u16::from_le_bytes([b[0], b[1]]));
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let b = self.bytes.get(pos..pos + 4)?;
+        return Some(u32::from_le_bytes(b.try_into().unwrap()));
+    }
+
+    // конец последнего блока может выходить за файл, недостающие байты - нули
+    fn u32_be(&self, pos: usize) -> Option<u32> {
+        if pos >= self.bytes.len() {
+            return None;
+        }
+        let mut b = [0_u8; 4];
+        for (i, x) in b.iter_mut().enumerate() {
+            *x = self.bytes.get(pos + i).copied().unwrap_or(0);
+        }
+        return Some(u32::from_be_bytes(b));
+    }
+
+    fn u64_be(&self, pos: usize) -> Option<u64> {
+        let hi = self.u32_be(pos)? as u64;
+        let lo = self.u32_be(pos + 4).unwrap_or(0) as u64;
+        return Some((hi << 32) | lo);
+    }
+
+    // (left, right) 12-битные поля узла дерева пар
+    fn lr(&self, pos: usize) -> Option<(usize, usize)> {
+        let b = self.bytes.get(pos..pos + 3)?;
+        let left = (((b[1] & 0xF) as usize) << 8) | b[0] as usize;
+        let right = ((b[2] as usize) << 4) | (b[1] >> 4) as usize;
+        return Some((left, right));
+    }
+}
@@ -0,0 +1,976 @@
+// Тесты на сгенерированных таблицах: настоящих файлов Syzygy в репозитории нет,
+// поэтому тест записывает файлы того же формата. Индексы позиций для записи
+// считает эталон ниже - отдельная от table.rs и encoding.rs реализация
+// кодирования из tbprobe.cpp Stockfish, таблицы которого заданы определениями,
+// а не портом инициализации. Коды Хаффмана разной длины, один символ - пара.
+
+use super::table::{
+    DTZ_MAGIC, FLAG_STM, FLAG_WIN_PLIES, HEADER_HAS_PAWNS, HEADER_SPLIT, Table, TableType,
+    TableValue, WDL_MAGIC,
+};
+use super::{Tablebases, WDL_DRAW, WDL_LOSS, WDL_WIN};
+use crate::chess::{Move, MoveList, Piece, Position, Side, Square, XorshiftRng};
+use std::path::{Path, PathBuf};
+
+// Коды фигур в файлах Syzygy: цвет * 8 + фигура, пешка 1 ... король 6
+const W_PAWN: u8 = 1;
+const W_KNIGHT: u8 = 2;
+const W_QUEEN: u8 = 5;
+const W_KING: u8 = 6;
+const B_PAWN: u8 = 9;
+const B_KING: u8 = 14;
+
+const LOG_BLOCK_SIZE: u8 = 10;
+const LOG_SPAN: u8 = 10;
+
+// ---------------------------------------------------------------------------
+// Эталонное кодирование позиции
+
+// Треугольник a1-d1-d4: поля под диагональю a1-h8, затем на диагонали
+const A1D1D4: [usize; 10] = [1, 2, 3, 10, 11, 19, 0, 9, 18, 27];
+
+fn file_of(sq: usize) -> usize {
+    return sq % 8;
+}
+
+fn rank_of(sq: usize) -> usize {
+    return sq / 8;
+}
+
+fn diagonal_offset(sq: usize) -> isize {
+    return rank_of(sq) as isize - file_of(sq) as isize;
+}
+
+fn distance(a: usize, b: usize) -> usize {
+    let df = file_of(a).abs_diff(file_of(b));
+    let dr = rank_of(a).abs_diff(rank_of(b));
+    return df.max(dr);
+}
+
+fn binomial(k: usize, n: usize) -> u64 {
+    if k > n {
+        return 0;
+    }
+    return (0..k).fold(1, |acc, i| acc * (n - i) as u64 / (i + 1) as u64);
+}
+
+fn triangle_index(sq: usize) -> u64 {
+    return A1D1D4.iter().position(|&x| x == sq).unwrap() as u64;
+}
+
+// Номер поля среди 28 полей под диагональю a1-h8
+fn below_diagonal_index(sq: usize) -> u64 {
+    return (0..sq).filter(|&s| diagonal_offset(s) < 0).count() as u64;
+}
+
+// Номер поля без занятых полей others
+fn free_index(sq: usize, others: &[usize]) -> u64 {
+    return (sq - others.iter().filter(|&&s| s < sq).count()) as u64;
+}
+
+// Пары королей: первый в треугольнике, второй не рядом и, если первый на
+// диагонали, не над ней. Пары, где оба на диагонали, идут последними.
+fn king_pairs() -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for &k1 in &A1D1D4 {
+        for k2 in 0..64 {
+            if distance(k1, k2) > 1 && !(diagonal_offset(k1) == 0 && diagonal_offset(k2) > 0) {
+                pairs.push((k1, k2));
+            }
+        }
+    }
+    let (mut res, both_on_diagonal): (Vec<_>, Vec<_>) = pairs
+        .into_iter()
+        .partition(|&(k1, k2)| diagonal_offset(k1) != 0 || diagonal_offset(k2) != 0);
+    res.extend(both_on_diagonal);
+    return res;
+}
+
+// Три первые фигуры, когда у кого-то есть единственная фигура (кроме короля)
+fn unique_index(s: &[usize]) -> u64 {
+    // на диагонали номер поля - горизонталь
+    let diagonal_index = |i: usize| {
+        let below = s[..i].iter().filter(|&&x| x < s[i]).count();
+        return (rank_of(s[i]) - below) as u64;
+    };
+    if diagonal_offset(s[0]) != 0 {
+        return (triangle_index(s[0]) * 63 + free_index(s[1], &s[..1])) * 62
+            + free_index(s[2], &s[..2]);
+    }
+    if diagonal_offset(s[1]) != 0 {
+        return (6 * 63 + rank_of(s[0]) as u64 * 28 + below_diagonal_index(s[1])) * 62
+            + free_index(s[2], &s[..2]);
+    }
+    if diagonal_offset(s[2]) != 0 {
+        return 6 * 63 * 62
+            + 4 * 28 * 62
+            + rank_of(s[0]) as u64 * 7 * 28
+            + diagonal_index(1) * 28
+            + below_diagonal_index(s[2]);
+    }
+    return 6 * 63 * 62
+        + 4 * 28 * 62
+        + 4 * 7 * 28
+        + rank_of(s[0]) as u64 * 7 * 6
+        + diagonal_index(1) * 6
+        + diagonal_index(2);
+}
+
+// Поля пешек a2-h7 от 47 до 0: a2 h2 a3 h3 ... a7 h7 b2 g2 ... d7 e7.
+// Ведущая пешка - с наибольшим номером, то есть ближе к краю и ниже.
+fn pawn_index(sq: usize) -> u64 {
+    let file = file_of(sq);
+    let edge = file.min(7 - file);
+    let used = (edge * 6 + rank_of(sq) - 1) * 2;
+    return if file < 4 { 47 - used } else { 46 - used } as u64;
+}
+
+// Позиций ведущих пешек (count штук), когда ведущая на вертикали file (a-d)
+// ниже горизонтали rank
+fn lead_pawns_before(count: usize, file: usize, rank: usize) -> u64 {
+    return (1..rank)
+        .map(|r| binomial(count - 1, pawn_index(r * 8 + file) as usize))
+        .sum();
+}
+
+// Описание данных одной стороны на одной вертикали, как оно записано в файле
+#[derive(Clone)]
+struct Item {
+    // фигуры в порядке кодирования
+    pieces: Vec<u8>,
+    // место ведущей группы и группы остальных пешек в произведении
+    order: [u8; 2],
+}
+
+struct RefTable {
+    white: Vec<u8>,
+    black: Vec<u8>,
+    // [вертикаль ведущей пешки][сторона]
+    items: Vec<Vec<Item>>,
+}
+
+impl RefTable {
+    fn has_pawns(&self) -> bool {
+        return self.white.contains(&W_PAWN);
+    }
+
+    fn symmetric(&self) -> bool {
+        let mut black: Vec<u8> = self.black.iter().map(|x| x ^ 8).collect();
+        let mut white = self.white.clone();
+        black.sort();
+        white.sort();
+        return black == white;
+    }
+
+    fn has_unique_pieces(&self) -> bool {
+        let all: Vec<u8> = self.white.iter().chain(&self.black).copied().collect();
+        return all
+            .iter()
+            .any(|&x| x & 7 != 6 && all.iter().filter(|&&y| y == x).count() == 1);
+    }
+
+    // обе стороны с пешками: остальные пешки - отдельная группа
+    fn both_pawns(&self) -> bool {
+        return self.has_pawns() && self.black.contains(&B_PAWN);
+    }
+
+    // Длины групп одинаковых фигур, первая группа - ведущая
+    fn groups(&self, item: &Item) -> Vec<usize> {
+        let forced = if self.has_pawns() {
+            1
+        } else if self.has_unique_pieces() {
+            3
+        } else {
+            2
+        };
+        let mut res = vec![1];
+        for i in 1..item.pieces.len() {
+            if i < forced || item.pieces[i] == item.pieces[i - 1] {
+                *res.last_mut().unwrap() += 1;
+            } else {
+                res.push(1);
+            }
+        }
+        return res;
+    }
+
+    // Множители групп в индексе и размер таблицы
+    fn factors(&self, item: &Item, file: usize) -> (Vec<u64>, u64) {
+        let groups = self.groups(item);
+        let both_pawns = self.both_pawns();
+        let mut sizes = vec![0; groups.len()];
+        sizes[0] = if self.has_pawns() {
+            lead_pawns_before(groups[0], file, 7)
+        } else if self.has_unique_pieces() {
+            31_332
+        } else {
+            462
+        };
+        let mut free = 64 - groups[0];
+        for g in 1..groups.len() {
+            if g == 1 && both_pawns {
+                sizes[g] = binomial(groups[g], 48 - groups[0]);
+            } else {
+                sizes[g] = binomial(groups[g], free);
+            }
+            free -= groups[g];
+        }
+
+        // группы в порядке от младшей к старшей
+        let mut slots = Vec::new();
+        let mut next = if both_pawns { 2 } else { 1 };
+        for k in 0..groups.len() {
+            if k == item.order[0] as usize {
+                slots.push(0);
+            } else if both_pawns && k == item.order[1] as usize {
+                slots.push(1);
+            } else {
+                slots.push(next);
+                next += 1;
+            }
+        }
+        let mut factors = vec![0; groups.len()];
+        let mut product = 1;
+        for g in slots {
+            factors[g] = product;
+            product *= sizes[g];
+        }
+        return (factors, product);
+    }
+
+    // (сторона, вертикаль, индекс) позиции
+    fn index(&self, pos: &Position) -> (usize, usize, u64) {
+        let mut pieces = Vec::new();
+        for sq in 0..64 {
+            if let Some((side, piece)) = pos.side_piece_on_square(Square::make(sq % 8, sq / 8)) {
+                pieces.push((side.index() as u8 * 8 + piece as u8, sq));
+            }
+        }
+        let mut white: Vec<u8> = pieces.iter().map(|x| x.0).filter(|&x| x < 8).collect();
+        let mut expected = self.white.clone();
+        white.sort();
+        expected.sort();
+        let mut stm = pos.side_to_move.index();
+        // таблица для белых как сильнейшей стороны и для хода белых, если материал равный
+        if white != expected || self.symmetric() && stm == 1 {
+            for (code, sq) in pieces.iter_mut() {
+                *code ^= 8;
+                *sq ^= 56;
+            }
+            stm ^= 1;
+        }
+
+        let mut squares = Vec::new();
+        let mut file = 0;
+        let mut lead_count = 0;
+        if self.has_pawns() {
+            let lead_code = self.items[0][0].pieces[0];
+            let mut lead: Vec<usize> = pieces
+                .iter()
+                .filter(|x| x.0 == lead_code)
+                .map(|x| x.1)
+                .collect();
+            lead.sort_by_key(|&sq| std::cmp::Reverse(pawn_index(sq)));
+            lead_count = lead.len();
+            file = file_of(lead[0]).min(7 - file_of(lead[0]));
+            pieces.retain(|x| x.0 != lead_code);
+            squares.extend(lead);
+        }
+        let item = &self.items[file][stm % self.items[file].len()];
+        for &code in &item.pieces[lead_count..] {
+            let i = pieces.iter().position(|x| x.0 == code).unwrap();
+            squares.push(pieces.remove(i).1);
+        }
+
+        if file_of(squares[0]) > 3 {
+            squares.iter_mut().for_each(|sq| *sq ^= 7);
+        }
+        let groups = self.groups(item);
+        let lead_index = if self.has_pawns() {
+            let others = &mut squares[1..lead_count];
+            others.sort_by_key(|&sq| pawn_index(sq));
+            let mut idx = lead_pawns_before(lead_count, file_of(squares[0]), rank_of(squares[0]));
+            for (i, &sq) in squares[1..lead_count].iter().enumerate() {
+                idx += binomial(i + 1, pawn_index(sq) as usize);
+            }
+            idx
+        } else {
+            if rank_of(squares[0]) > 3 {
+                squares.iter_mut().for_each(|sq| *sq ^= 56);
+            }
+            // первая фигура ведущей группы не на диагонали - под диагональю
+            let first = squares[..groups[0]]
+                .iter()
+                .find(|&&sq| diagonal_offset(sq) != 0);
+            if first.is_some_and(|&sq| diagonal_offset(sq) > 0) {
+                squares
+                    .iter_mut()
+                    .for_each(|sq| *sq = file_of(*sq) * 8 + rank_of(*sq));
+            }
+            if self.has_unique_pieces() {
+                unique_index(&squares)
+            } else {
+                let pair = (squares[0], squares[1]);
+                king_pairs().iter().position(|&x| x == pair).unwrap() as u64
+            }
+        };
+
+        let (factors, _) = self.factors(item, file);
+        let mut idx = lead_index * factors[0];
+        let mut start = groups[0];
+        for g in 1..groups.len() {
+            let end = start + groups[g];
+            squares[start..end].sort();
+            let pawns = if g == 1 && self.both_pawns() { 8 } else { 0 };
+            let mut n = 0;
+            for (i, &sq) in squares[start..end].iter().enumerate() {
+                n += binomial(i + 1, free_index(sq, &squares[..start]) as usize - pawns);
+            }
+            idx += n * factors[g];
+            start = end;
+        }
+        return (stm, file, idx);
+    }
+
+    fn counts(&self) -> [[usize; Piece::PIECE_NB]; 2] {
+        let mut counts = [[0; Piece::PIECE_NB]; 2];
+        for &code in self.white.iter().chain(&self.black) {
+            counts[(code >> 3) as usize][(code & 7) as usize] += 1;
+        }
+        return counts;
+    }
+}
+
+#[test]
+fn test_reference_index_tables() {
+    // размеры из tbprobe.cpp
+    assert_eq!(king_pairs().len(), 462);
+    let lead: Vec<u64> = (0..4).map(|f| lead_pawns_before(1, f, 7)).collect();
+    assert_eq!(lead, [6, 6, 6, 6]);
+    let mut pawns: Vec<u64> = (8..56).map(pawn_index).collect();
+    pawns.sort();
+    assert_eq!(pawns, (0..48).collect::<Vec<u64>>());
+    assert_eq!(pawn_index(8), 47);
+    assert_eq!(pawn_index(15), 46);
+    assert_eq!(pawn_index(9), 35);
+    assert_eq!(pawn_index(52), 0);
+}
+
+// ---------------------------------------------------------------------------
+// Запись файлов
+
+// Символы в порядке номеров: сначала самые длинные коды. Лист - значение,
+// пара - два символа подряд.
+enum Symbol {
+    Leaf(u8),
+    Pair(usize, usize),
+}
+
+// значения 0..=4; пара двух значений 2 - символ 1
+const SYMBOLS: [(usize, Symbol); 6] = [
+    (4, Symbol::Leaf(4)),
+    (4, Symbol::Pair(5, 5)),
+    (3, Symbol::Leaf(3)),
+    (2, Symbol::Leaf(0)),
+    (2, Symbol::Leaf(1)),
+    (2, Symbol::Leaf(2)),
+];
+
+// Канонический код: у более длинных кодов меньшие числа
+fn huffman_codes() -> (Vec<u16>, Vec<(usize, u64)>) {
+    let min_len = SYMBOLS.iter().map(|x| x.0).min().unwrap();
+    let max_len = SYMBOLS.iter().map(|x| x.0).max().unwrap();
+    let count = |len: usize| SYMBOLS.iter().filter(|x| x.0 == len).count();
+    let size = max_len - min_len + 1;
+    let mut lowest_sym = vec![0_u16; size];
+    let mut base = vec![0_u64; size];
+    for i in (0..size - 1).rev() {
+        let next = count(min_len + i + 1);
+        lowest_sym[i] = lowest_sym[i + 1] + next as u16;
+        assert!((base[i + 1] + next as u64).is_multiple_of(2));
+        base[i] = (base[i + 1] + next as u64) / 2;
+    }
+    let codes = SYMBOLS
+        .iter()
+        .enumerate()
+        .map(|(sym, &(len, _))| {
+            let i = len - min_len;
+            (len, base[i] + (sym - lowest_sym[i] as usize) as u64)
+        })
+        .collect();
+    return (lowest_sym, codes);
+}
+
+// Сжатые данные одной стороны на одной вертикали
+struct Pairs {
+    flags: u8,
+    blocks: Vec<Vec<u8>>,
+    block_starts: Vec<usize>,
+    size: usize,
+}
+
+fn compress(values: &[u8], flags: u8) -> Pairs {
+    let (_, codes) = huffman_codes();
+    let block_bits = 8 << LOG_BLOCK_SIZE;
+    let mut res = Pairs {
+        flags: flags,
+        blocks: Vec::new(),
+        block_starts: Vec::new(),
+        size: values.len(),
+    };
+    let mut i = 0;
+    while i < values.len() {
+        res.block_starts.push(i);
+        let mut block = vec![0_u8; block_bits / 8];
+        let mut bits = 0;
+        while i < values.len() {
+            let (sym, n) = if values[i] == 2 && values.get(i + 1) == Some(&2) {
+                (1, 2)
+            } else {
+                let leaf = SYMBOLS
+                    .iter()
+                    .position(|x| matches!(x.1, Symbol::Leaf(v) if v == values[i]));
+                (leaf.unwrap(), 1)
+            };
+            let (len, code) = codes[sym];
+            if bits + len > block_bits {
+                break;
+            }
+            for bit in 0..len {
+                if code >> (len - 1 - bit) & 1 != 0 {
+                    block[(bits + bit) / 8] |= 0x80 >> ((bits + bit) % 8);
+                }
+            }
+            bits += len;
+            i += n;
+        }
+        res.blocks.push(block);
+    }
+    return res;
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn align(&mut self, n: usize) {
+        while !self.bytes.len().is_multiple_of(n) {
+            self.bytes.push(0);
+        }
+    }
+
+    fn sizes(&mut self, d: &Pairs) {
+        let (lowest_sym, _) = huffman_codes();
+        self.u8(d.flags);
+        self.u8(LOG_BLOCK_SIZE);
+        self.u8(LOG_SPAN);
+        self.u8(0);
+        self.u32(d.blocks.len() as u32);
+        self.u8(SYMBOLS.iter().map(|x| x.0).max().unwrap() as u8);
+        self.u8(SYMBOLS.iter().map(|x| x.0).min().unwrap() as u8);
+        for x in lowest_sym {
+            self.u16(x);
+        }
+        self.u16(SYMBOLS.len() as u16);
+        for (_, sym) in &SYMBOLS {
+            let (left, right) = match *sym {
+                Symbol::Leaf(value) => (value as usize, 0xFFF),
+                Symbol::Pair(left, right) => (left, right),
+            };
+            self.u8(left as u8);
+            self.u8((left >> 8) as u8 | ((right & 0xF) << 4) as u8);
+            self.u8((right >> 4) as u8);
+        }
+        if SYMBOLS.len() & 1 != 0 {
+            self.u8(0);
+        }
+    }
+
+    // блок и смещение значения с индексом k * span + span / 2
+    fn sparse_index(&mut self, d: &Pairs) {
+        let span = 1 << LOG_SPAN;
+        for k in 0..d.size.div_ceil(span) {
+            let idx = k * span + span / 2;
+            let block = d.block_starts.partition_point(|&start| start <= idx) - 1;
+            self.u32(block as u32);
+            self.u16((idx - d.block_starts[block]) as u16);
+        }
+    }
+
+    fn block_lengths(&mut self, d: &Pairs) {
+        for (i, &start) in d.block_starts.iter().enumerate() {
+            let end = d.block_starts.get(i + 1).copied().unwrap_or(d.size);
+            self.u16((end - start - 1) as u16);
+        }
+    }
+}
+
+// data[вертикаль][сторона]; у DTZ одна сторона, какая - во флаге FLAG_STM
+fn write_table(magic: [u8; 4], table: &RefTable, data: &[Vec<Pairs>]) -> Vec<u8> {
+    let sides = data[0].len();
+    let mut w = Writer { bytes: Vec::new() };
+    w.bytes.extend_from_slice(&magic);
+    let mut header = 0;
+    if sides == 2 {
+        header |= HEADER_SPLIT;
+    }
+    if table.has_pawns() {
+        header |= HEADER_HAS_PAWNS;
+    }
+    w.u8(header);
+    for items in &table.items {
+        let other = &items[items.len() - 1];
+        w.u8(items[0].order[0] | other.order[0] << 4);
+        if table.both_pawns() {
+            w.u8(items[0].order[1] | other.order[1] << 4);
+        }
+        for (a, b) in items[0].pieces.iter().zip(&other.pieces) {
+            w.u8(a | b << 4);
+        }
+    }
+    w.align(2);
+    for d in data.iter().flatten() {
+        w.sizes(d);
+    }
+    if magic == DTZ_MAGIC {
+        w.align(2);
+    }
+    for d in data.iter().flatten() {
+        w.sparse_index(d);
+    }
+    for d in data.iter().flatten() {
+        w.block_lengths(d);
+    }
+    for d in data.iter().flatten() {
+        w.align(64);
+        for block in &d.blocks {
+            w.bytes.extend_from_slice(block);
+        }
+    }
+    return w.bytes;
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("counter_tb_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+// ---------------------------------------------------------------------------
+// KQvK: значения WDL по правилам, проверка через Tablebases
+
+struct KqkPosition {
+    wk: usize,
+    wq: usize,
+    bk: usize,
+    white_to_move: bool,
+}
+
+fn make_fen(pieces: &[(char, usize)], white_to_move: bool) -> String {
+    let mut board = [' '; 64];
+    for &(ch, sq) in pieces {
+        board[sq] = ch;
+    }
+    let mut fen = String::new();
+    for rank in (0..8).rev() {
+        let mut empty = 0;
+        for file in 0..8 {
+            let ch = board[rank * 8 + file];
+            if ch == ' ' {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+                empty = 0;
+            }
+            fen.push(ch);
+        }
+        if empty > 0 {
+            fen.push_str(&empty.to_string());
+        }
+        if rank > 0 {
+            fen.push('/');
+        }
+    }
+    let stm = if white_to_move { "w" } else { "b" };
+    return format!("{fen} {stm} - - 0 1");
+}
+
+// flip - цвета меняются местами, доска отражается по горизонтали
+fn flip_fen(pieces: &[(char, usize)], white_to_move: bool) -> String {
+    let flipped: Vec<(char, usize)> = pieces
+        .iter()
+        .map(|&(ch, sq)| {
+            let ch = if ch.is_ascii_uppercase() {
+                ch.to_ascii_lowercase()
+            } else {
+                ch.to_ascii_uppercase()
+            };
+            (ch, sq ^ 56)
+        })
+        .collect();
+    return make_fen(&flipped, !white_to_move);
+}
+
+fn to_fen(p: &KqkPosition, flip: bool) -> String {
+    let pieces = [('K', p.wk), ('Q', p.wq), ('k', p.bk)];
+    if flip {
+        return flip_fen(&pieces, p.white_to_move);
+    }
+    return make_fen(&pieces, p.white_to_move);
+}
+
+// Синтетическое DTZ, инвариантное к симметриям доски
+fn dtz_value(p: &KqkPosition) -> u8 {
+    return ((distance(p.wk, p.bk) + distance(p.wq, p.bk)) % 5) as u8;
+}
+
+// Все позиции с белым королем в треугольнике a1-d1-d4 покрывают все индексы таблицы.
+fn enumerate_positions() -> Vec<(KqkPosition, Position)> {
+    let mut res = Vec::new();
+    for wk in [0, 1, 2, 3, 9, 10, 11, 18, 19, 27] {
+        for wq in 0..64 {
+            for bk in 0..64 {
+                if wq == wk || bk == wk || bk == wq || distance(wk, bk) <= 1 {
+                    continue;
+                }
+                for white_to_move in [true, false] {
+                    let p = KqkPosition {
+                        wk,
+                        wq,
+                        bk,
+                        white_to_move,
+                    };
+                    if let Some(pos) = Position::from_fen(&to_fen(&p, false)) {
+                        res.push((p, pos));
+                    }
+                }
+            }
+        }
+    }
+    return res;
+}
+
+fn expected_wdl(pos: &Position) -> isize {
+    if pos.side_to_move == Side::WHITE {
+        return WDL_WIN;
+    }
+    let mut ml = MoveList::new();
+    ml.gen_legal_moves(pos);
+    let moves = &ml.moves[..ml.size];
+    if moves.is_empty() && !pos.is_check()
+        || moves.iter().any(|x| x.mv.captured_piece() != Piece::NONE)
+    {
+        return WDL_DRAW;
+    }
+    return WDL_LOSS;
+}
+
+fn kqk_table() -> RefTable {
+    let item = Item {
+        pieces: vec![W_KING, W_QUEEN, B_KING],
+        order: [0, 0xF],
+    };
+    return RefTable {
+        white: vec![W_KING, W_QUEEN],
+        black: vec![B_KING],
+        items: vec![vec![item.clone(), item]],
+    };
+}
+
+fn generate_tables(dir: &Path) {
+    let table = kqk_table();
+    let (_, size) = table.factors(&table.items[0][0], 0);
+    assert_eq!(size, 31_332);
+    let mut wdl = vec![vec![(WDL_DRAW + 2) as u8; size as usize]; 2];
+    let mut dtz = vec![0; size as usize];
+    for (p, pos) in enumerate_positions().iter() {
+        let (side, _, idx) = table.index(pos);
+        wdl[side][idx as usize] = (expected_wdl(pos) + 2) as u8;
+        if p.white_to_move {
+            dtz[idx as usize] = dtz_value(p);
+        }
+    }
+
+    let wdl = vec![wdl.iter().map(|x| compress(x, 0)).collect()];
+    let dtz = vec![vec![compress(&dtz, 0)]];
+    std::fs::write(dir.join("KQvK.rtbw"), write_table(WDL_MAGIC, &table, &wdl)).unwrap();
+    std::fs::write(dir.join("KQvK.rtbz"), write_table(DTZ_MAGIC, &table, &dtz)).unwrap();
+}
+
+fn make_tablebases() -> (Tablebases, PathBuf) {
+    unsafe { crate::chess::init() };
+    let dir = temp_dir("kqk");
+    generate_tables(&dir);
+    let tb = Tablebases::load(dir.to_str().unwrap());
+    return (tb, dir);
+}
+
+#[test]
+fn test_tablebase_probe() {
+    let (tb, dir) = make_tablebases();
+    assert_eq!(tb.max_pieces(), 3);
+
+    for (i, (p, pos)) in enumerate_positions().iter().enumerate() {
+        if i % 41 != 0 {
+            continue;
+        }
+        let expected = expected_wdl(pos);
+        assert_eq!(tb.probe_wdl(pos), Some(expected), "{}", to_fen(p, false));
+        let flipped = Position::from_fen(&to_fen(p, true)).unwrap();
+        assert_eq!(
+            tb.probe_wdl(&flipped),
+            Some(expected),
+            "{}",
+            to_fen(p, true)
+        );
+
+        // белые: значение из таблицы; черные: поиск на один полуход
+        let expected_dtz = if expected == WDL_DRAW {
+            0
+        } else if p.white_to_move {
+            2 * dtz_value(p) as isize + 1
+        } else {
+            let mut ml = MoveList::new();
+            ml.gen_legal_moves(pos);
+            let worst = ml.moves[..ml.size]
+                .iter()
+                .map(|x| {
+                    let child = KqkPosition {
+                        bk: x.mv.to().index(),
+                        white_to_move: true,
+                        ..*p
+                    };
+                    2 * dtz_value(&child) as isize + 2
+                })
+                .max();
+            worst.map_or(-1, |x| -x)
+        };
+        assert_eq!(
+            tb.probe_dtz(pos),
+            Some(expected_dtz),
+            "{}",
+            to_fen(p, false)
+        );
+        assert_eq!(
+            tb.probe_dtz(&flipped),
+            Some(expected_dtz),
+            "{}",
+            to_fen(p, true)
+        );
+    }
+
+    // ферзь не должен подставляться под взятие
+    let pos = Position::from_fen("8/8/8/8/8/4k3/8/3Q3K w - - 0 1").unwrap();
+    let mut ml = MoveList::new();
+    ml.gen_legal_moves(&pos);
+    let moves: Vec<Move> = ml.moves[..ml.size].iter().map(|x| x.mv).collect();
+    let best = tb.root_probe(&pos, &moves, &[]).unwrap();
+    assert!(!best.is_empty());
+    let hanging = Move::parse_lan(&pos, "d1d2").unwrap();
+    assert!(moves.contains(&hanging) && !best.contains(&hanging));
+    let mut child = pos.clone();
+    for &mv in best.iter() {
+        pos.make_move(mv, &mut child);
+        assert_eq!(tb.probe_wdl(&child), Some(WDL_LOSS));
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+// ---------------------------------------------------------------------------
+// Таблицы с пешками и без единственных фигур: значения - хеш индекса,
+// каждая позиция читается через Table и сравнивается с эталоном
+
+fn hash_value(seed: u64, idx: u64) -> u8 {
+    let mut x = (idx + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ seed;
+    x ^= x >> 31;
+    x = x.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x ^= x >> 29;
+    return (x % 5) as u8;
+}
+
+fn random_position(table: &RefTable, rng: &mut XorshiftRng) -> Option<Position> {
+    let mut pieces: Vec<(char, usize)> = Vec::new();
+    for &code in table.white.iter().chain(&table.black) {
+        let ch = b" pnbrqk"[(code & 7) as usize] as char;
+        let ch = if code < 8 {
+            ch.to_ascii_uppercase()
+        } else {
+            ch
+        };
+        let sq = (rng.next() % 64) as usize;
+        if pieces.iter().any(|x| x.1 == sq)
+            || ch.eq_ignore_ascii_case(&'p') && !(8..56).contains(&sq)
+        {
+            return None;
+        }
+        pieces.push((ch, sq));
+    }
+    let kings: Vec<usize> = pieces
+        .iter()
+        .filter(|x| x.0.eq_ignore_ascii_case(&'k'))
+        .map(|x| x.1)
+        .collect();
+    if distance(kings[0], kings[1]) <= 1 {
+        return None;
+    }
+    let white_to_move = rng.next() & 1 != 0;
+    let fen = if rng.next() & 1 != 0 {
+        flip_fen(&pieces, white_to_move)
+    } else {
+        make_fen(&pieces, white_to_move)
+    };
+    return Position::from_fen(&fen);
+}
+
+fn check_table(dir: &Path, name: &str, table: &RefTable, seed: u64) {
+    let sides = if table.symmetric() { 1 } else { 2 };
+    let wdl: Vec<Vec<Pairs>> = (0..table.items.len())
+        .map(|file| {
+            (0..sides)
+                .map(|side| {
+                    let (_, size) = table.factors(&table.items[file][side], file);
+                    let values: Vec<u8> = (0..size)
+                        .map(|idx| hash_value(seed + (file * 2 + side) as u64, idx))
+                        .collect();
+                    compress(&values, 0)
+                })
+                .collect()
+        })
+        .collect();
+    let path = dir.join(format!("{}.rtbw", name));
+    std::fs::write(&path, write_table(WDL_MAGIC, table, &wdl)).unwrap();
+
+    // DTZ только для таблиц с пешками: на вертикалях b и d хранится ход черных
+    // (если материал неравный), на c выигрыш в полуходах
+    let stm_flag = if sides == 2 { FLAG_STM } else { 0 };
+    let dtz_flags = [0, stm_flag, FLAG_WIN_PLIES, stm_flag];
+    if table.has_pawns() {
+        let dtz: Vec<Vec<Pairs>> = (0..4)
+            .map(|file| {
+                let side = (dtz_flags[file] & FLAG_STM) as usize;
+                let (_, size) = table.factors(&table.items[file][side], file);
+                let values: Vec<u8> = (0..size)
+                    .map(|idx| hash_value(seed + 16 + file as u64, idx))
+                    .collect();
+                vec![compress(&values, dtz_flags[file])]
+            })
+            .collect();
+        let dtz_table = RefTable {
+            white: table.white.clone(),
+            black: table.black.clone(),
+            items: (0..4)
+                .map(|file| vec![table.items[file][(dtz_flags[file] & FLAG_STM) as usize].clone()])
+                .collect(),
+        };
+        std::fs::write(
+            dir.join(format!("{}.rtbz", name)),
+            write_table(DTZ_MAGIC, &dtz_table, &dtz),
+        )
+        .unwrap();
+    }
+
+    let tb = Table::new(&table.counts(), path);
+    let mut rng = XorshiftRng::with_seed(seed);
+    let mut checked = 0;
+    let mut dtz_checked = 0;
+    while checked < 2_000 {
+        let Some(pos) = random_position(table, &mut rng) else {
+            continue;
+        };
+        checked += 1;
+        let fen = pos.to_fen();
+        let (side, file, idx) = table.index(&pos);
+        let expected = hash_value(seed + (file * 2 + side % sides) as u64, idx) as isize - 2;
+        match tb.probe(&pos, TableType::Wdl, 0) {
+            TableValue::Value(value) => assert_eq!(value, expected, "{} {}", name, fen),
+            _ => panic!("{} {}", name, fen),
+        }
+
+        if !table.has_pawns() {
+            continue;
+        }
+        match tb.probe(&pos, TableType::Dtz, WDL_WIN) {
+            TableValue::Value(value) => {
+                assert_eq!(side, (dtz_flags[file] & FLAG_STM) as usize, "{}", fen);
+                let v = hash_value(seed + 16 + file as u64, idx) as isize;
+                let plies = dtz_flags[file] & FLAG_WIN_PLIES != 0;
+                let expected = if plies { v + 1 } else { 2 * v + 1 };
+                assert_eq!(value, expected, "{} {}", name, fen);
+                dtz_checked += 1;
+            }
+            TableValue::ChangeStm => {
+                assert_ne!(side, (dtz_flags[file] & FLAG_STM) as usize, "{}", fen)
+            }
+            TableValue::Fail => panic!("{} {}", name, fen),
+        }
+    }
+    assert!(!table.has_pawns() || dtz_checked > 500);
+}
+
+#[test]
+fn test_tablebase_index() {
+    unsafe { crate::chess::init() };
+    let dir = temp_dir("index");
+    let item = |pieces: &[u8], order: [u8; 2]| Item {
+        pieces: pieces.to_vec(),
+        order: order,
+    };
+
+    // без единственных фигур: пара королей и группа из двух коней,
+    // у черных группа коней - младшая
+    let knnk = RefTable {
+        white: vec![W_KING, W_KNIGHT, W_KNIGHT],
+        black: vec![B_KING],
+        items: vec![vec![
+            item(&[W_KING, B_KING, W_KNIGHT, W_KNIGHT], [0, 0xF]),
+            item(&[B_KING, W_KING, W_KNIGHT, W_KNIGHT], [1, 0xF]),
+        ]],
+    };
+    check_table(&dir, "KNNvK", &knnk, 1);
+
+    // своя раскладка на каждой вертикали ведущей пешки
+    let kpk = RefTable {
+        white: vec![W_KING, W_PAWN],
+        black: vec![B_KING],
+        items: [[0, 2], [2, 1], [1, 0], [0, 0]]
+            .iter()
+            .map(|order| {
+                vec![
+                    item(&[W_PAWN, W_KING, B_KING], [order[0], 0xF]),
+                    item(&[W_PAWN, B_KING, W_KING], [order[1], 0xF]),
+                ]
+            })
+            .collect(),
+    };
+    check_table(&dir, "KPvK", &kpk, 2);
+
+    // пешки у обеих сторон: остальные пешки - отдельная группа,
+    // материал равный - одна сторона
+    let kpkp = RefTable {
+        white: vec![W_KING, W_PAWN],
+        black: vec![B_KING, B_PAWN],
+        items: [[0, 1], [3, 0], [1, 2], [2, 3]]
+            .iter()
+            .map(|&order| vec![item(&[W_PAWN, B_PAWN, W_KING, B_KING], order)])
+            .collect(),
+    };
+    check_table(&dir, "KPvKP", &kpkp, 3);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
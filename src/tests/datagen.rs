@@ -0,0 +1,281 @@
+// Позиции для обучения сети: движок играет сам с собой на фиксированном числе
+// узлов, дебют - несколько случайных ходов. В каждом потоке свой Engine.
+// counter datagen [games] [nodes] [threads] [output]
+// Строки "fen | score | result" дописываются в output (его читает counter train),
+// score - оценка поиска за белых, result - 1.0, 0.5 или 0.0 за белых.
+
+use crate::chess::{Move, MoveList, Piece, Position, Side, XorshiftRng, bitboard};
+use crate::domain::{CancelToken, IEngine, LimitsType, PonderHitToken, SearchParams, UciScore};
+use crate::engine::Engine;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime};
+
+const RANDOM_PLIES: usize = 8;
+// дебют с большим перевесом одной стороны отбрасывается
+const MAX_OPENING_SCORE: isize = 400;
+// победа: WIN_PLIES полуходов подряд оценка не меньше WIN_SCORE в пользу одной стороны
+const WIN_SCORE: isize = 1_500;
+const WIN_PLIES: isize = 6;
+// ничья: после DRAW_PLY полуходов DRAW_PLIES полуходов подряд |оценка| <= DRAW_SCORE
+const DRAW_PLY: usize = 80;
+const DRAW_SCORE: isize = 10;
+const DRAW_PLIES: usize = 10;
+const MAX_PLIES: usize = 400;
+const THREAD_STACK_SIZE: usize = 16 << 20;
+
+struct Stats {
+    games: usize,
+    positions: usize,
+    // победы белых, ничьи, победы черных
+    results: [usize; 3],
+}
+
+pub fn datagen_handler() {
+    let args: Vec<String> = std::env::args().collect();
+    let games = args
+        .get(2)
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(1_000);
+    let nodes = args
+        .get(3)
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(5_000);
+    let threads = args
+        .get(4)
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()));
+    let path = args.get(5).map(String::as_str).unwrap_or("data.txt");
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap();
+    let output = Mutex::new((
+        io::BufWriter::new(file),
+        Stats {
+            games: 0,
+            positions: 0,
+            results: [0; 3],
+        },
+    ));
+    let next_game = AtomicUsize::new(0);
+    let start = Instant::now();
+    // при повторном запуске дебюты другие
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |x| x.as_nanos() as u64);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            std::thread::Builder::new()
+                .stack_size(THREAD_STACK_SIZE)
+                .spawn_scoped(scope, || {
+                    let mut engine = Engine::new();
+                    engine.set_option("Hash", "16");
+                    loop {
+                        let index = next_game.fetch_add(1, Ordering::Relaxed);
+                        if index >= games {
+                            break;
+                        }
+                        let mut rng = XorshiftRng::with_seed(seed ^ (index as u64 + 1) << 20);
+                        let (positions, result) = loop {
+                            // каждая партия с чистой хеш-таблицей и историей, как ucinewgame
+                            engine.clear();
+                            if let Some(game) = play_game(&mut engine, nodes, &mut rng) {
+                                break game;
+                            }
+                        };
+
+                        let mut output = output.lock().unwrap();
+                        let (writer, stats) = &mut *output;
+                        for (fen, score) in &positions {
+                            writeln!(writer, "{} | {} | {:.1}", fen, score, result).unwrap();
+                        }
+                        writer.flush().unwrap();
+                        stats.games += 1;
+                        stats.positions += positions.len();
+                        stats.results[(2.0 - 2.0 * result) as usize] += 1;
+                        if stats.games % 100 == 0 || stats.games == games {
+                            eprintln!(
+                                "games {} positions {} +{}={}-{} time {:?}",
+                                stats.games,
+                                stats.positions,
+                                stats.results[0],
+                                stats.results[1],
+                                stats.results[2],
+                                start.elapsed()
+                            );
+                        }
+                    }
+                })
+                .unwrap();
+        }
+    });
+}
+
+// Партия из случайного дебюта: отобранные позиции с оценкой за белых и результат
+// за белых. None, если дебют не подошел.
+fn play_game(
+    engine: &mut Engine,
+    nodes: u64,
+    rng: &mut XorshiftRng,
+) -> Option<(Vec<(String, isize)>, f32)> {
+    let mut pos = Position::from_fen(Position::INITIAL_POSITION_FEN).unwrap();
+    // ключи позиций после последнего необратимого хода, без текущей
+    let mut repeats: Vec<u64> = Vec::new();
+
+    for _ in 0..RANDOM_PLIES {
+        let mut ml = MoveList::new();
+        ml.gen_legal_moves(&pos);
+        if ml.size == 0 {
+            return None;
+        }
+        play_move(
+            &mut pos,
+            &mut repeats,
+            ml.moves[rng.next() as usize % ml.size].mv,
+        );
+    }
+
+    let mut positions = Vec::new();
+    let mut win_plies: isize = 0;
+    let mut draw_plies = 0;
+    let white = |pos: &Position, x: f32| {
+        if pos.side_to_move == Side::WHITE {
+            x
+        } else {
+            1.0 - x
+        }
+    };
+    for ply in RANDOM_PLIES..MAX_PLIES {
+        let mut ml = MoveList::new();
+        ml.gen_legal_moves(&pos);
+        if ml.size == 0 {
+            // мат или пат
+            let result = if pos.is_check() {
+                white(&pos, 0.0)
+            } else {
+                0.5
+            };
+            return Some((positions, result));
+        }
+        if is_draw(&pos, &repeats) {
+            return Some((positions, 0.5));
+        }
+
+        let info = engine.search(SearchParams {
+            position: pos.clone(),
+            repeats: repeats.clone(),
+            limits: LimitsType {
+                fixed_nodes: Some(nodes),
+                ..Default::default()
+            },
+            cancel: CancelToken::new(),
+            ponderhit: PonderHitToken::new(),
+            progress: Box::new(|_| {}),
+        });
+        let mv = info.main_line[0];
+        let score = match info.score {
+            UciScore::Centipawns(x) => x,
+            UciScore::Mate(x) => {
+                let result = if x > 0 { 1.0 } else { 0.0 };
+                return Some((positions, white(&pos, result)));
+            }
+        };
+        if ply == RANDOM_PLIES && score.abs() > MAX_OPENING_SCORE {
+            return None;
+        }
+        let white_score = if pos.side_to_move == Side::WHITE {
+            score
+        } else {
+            -score
+        };
+
+        if white_score >= WIN_SCORE {
+            win_plies = win_plies.max(0) + 1;
+        } else if white_score <= -WIN_SCORE {
+            win_plies = win_plies.min(0) - 1;
+        } else {
+            win_plies = 0;
+        }
+        if win_plies.abs() >= WIN_PLIES {
+            return Some((positions, if win_plies > 0 { 1.0 } else { 0.0 }));
+        }
+        if ply >= DRAW_PLY && score.abs() <= DRAW_SCORE {
+            draw_plies += 1;
+        } else {
+            draw_plies = 0;
+        }
+        if draw_plies >= DRAW_PLIES {
+            return Some((positions, 0.5));
+        }
+
+        // в позициях с тактикой оценка сети и поиска расходятся
+        if !pos.is_check() && mv.captured_piece() == Piece::NONE && mv.promotion() == Piece::NONE {
+            positions.push((pos.to_fen(), white_score));
+        }
+        play_move(&mut pos, &mut repeats, mv);
+    }
+    return Some((positions, 0.5));
+}
+
+fn play_move(pos: &mut Position, repeats: &mut Vec<u64>, mv: Move) {
+    if mv.moving_piece() == Piece::PAWN || mv.captured_piece() != Piece::NONE {
+        repeats.clear();
+    } else {
+        repeats.push(pos.key);
+    }
+    let mut child = pos.clone();
+    pos.make_move(mv, &mut child);
+    *pos = child;
+}
+
+// Правило 50 ходов, троекратное повторение или мало материала для мата
+fn is_draw(pos: &Position, repeats: &[u64]) -> bool {
+    if pos.rule50 >= 100 || repeats.iter().filter(|&&key| key == pos.key).count() >= 2 {
+        return true;
+    }
+    return (pos.pawns | pos.rooks | pos.queens) == 0
+        && !bitboard::multiple(pos.knights | pos.bishops);
+}
+
+#[test]
+fn test_datagen() {
+    unsafe { crate::chess::init() };
+    for fen in [
+        Position::INITIAL_POSITION_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "bqnbnrkr/pppppppp/8/8/8/8/PPPPPPPP/BQNBNRKR w KQkq - 0 1",
+        // ладья e1 не крайняя: рокировка записывается вертикалью
+        "1k2r1r1/8/8/8/8/8/8/1K2R1R1 w Ee - 3 1",
+        "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 1",
+    ] {
+        assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+    }
+
+    // поиску нужен большой стек
+    std::thread::Builder::new()
+        .stack_size(THREAD_STACK_SIZE)
+        .spawn(|| {
+            let mut engine = Engine::new();
+            let mut rng = XorshiftRng::with_seed(20);
+            let (positions, result) = loop {
+                if let Some(game) = play_game(&mut engine, 300, &mut rng) {
+                    break game;
+                }
+            };
+            assert!([0.0, 0.5, 1.0].contains(&result));
+            for (fen, _) in &positions {
+                let pos = Position::from_fen(fen).unwrap();
+                assert!(!pos.is_check());
+                assert_eq!(&pos.to_fen(), fen);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -3,15 +3,15 @@ use crate::eval;
 use std::fs::File;
 use std::io::{self, BufRead};
 
-struct DatasetItem {
-    fen: String,
-    target: f64,
+pub(super) struct DatasetItem {
+    pub fen: String,
+    pub target: f64,
 }
 
 pub fn eval_handler() {
     let dataset_path = super::map_path("chess/tuner/quiet-labeled.epd");
     let sigmoid_scale = 3.5 / 512.0;
-    let mut evaluator = eval::make_eval("").unwrap();
+    let mut evaluator = eval::make_eval("nnue", eval::nnue::DEFAULT_EVAL_FILE).unwrap();
 
     let mut total_cost = 0.0;
     let mut count = 0;
@@ -34,12 +34,12 @@ pub fn eval_handler() {
     eprintln!("Average cost: {}", average_cost);
 }
 
-fn sigmoid(x: f64) -> f64 {
+pub(super) fn sigmoid(x: f64) -> f64 {
     return 1.0 / (1.0 + (-x).exp());
 }
 
 //rnb1kbnr/pp1pppp1/7p/2q5/5P2/N1P1P3/P2P2PP/R1BQKBNR w KQkq - c9 "1/2-1/2";
-fn parse_item(s: String) -> DatasetItem {
+pub(super) fn parse_item(s: String) -> DatasetItem {
     let index = s.find('"').unwrap();
     let fen = &s[..index];
     let score = &s[index + 1..];
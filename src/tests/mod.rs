@@ -1,6 +1,9 @@
+mod datagen;
 mod eval;
 mod perft;
 mod tactic;
+mod train;
+mod tune;
 use std::path::PathBuf;
 
 pub fn test_handler() -> bool {
@@ -10,6 +13,10 @@ pub fn test_handler() -> bool {
                 perft::perft_handler();
                 return true;
             }
+            "perft960" => {
+                perft::perft960_handler();
+                return true;
+            }
             "tactic" => {
                 tactic::tactic_handler();
                 return true;
@@ -18,6 +25,18 @@ pub fn test_handler() -> bool {
                 eval::eval_handler();
                 return true;
             }
+            "tune" => {
+                tune::tune_handler();
+                return true;
+            }
+            "datagen" => {
+                datagen::datagen_handler();
+                return true;
+            }
+            "train" => {
+                train::train_handler();
+                return true;
+            }
             _ => return false,
         }
     }
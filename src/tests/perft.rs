@@ -48,6 +48,79 @@ pub fn perft_handler() {
     println!("Elapsed: {:?}", start.elapsed())
 }
 
+// Начальные позиции Chess960 (Shredder-FEN) с числом узлов на глубинах 1-4
+const CHESS960_EPD: &str = include_str!("chess960.epd");
+
+pub fn perft960_handler() {
+    let data = [
+        PerftIfno {
+            fen: "rnqb2br/1p1pppk1/5npp/p1p5/2P5/P2N1P2/1P1PP1PP/RN1BQKBR w HA - 0 8",
+            depth: 4,
+            nodes: 671066,
+        },
+        PerftIfno {
+            fen: "1rqnkbr1/1b1ppppp/p5n1/1pp5/8/P3NP2/1PPPP1PP/BRQ1KBRN w GBgb - 0 6",
+            depth: 4,
+            nodes: 377423,
+        },
+        PerftIfno {
+            fen: "2krrbbq/ppppppp1/n6p/8/1n1P1P2/NP2B3/P1P1P1PP/R1KNRB1Q b EA - 0 6",
+            depth: 4,
+            nodes: 271849,
+        },
+        PerftIfno {
+            fen: "rqbknnrb/ppp4p/6p1/3ppP2/P1P1p3/6NP/1P1P2P1/RQBKN1RB w GAga - 1 8",
+            depth: 4,
+            nodes: 505888,
+        },
+        PerftIfno {
+            fen: "rbbqr1k1/pp1ppppp/2p1nn2/8/8/1P1P1P2/PBPQP1PP/RB2NNKR w HA - 5 6",
+            depth: 4,
+            nodes: 991807,
+        },
+        PerftIfno {
+            fen: "r1nbkqbr/p1p1p1pp/1p1p1p2/4n3/7P/1PP1P3/P2P1PPR/RNNBKQB1 w Aha - 0 6",
+            depth: 4,
+            nodes: 546727,
+        },
+        PerftIfno {
+            fen: "r1qnk1br/1ppnpp1p/B4b2/p2p2p1/7P/P3P3/RNPP1PP1/1NQ2KBR b ha - 4 9",
+            depth: 4,
+            nodes: 806603,
+        },
+        PerftIfno {
+            fen: "rq1bnnkr/p1p1p1p1/bp1p3p/5pP1/7P/P3P3/1PPPBP2/RQB1NNKR w HAha - 0 7",
+            depth: 4,
+            nodes: 608885,
+        },
+    ];
+    let start = std::time::Instant::now();
+    for item in data {
+        let mut pos = Position::from_fen(item.fen).unwrap();
+        pos.chess960 = true;
+        assert!(item.nodes == perft(&pos, item.depth), "{}", item.fen);
+    }
+    for line in CHESS960_EPD.lines() {
+        let (fen, expected) = parse_epd_line(line);
+        let pos = Position::from_fen(fen).unwrap();
+        let (depth, nodes) = *expected.last().unwrap();
+        assert!(nodes == perft(&pos, depth), "{}", fen);
+    }
+    println!("Elapsed: {:?}", start.elapsed())
+}
+
+fn parse_epd_line(line: &str) -> (&str, Vec<(isize, isize)>) {
+    let mut split = line.split(" ;");
+    let fen = split.next().unwrap();
+    let expected = split
+        .map(|x| {
+            let (depth, nodes) = x.split_once(' ').unwrap();
+            (depth[1..].parse().unwrap(), nodes.parse().unwrap())
+        })
+        .collect();
+    return (fen, expected);
+}
+
 #[allow(invalid_value)]
 fn perft(p: &Position, depth: isize) -> isize {
     let mut result: isize = 0;
@@ -68,3 +141,86 @@ fn perft(p: &Position, depth: isize) -> isize {
     }
     return result;
 }
+
+#[test]
+fn test_perft_chess960() {
+    unsafe { crate::chess::init() };
+    for line in CHESS960_EPD.lines().step_by(37) {
+        let (fen, expected) = parse_epd_line(line);
+        let pos = Position::from_fen(fen).unwrap();
+        for (depth, nodes) in expected.into_iter().take(3) {
+            assert_eq!(perft(&pos, depth), nodes, "{} depth {}", fen, depth);
+        }
+    }
+}
+
+// is_pseudo_legal совпадает с генератором на ходах из других позиций,
+// gen_captures и gen_quiets вместе дают gen_moves
+#[test]
+fn test_pseudo_legal() {
+    use crate::chess::Move;
+    unsafe { crate::chess::init() };
+    let mut positions = Vec::new();
+    for fen in [
+        Position::INITIAL_POSITION_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 1",
+        "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        "4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1",
+    ]
+    .into_iter()
+    .chain(
+        CHESS960_EPD
+            .lines()
+            .step_by(97)
+            .map(|x| parse_epd_line(x).0),
+    ) {
+        let mut pos = Position::from_fen(fen).unwrap();
+        pos.chess960 = true;
+        positions.push(pos.clone());
+        // и позиции на ход-два дальше, чтобы были взятия на проходе и шахи
+        let mut ml = MoveList::new();
+        ml.gen_legal_moves(&pos);
+        for item in ml.moves[..ml.size].iter().step_by(7) {
+            let mut child = pos.clone();
+            pos.make_move(item.mv, &mut child);
+            positions.push(child);
+        }
+    }
+
+    let mut pool = Vec::new();
+    for pos in &positions {
+        let mut ml = MoveList::new();
+        ml.gen_moves(pos);
+        pool.extend(ml.moves[..ml.size].iter().map(|x| x.mv));
+    }
+    pool.extend([Move::NONE, Move::NULL]);
+
+    for pos in &positions {
+        let mut all = MoveList::new();
+        all.gen_moves(pos);
+        let all: Vec<Move> = all.moves[..all.size].iter().map(|x| x.mv).collect();
+        for &mv in &pool {
+            assert_eq!(
+                pos.is_pseudo_legal(mv),
+                all.contains(&mv),
+                "{} {:#?}",
+                pos.to_fen(),
+                mv
+            );
+        }
+
+        let mut captures = MoveList::new();
+        captures.gen_captures(pos);
+        let mut quiets = MoveList::new();
+        quiets.gen_quiets(pos);
+        let mut split: Vec<Move> = captures.moves[..captures.size]
+            .iter()
+            .chain(&quiets.moves[..quiets.size])
+            .map(|x| x.mv)
+            .collect();
+        assert_eq!(split.len(), all.len(), "{}", pos.to_fen());
+        split.retain(|mv| !all.contains(mv));
+        assert!(split.is_empty());
+    }
+}
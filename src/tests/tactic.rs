@@ -1,5 +1,5 @@
 use crate::chess::{Move, Position};
-use crate::domain::{CancelToken, IEngine, LimitsType, SearchInfo, SearchParams};
+use crate::domain::{CancelToken, IEngine, LimitsType, PonderHitToken, SearchInfo, SearchParams};
 use crate::engine::Engine;
 use std::fs::File;
 use std::io;
@@ -30,6 +30,7 @@ pub fn tactic_handler() {
             repeats: Vec::new(),
             limits: LimitsType::fixed_time(Duration::from_secs(3)),
             cancel: cancel.clone(),
+            ponderhit: PonderHitToken::new(),
             progress: Box::new(solve_tactic_progress(cancel, test.best_moves.clone())),
         });
         total += 1;
@@ -0,0 +1,97 @@
+// Обучение сети NNUE 768x512x1 с нуля или от готовой сети.
+// counter train [dataset] [epochs] [wdl] [network]
+// Строка датасета: "fen | score | result", score - оценка за белых в сантипешках,
+// result - 1.0, 0.5 или 0.0 за белых; подходит и EPD с результатом в кавычках.
+// Цель обучения: wdl * result + (1 - wdl) * sigmoid(score).
+// После каждой эпохи сеть записывается в train-<epoch>.nn.
+
+use super::eval::parse_item;
+use crate::chess::{Position, XorshiftRng};
+use crate::eval::nnue::train::{SIGMOID_SCALE, Sample, Trainer, sigmoid};
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+const BATCH_SIZE: usize = 16_384;
+const LEARNING_RATE: f32 = 1e-3;
+// каждая сотая позиция не участвует в обучении, по ней считается ошибка
+const VALIDATION_STEP: usize = 100;
+
+pub fn train_handler() {
+    let args: Vec<String> = std::env::args().collect();
+    let dataset_path = args
+        .get(2)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| super::map_path("chess/train/data.txt"));
+    let epochs = args
+        .get(3)
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(30);
+    let wdl = args
+        .get(4)
+        .and_then(|x| x.parse::<f32>().ok())
+        .unwrap_or(0.5);
+
+    let mut trainer = match args.get(5) {
+        Some(path) => Trainer::load(Path::new(path)).unwrap(),
+        None => Trainer::new(5268),
+    };
+    let (mut samples, validation) = load_dataset(&dataset_path, wdl);
+    eprintln!(
+        "positions {} validation {}",
+        samples.len(),
+        validation.len()
+    );
+
+    let mut rng = XorshiftRng::with_seed(1);
+    for epoch in 1..=epochs {
+        // в последней четверти эпох шаг в 10 раз меньше
+        let learning_rate = if 4 * epoch > 3 * epochs {
+            LEARNING_RATE / 10.0
+        } else {
+            LEARNING_RATE
+        };
+        let start = std::time::Instant::now();
+        let loss = trainer.train_epoch(&mut samples, BATCH_SIZE, learning_rate, &mut rng);
+        let path = format!("train-{}.nn", epoch);
+        trainer.save(Path::new(&path)).unwrap();
+        eprintln!(
+            "epoch {} loss {:.6} validation {:.6} time {:?} saved {}",
+            epoch,
+            loss,
+            trainer.loss(&validation),
+            start.elapsed(),
+            path
+        );
+    }
+}
+
+fn load_dataset(path: &Path, wdl: f32) -> (Vec<Sample>, Vec<Sample>) {
+    let reader = io::BufReader::new(File::open(path).unwrap());
+    let mut samples = Vec::new();
+    let mut validation = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let (pos, target) = parse_line(&line.unwrap(), wdl);
+        let sample = Sample::new(&pos, target);
+        if i % VALIDATION_STEP == VALIDATION_STEP - 1 {
+            validation.push(sample);
+        } else {
+            samples.push(sample);
+        }
+    }
+    return (samples, validation);
+}
+
+// rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1 | 35 | 0.5
+fn parse_line(line: &str, wdl: f32) -> (Position, f32) {
+    let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+    if fields.len() != 3 {
+        let item = parse_item(line.to_string());
+        return (Position::from_fen(&item.fen).unwrap(), item.target as f32);
+    }
+    let pos = Position::from_fen(fields[0]).unwrap();
+    let score: f32 = fields[1].parse().unwrap();
+    let result: f32 = fields[2].parse().unwrap();
+    let target = wdl * result + (1.0 - wdl) * sigmoid(SIGMOID_SCALE * score);
+    return (pos, target);
+}
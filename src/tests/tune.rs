@@ -0,0 +1,293 @@
+// Подбор весов линейной оценки (Texel tuning): минимизируется среднеквадратичная
+// ошибка sigmoid(K * eval) относительно результата партии, градиентный спуск Adam.
+// counter tune [evaluator] [epochs] [dataset]
+
+use super::eval::{parse_item, sigmoid};
+use crate::chess::Position;
+use crate::eval::classical::{self, MAX_PHASE, Score};
+use crate::eval::{self, TunableEvaluator};
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+// Признаки позиции: (индекс параметра, число срабатываний)
+struct Entry {
+    features: Vec<(u16, i16)>,
+    // доля middlegame, 1.0 - все фигуры на доске
+    phase: f64,
+    target: f64,
+}
+
+type Params = Vec<[f64; 2]>;
+
+pub fn tune_handler() {
+    let args: Vec<String> = std::env::args().collect();
+    let name = args.get(2).map(String::as_str).unwrap_or("classical");
+    let epochs = args
+        .get(3)
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(500);
+    let dataset_path = args
+        .get(4)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| super::map_path("chess/tuner/quiet-labeled.epd"));
+
+    let mut evaluator = eval::make_tunable(name).unwrap();
+    let entries = load_dataset(evaluator.as_ref(), &dataset_path);
+    eprintln!("positions {}", entries.len());
+
+    let mut params: Params = evaluator
+        .params()
+        .iter()
+        .map(|s| [s.mg as f64, s.eg as f64])
+        .collect();
+    let k = fit_sigmoid_scale(&entries, &params);
+    eprintln!("K {} loss {}", k, loss_and_gradient(&entries, &params, k).0);
+
+    let mut adam = Adam::new(params.len());
+    for epoch in 1..=epochs {
+        let (loss, gradient) = loss_and_gradient(&entries, &params, k);
+        adam.step(&mut params, &gradient);
+        if epoch % 10 == 0 {
+            eprintln!("epoch {} loss {}", epoch, loss);
+        }
+    }
+
+    let tuned: Vec<Score> = params
+        .iter()
+        .map(|p| Score {
+            mg: p[0].round() as isize,
+            eg: p[1].round() as isize,
+        })
+        .collect();
+    // ошибка после округления параметров, посчитанная самой оценкой
+    evaluator.set_params(tuned.clone());
+    let rounded_loss = dataset_positions(&dataset_path)
+        .map(|(pos, target)| (sigmoid(k * evaluator.evaluate(&pos) as f64) - target).powi(2))
+        .sum::<f64>()
+        / entries.len() as f64;
+    eprintln!("rounded loss {}", rounded_loss);
+    let path = format!("tuned_{}.rs", name);
+    write_source(Path::new(&path), name, &tuned, k, entries.len()).unwrap();
+    eprintln!("params saved to {}", path);
+}
+
+fn dataset_positions(path: &Path) -> impl Iterator<Item = (Position, f64)> {
+    let reader = io::BufReader::new(File::open(path).unwrap());
+    return reader.lines().map(|line| {
+        let item = parse_item(line.unwrap());
+        (Position::from_fen(&item.fen).unwrap(), item.target)
+    });
+}
+
+fn load_dataset(evaluator: &dyn TunableEvaluator, path: &Path) -> Vec<Entry> {
+    return dataset_positions(path)
+        .map(|(pos, target)| make_entry(evaluator, &pos, target))
+        .collect();
+}
+
+fn make_entry(evaluator: &dyn TunableEvaluator, pos: &Position, target: f64) -> Entry {
+    let mut features = Vec::new();
+    evaluator.features(pos, &mut |index, count| {
+        features.push((index as u16, count as i16));
+    });
+    // одинаковые признаки складываем: так меньше памяти и быстрее эпоха
+    features.sort_unstable_by_key(|x| x.0);
+    features.dedup_by(|x, acc| {
+        if x.0 == acc.0 {
+            acc.1 += x.1;
+            return true;
+        }
+        return false;
+    });
+    features.retain(|x| x.1 != 0);
+    return Entry {
+        features: features,
+        phase: classical::phase(pos) as f64 / MAX_PHASE as f64,
+        target: target,
+    };
+}
+
+fn evaluate(entry: &Entry, params: &Params) -> f64 {
+    let (mut mg, mut eg) = (0_f64, 0_f64);
+    for &(index, count) in &entry.features {
+        mg += params[index as usize][0] * count as f64;
+        eg += params[index as usize][1] * count as f64;
+    }
+    return mg * entry.phase + eg * (1.0 - entry.phase);
+}
+
+// Средняя ошибка и ее градиент по параметрам, считается во всех потоках
+fn loss_and_gradient(entries: &[Entry], params: &Params, k: f64) -> (f64, Params) {
+    let threads = std::thread::available_parallelism().map_or(1, |x| x.get());
+    let chunk_size = entries.len().div_ceil(threads).max(1);
+    let (loss, mut gradient) = std::thread::scope(|scope| {
+        let handles: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut loss = 0_f64;
+                    let mut gradient = vec![[0_f64; 2]; params.len()];
+                    for entry in chunk {
+                        let prob = sigmoid(k * evaluate(entry, params));
+                        let diff = prob - entry.target;
+                        loss += diff * diff;
+                        let g = 2.0 * diff * prob * (1.0 - prob) * k;
+                        for &(index, count) in &entry.features {
+                            let x = g * count as f64;
+                            gradient[index as usize][0] += x * entry.phase;
+                            gradient[index as usize][1] += x * (1.0 - entry.phase);
+                        }
+                    }
+                    (loss, gradient)
+                })
+            })
+            .collect();
+        let mut total = (0_f64, vec![[0_f64; 2]; params.len()]);
+        for handle in handles {
+            let (loss, gradient) = handle.join().unwrap();
+            total.0 += loss;
+            for (t, g) in total.1.iter_mut().zip(gradient) {
+                t[0] += g[0];
+                t[1] += g[1];
+            }
+        }
+        total
+    });
+    let n = entries.len().max(1) as f64;
+    for g in gradient.iter_mut() {
+        g[0] /= n;
+        g[1] /= n;
+    }
+    return (loss / n, gradient);
+}
+
+// K, при котором начальные параметры лучше всего предсказывают результат
+fn fit_sigmoid_scale(entries: &[Entry], params: &Params) -> f64 {
+    let evals: Vec<f64> = entries.iter().map(|e| evaluate(e, params)).collect();
+    let loss = |k: f64| -> f64 {
+        return entries
+            .iter()
+            .zip(&evals)
+            .map(|(e, &eval)| (sigmoid(k * eval) - e.target).powi(2))
+            .sum();
+    };
+    // ошибка унимодальна по K: тернарный поиск
+    let (mut lo, mut hi) = (0_f64, 0.05_f64);
+    for _ in 0..100 {
+        let m1 = lo + (hi - lo) / 3.0;
+        let m2 = hi - (hi - lo) / 3.0;
+        if loss(m1) < loss(m2) {
+            hi = m2;
+        } else {
+            lo = m1;
+        }
+    }
+    return (lo + hi) / 2.0;
+}
+
+struct Adam {
+    m: Params,
+    v: Params,
+    t: i32,
+}
+
+impl Adam {
+    // параметры в сантипешках, шаг порядка одной сантипешки
+    const LEARNING_RATE: f64 = 1.0;
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    const EPSILON: f64 = 1e-8;
+
+    fn new(size: usize) -> Self {
+        return Adam {
+            m: vec![[0_f64; 2]; size],
+            v: vec![[0_f64; 2]; size],
+            t: 0,
+        };
+    }
+
+    fn step(&mut self, params: &mut Params, gradient: &Params) {
+        self.t += 1;
+        let correction1 = 1.0 - Self::BETA1.powi(self.t);
+        let correction2 = 1.0 - Self::BETA2.powi(self.t);
+        for i in 0..params.len() {
+            for j in 0..2 {
+                let g = gradient[i][j];
+                self.m[i][j] = Self::BETA1 * self.m[i][j] + (1.0 - Self::BETA1) * g;
+                self.v[i][j] = Self::BETA2 * self.v[i][j] + (1.0 - Self::BETA2) * g * g;
+                let m = self.m[i][j] / correction1;
+                let v = self.v[i][j] / correction2;
+                params[i][j] -= Self::LEARNING_RATE * m / (v.sqrt() + Self::EPSILON);
+            }
+        }
+    }
+}
+
+// Параметры в виде исходного кода для вставки вместо default_params
+fn write_source(path: &Path, name: &str, params: &[Score], k: f64, size: usize) -> io::Result<()> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    writeln!(
+        file,
+        "// counter tune {}: {} positions, K = {:.6}",
+        name, size, k
+    )?;
+    writeln!(
+        file,
+        "pub const TUNED_PARAMS: [Score; {}] = [",
+        params.len()
+    )?;
+    for p in params {
+        writeln!(file, "    Score {{ mg: {}, eg: {} }},", p.mg, p.eg)?;
+    }
+    writeln!(file, "];")?;
+    return Ok(());
+}
+
+#[test]
+fn test_tune() {
+    unsafe { crate::chess::init() };
+    // позиции из случайных партий, результат - вероятность по классической оценке
+    let mut classical = eval::make_eval("classical", "").unwrap();
+    let pst = eval::make_tunable("pst").unwrap();
+    let mut rng = crate::chess::XorshiftRng::with_seed(18);
+    let mut entries = Vec::new();
+    for _ in 0..20 {
+        let mut pos = Position::from_fen(Position::INITIAL_POSITION_FEN).unwrap();
+        for _ in 0..60 {
+            let mut ml = crate::chess::MoveList::new();
+            ml.gen_legal_moves(&pos);
+            if ml.size == 0 {
+                break;
+            }
+            let mut child = pos.clone();
+            pos.make_move(ml.moves[rng.next() as usize % ml.size].mv, &mut child);
+            pos = child;
+            let target = sigmoid(0.01 * classical.evaluate(&pos) as f64);
+            entries.push(make_entry(pst.as_ref(), &pos, target));
+        }
+    }
+
+    let mut params: Params = pst
+        .params()
+        .iter()
+        .map(|s| [s.mg as f64, s.eg as f64])
+        .collect();
+    let k = fit_sigmoid_scale(&entries, &params);
+    assert!(k > 0.0 && k < 0.05);
+
+    // градиент совпадает с разностной производной
+    let (loss, gradient) = loss_and_gradient(&entries, &params, k);
+    let index = classical::MATERIAL; // пешка
+    let mut shifted = params.clone();
+    shifted[index][0] += 1e-3;
+    let numeric = (loss_and_gradient(&entries, &shifted, k).0 - loss) / 1e-3;
+    assert!((numeric - gradient[index][0]).abs() < 1e-3 * gradient[index][0].abs() + 1e-9);
+
+    let mut adam = Adam::new(params.len());
+    for _ in 0..50 {
+        let gradient = loss_and_gradient(&entries, &params, k).1;
+        adam.step(&mut params, &gradient);
+    }
+    assert!(loss_and_gradient(&entries, &params, k).0 < loss);
+}
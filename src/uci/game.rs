@@ -47,7 +47,10 @@ impl Game {
     }
 }
 
-pub fn parse_game(split: &mut std::str::SplitAsciiWhitespace) -> Result<Game, String> {
+pub fn parse_game(
+    split: &mut std::str::SplitAsciiWhitespace,
+    chess960: bool,
+) -> Result<Game, String> {
     let mut state = 0;
     let mut init_fen = String::new();
     let mut parse_moves = false;
@@ -77,7 +80,8 @@ pub fn parse_game(split: &mut std::str::SplitAsciiWhitespace) -> Result<Game, St
             }
         }
     }
-    let init_pos = Position::from_fen(&init_fen).ok_or("parse fen failed")?;
+    let mut init_pos = Position::from_fen(&init_fen).ok_or("parse fen failed")?;
+    init_pos.chess960 = chess960;
     let mut game = Game {
         position: init_pos,
         repeats: Vec::new(),
@@ -1,8 +1,10 @@
 mod game;
 mod parse;
 
+use crate::chess::Move;
 use crate::domain::{
-    CancelToken, IEngine, LimitsType, OptionValue, SearchInfo, SearchParams, UciScore,
+    CancelToken, IEngine, LimitsType, OptionValue, PonderHitToken, SearchInfo, SearchParams,
+    TournamentLimit, UciScore,
 };
 use game::Game;
 use parse::UciMessage;
@@ -12,8 +14,10 @@ enum EngineMessage {
     Uci,
     SetOption { name: String, value: String },
     IsReady,
-    Go(Game, LimitsType, CancelToken),
+    Go(Box<Game>, LimitsType, CancelToken, PonderHitToken),
     NewGame,
+    SaveHash,
+    LoadHash,
     Quit,
 }
 
@@ -31,12 +35,16 @@ fn cli_commands_cycle(
     sender: std::sync::mpsc::Sender<EngineMessage>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut cancel = CancelToken::new();
+    let mut ponderhit = PonderHitToken::new();
+    let mut ponder_limits = TournamentLimit::default();
     let mut game = Game::new();
+    // от режима зависит разбор рокировок в команде position
+    let mut chess960 = false;
     let mut buffer = String::new();
     loop {
         buffer.clear();
         std::io::stdin().read_line(&mut buffer)?;
-        let user_cmd = parse::parse_command(buffer.trim_end());
+        let user_cmd = parse::parse_command(buffer.trim_end(), chess960);
         match user_cmd {
             Err(msg) => {
                 eprintln!("{}", msg);
@@ -44,6 +52,9 @@ fn cli_commands_cycle(
             Ok(UciMessage::Uci) => {
                 sender.send(EngineMessage::Uci)?;
             }
+            Ok(UciMessage::SetOption { name, value }) if name == "UCI_Chess960" => {
+                chess960 = value.eq_ignore_ascii_case("true");
+            }
             Ok(UciMessage::SetOption { name, value }) => {
                 sender.send(EngineMessage::SetOption {
                     name: name,
@@ -59,18 +70,36 @@ fn cli_commands_cycle(
             }
             Ok(UciMessage::Stop) => {
                 cancel.cancel();
+                ponderhit.stop();
+            }
+            Ok(UciMessage::PonderHit) => {
+                ponderhit.ponderhit(ponder_limits.clone());
             }
             Ok(UciMessage::Quit) => {
                 cancel.cancel();
+                ponderhit.stop();
                 sender.send(EngineMessage::Quit)?;
                 return Ok(());
             }
+            Ok(UciMessage::SaveHash) => {
+                sender.send(EngineMessage::SaveHash)?;
+            }
+            Ok(UciMessage::LoadHash) => {
+                sender.send(EngineMessage::LoadHash)?;
+            }
             Ok(UciMessage::Position(g)) => {
                 game = g;
             }
             Ok(UciMessage::Go(limits)) => {
                 cancel = CancelToken::new();
-                sender.send(EngineMessage::Go(game.clone(), limits, cancel.clone()))?;
+                ponderhit = PonderHitToken::new();
+                ponder_limits = limits.tournament.clone();
+                sender.send(EngineMessage::Go(
+                    Box::new(game.clone()),
+                    limits,
+                    cancel.clone(),
+                    ponderhit.clone(),
+                ))?;
             }
         }
     }
@@ -108,8 +137,16 @@ fn engine_commands_cycle(
                             };
                             println!("option name {} type string default {}", opt.name, val);
                         }
+                        OptionValue::Combo { value, vars } => {
+                            print!("option name {} type combo default {}", opt.name, value);
+                            for var in vars {
+                                print!(" var {}", var);
+                            }
+                            println!();
+                        }
                     }
                 }
+                println!("option name UCI_Chess960 type check default false");
                 println!("uciok");
             }
             EngineMessage::SetOption { name, value } => {
@@ -121,18 +158,49 @@ fn engine_commands_cycle(
             EngineMessage::NewGame => {
                 eng.clear();
             }
-            EngineMessage::Go(game, limits, cancel) => {
+            EngineMessage::SaveHash => {
+                if let Err(err) = eng.save_hash() {
+                    println!("info string {}", err);
+                }
+            }
+            EngineMessage::LoadHash => {
+                if let Err(err) = eng.load_hash() {
+                    println!("info string {}", err);
+                }
+            }
+            EngineMessage::Go(game, limits, cancel, ponderhit) => {
                 let repeats = game.two_time_repeats();
+                let ponder = limits.ponder;
+                let chess960 = game.position.chess960;
                 let search_result = eng.search(SearchParams {
                     position: game.position,
                     repeats: repeats,
                     limits: limits,
                     cancel: cancel,
-                    progress: Box::new(uci_search_progress()),
+                    ponderhit: ponderhit.clone(),
+                    progress: Box::new(uci_search_progress(chess960)),
                 });
-                println!("{}", &search_result);
-                if !search_result.main_line.is_empty() {
-                    println!("bestmove {:?}", search_result.main_line[0]);
+                // в режиме ponder bestmove можно отправить только после ponderhit или stop
+                if ponder {
+                    ponderhit.wait();
+                }
+                // ход из книги выдается без строки info
+                if search_result.depth > 0 {
+                    print_search_info(&search_result, chess960);
+                }
+                let lan = |mv: &Move| {
+                    if chess960 {
+                        format!("{:#?}", mv)
+                    } else {
+                        format!("{:?}", mv)
+                    }
+                };
+                match search_result.main_line.as_slice() {
+                    [] => (),
+                    [best_move] => println!("bestmove {}", lan(best_move)),
+                    [best_move, ponder_move, ..] => {
+                        println!("bestmove {} ponder {}", lan(best_move), lan(ponder_move))
+                    }
                 }
             }
             EngineMessage::Quit => {
@@ -142,11 +210,36 @@ fn engine_commands_cycle(
     }
 }
 
+// Альтернативный формат ({:#}) выводит рокировки в нотации Chess960
 impl fmt::Display for SearchInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lines.len() <= 1 {
+            return self.write_line(f, None, self.score, &self.main_line);
+        }
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            self.write_line(f, Some(i + 1), line.score, &line.main_line)?;
+        }
+        Ok(())
+    }
+}
+
+impl SearchInfo {
+    fn write_line(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        multi_pv: Option<usize>,
+        score: UciScore,
+        main_line: &[Move],
+    ) -> fmt::Result {
         write!(f, "info")?;
         write!(f, " depth {}", self.depth)?;
-        match self.score {
+        if let Some(multi_pv) = multi_pv {
+            write!(f, " multipv {}", multi_pv)?;
+        }
+        match score {
             UciScore::Centipawns(x) => {
                 write!(f, " score cp {}", x)?;
             }
@@ -158,21 +251,34 @@ impl fmt::Display for SearchInfo {
         write!(f, " time {}", self.duration.as_millis())?;
         let nps = (self.nodes as f32 / self.duration.as_secs_f32()) as i32;
         write!(f, " nps {}", nps)?;
-        if !self.main_line.is_empty() {
+        write!(f, " hashfull {}", self.hashfull)?;
+        if !main_line.is_empty() {
             write!(f, " pv")?;
-            for m in self.main_line.iter() {
-                write!(f, " {:?}", m)?;
+            for m in main_line.iter() {
+                if f.alternate() {
+                    write!(f, " {:#?}", m)?;
+                } else {
+                    write!(f, " {:?}", m)?;
+                }
             }
         }
         Ok(())
     }
 }
 
-fn uci_search_progress() -> impl Fn(&SearchInfo) {
+fn print_search_info(si: &SearchInfo, chess960: bool) {
+    if chess960 {
+        println!("{:#}", si);
+    } else {
+        println!("{}", si);
+    }
+}
+
+fn uci_search_progress(chess960: bool) -> impl Fn(&SearchInfo) {
     const MIN_DURATION: std::time::Duration = std::time::Duration::from_millis(500);
     move |si| {
         if si.duration >= MIN_DURATION {
-            println!("{}", si);
+            print_search_info(si, chess960);
         }
     }
 }
@@ -10,10 +10,13 @@ pub enum UciMessage {
     Go(LimitsType),
     NewGame,
     Stop,
+    PonderHit,
     Quit,
+    SaveHash,
+    LoadHash,
 }
 
-pub fn parse_command(s: &str) -> Result<UciMessage, String> {
+pub fn parse_command(s: &str, chess960: bool) -> Result<UciMessage, String> {
     let mut split = s.split_ascii_whitespace();
     let cmd_name = split.next().ok_or("empty command")?;
     match cmd_name {
@@ -29,9 +32,18 @@ pub fn parse_command(s: &str) -> Result<UciMessage, String> {
         "stop" => {
             return Ok(UciMessage::Stop);
         }
+        "ponderhit" => {
+            return Ok(UciMessage::PonderHit);
+        }
         "quit" => {
             return Ok(UciMessage::Quit);
         }
+        "savehash" => {
+            return Ok(UciMessage::SaveHash);
+        }
+        "loadhash" => {
+            return Ok(UciMessage::LoadHash);
+        }
         "setoption" => {
             let (name, value) = parse_option(&mut split).ok_or("parse_option failed")?;
             return Ok(UciMessage::SetOption {
@@ -40,7 +52,7 @@ pub fn parse_command(s: &str) -> Result<UciMessage, String> {
             });
         }
         "position" => {
-            let game = parse_game(&mut split)?;
+            let game = parse_game(&mut split, chess960)?;
             return Ok(UciMessage::Position(game));
         }
         "go" => {
@@ -80,6 +92,9 @@ fn parse_limits(split: &mut std::str::SplitAsciiWhitespace) -> Option<LimitsType
             "infinite" => {
                 result.infinite = true;
             }
+            "ponder" => {
+                result.ponder = true;
+            }
             "wtime" => {
                 let millis: u64 = split.next()?.parse().ok()?;
                 result.tournament.white_time = Some(millis);